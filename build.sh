@@ -4,7 +4,9 @@ source $HOME/.cargo/env
 # unpack support files
 tar xvf eqfiles.tgz
 tar xvf bin.tgz
-# build evaluator
+# build evaluator library
 cd eval && cargo build && cd ..
 # build dwarf-updater
 cd gimli_write && cargo build && cd ..
+# build the command line tool and the evaluator
+cd debughdr && cargo build && cd ..
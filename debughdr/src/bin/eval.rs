@@ -0,0 +1,73 @@
+use std::{env, process};
+
+/* The evaluator as it was before debughdr, taking the function and the instruction map after the
+ * objects, and --suite <dir> for the suite: its arguments are those of debughdr eval or suite,
+ * whose options it shares */
+fn main() {
+    env_logger::init();
+
+    let mut options = Vec::new();
+    let mut paths = Vec::new();
+    let mut suite_dir = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--help" || arg == "-h" {
+            print!("{}", usage());
+            return;
+        }
+        if !arg.starts_with("--") {
+            paths.push(arg);
+        } else if arg == "--suite" {
+            suite_dir = Some(args.next().unwrap_or_else(|| fail()));
+        } else {
+            let value = if debughdr::is_flag(&arg) {
+                None
+            } else {
+                Some(args.next().unwrap_or_else(|| fail()))
+            };
+            options.push(arg);
+            options.extend(value);
+        }
+    }
+
+    let args = match suite_dir {
+        Some(suite_dir) if paths.is_empty() => {
+            let mut args = vec!["suite".to_string()];
+            args.extend(options);
+            args.push(suite_dir);
+            args
+        }
+        None if paths.len() == 4 => {
+            let mut paths = paths.into_iter();
+            let before = paths.next().unwrap();
+            let after = paths.next().unwrap();
+            let mut args = vec![
+                "eval".to_string(),
+                "--function".to_string(),
+                paths.next().unwrap(),
+                "--insn-map".to_string(),
+                paths.next().unwrap(),
+            ];
+            args.extend(options);
+            args.push(before);
+            args.push(after);
+            args
+        }
+        _ => fail(),
+    };
+    debughdr::run(args);
+}
+
+fn usage() -> String {
+    String::from(
+        "Usage: eval [options] <before_obj_file> <after_obj_file> <func_name> <insn_map_file>\n       \
+         eval [options] --suite <dir>\n\n\
+         The same as 'debughdr eval' (or 'debughdr suite' with --suite), whose options it takes.\n\
+         Run 'debughdr eval --help' or 'debughdr suite --help' for them.\n",
+    )
+}
+
+fn fail() -> ! {
+    eprint!("{}", usage());
+    process::exit(1);
+}
@@ -0,0 +1,573 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io;
+use std::path::Path;
+use std::{fs, process};
+
+use eval::{evaluate_coverage, evaluate_suite, find_pairs, heatmap, read_dwarf, OutputFormat};
+use object::Architecture;
+use rewrite::{DebugInfoRewriter, ExpressionFile};
+
+/* A subcommand, with its positional arguments, the options it takes and the output formats it
+ * has (the first one by default) */
+struct Command {
+    name: &'static str,
+    args: &'static str,
+    options: &'static [&'static str],
+    formats: &'static [&'static str],
+    about: &'static str,
+    run: fn(&Options),
+}
+
+const COMMANDS: &[Command] = &[
+    Command {
+        name: "rewrite",
+        args: "<infile> <outfile>",
+        options: &[
+            "--expr-file",
+            "--arch",
+            "--dwarf-version",
+            "--report",
+            "--simplify",
+        ],
+        formats: &[],
+        about: "Rewrites the debug info of an object file with the mappings of an expression file",
+        run: run_rewrite,
+    },
+    Command {
+        name: "check",
+        args: "<infile>",
+        options: &[
+            "--expr-file",
+            "--arch",
+            "--dwarf-version",
+            "--report",
+            "--simplify",
+        ],
+        formats: &[],
+        about: "Checks the mappings of an expression file against an object file, writing nothing",
+        run: run_check,
+    },
+    Command {
+        name: "eval",
+        args: "<before> <after>",
+        options: &[
+            "--function",
+            "--insn-map",
+            "--output-format",
+            "--variables",
+            "--heatmap",
+            "--listing",
+        ],
+        formats: &["text", "csv", "json"],
+        about: "Evaluates the coverage of the variables of a function before and after a rewrite",
+        run: run_eval,
+    },
+    Command {
+        name: "suite",
+        args: "<dir>",
+        options: &["--before-dir", "--output-format"],
+        formats: &["text", "csv", "json"],
+        about: "Evaluates all the functions of a rewrites directory, with totals and geometric \
+                means shifted by one for each compiler",
+        run: run_suite,
+    },
+    Command {
+        name: "dump",
+        args: "<infile>",
+        options: &["--function", "--insn-map", "--output-format"],
+        formats: &["text", "csv"],
+        about: "Prints the ranges of the instruction map where the variables of a function are \
+                available",
+        run: run_dump,
+    },
+    Command {
+        name: "diff",
+        args: "<before> <after>",
+        options: &["--function", "--insn-map", "--output-format"],
+        formats: &["text", "csv"],
+        about: "Prints the ranges the variables of a function gained or lost with a rewrite",
+        run: run_diff,
+    },
+];
+
+/* The options, all taking a value but the flags, which have none */
+const OPTIONS: &[(&str, &str, &str)] = &[
+    (
+        "--expr-file",
+        "<file>",
+        "expression file of the mappings (standard input by default)",
+    ),
+    (
+        "--function",
+        "<name>",
+        "function whose variables are evaluated",
+    ),
+    (
+        "--insn-map",
+        "<file>",
+        "instruction map of the function, the PCs the coverage is counted on",
+    ),
+    (
+        "--arch",
+        "<arch>",
+        "architecture of the register names of the expressions (i386, x86-64, aarch64, \
+         riscv32 or riscv64), that of the object file by default (eval, dump \
+         and diff always disassemble for that of the object file)",
+    ),
+    (
+        "--dwarf-version",
+        "<version>",
+        "DWARF version to upgrade the units to (only 5)",
+    ),
+    (
+        "--report",
+        "<file>",
+        "JSON file to write what became of each mapping to",
+    ),
+    (
+        "--simplify",
+        "",
+        "folds the constants and register offsets of the expressions, which changes the bytes \
+         written",
+    ),
+    ("--output-format", "<format>", "format of the results"),
+    (
+        "--before-dir",
+        "<dir>",
+        "directory of the objects before the rewrite, <function>.<compiler>.o (<dir> by default)",
+    ),
+    (
+        "--variables",
+        "",
+        "also prints the coverage of each variable, in csv instead of that of the function",
+    ),
+    (
+        "--heatmap",
+        "<file>",
+        "CSV file to write the availability of each variable at each PC to",
+    ),
+    (
+        "--listing",
+        "<file>",
+        "file to write the disassembly of the function to, with the variables available at \
+         each instruction",
+    ),
+];
+
+struct Options {
+    command: &'static Command,
+    values: HashMap<&'static str, String>,
+    paths: Vec<String>,
+}
+
+impl Options {
+    fn value(&self, option: &str) -> Option<&str> {
+        self.values.get(option).map(String::as_str)
+    }
+
+    fn required(&self, option: &str) -> &str {
+        match self.value(option) {
+            Some(value) => value,
+            None => fail(&format!("{} requires {}", self.command.name, option)),
+        }
+    }
+}
+
+/* Runs the command of the arguments (without the program name), exiting if they are invalid */
+pub fn run(args: Vec<String>) {
+    let name = match args.first() {
+        Some(name) => name.as_str(),
+        None => {
+            eprint!("{}", usage());
+            process::exit(1);
+        }
+    };
+    if name == "--help" || name == "-h" || name == "help" {
+        print!("{}", usage());
+        return;
+    }
+    let command = match COMMANDS.iter().find(|command| command.name == name) {
+        Some(command) => command,
+        None => fail(&format!("unknown command '{}'", name)),
+    };
+
+    let mut options = Options {
+        command,
+        values: HashMap::new(),
+        paths: Vec::new(),
+    };
+    let mut args = args.into_iter().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--help" || arg == "-h" {
+            print!("{}", command_usage(command));
+            return;
+        }
+        if !arg.starts_with("--") {
+            options.paths.push(arg);
+            continue;
+        }
+        let option = match command.options.iter().find(|option| **option == arg) {
+            Some(option) => *option,
+            None => fail(&format!("{} does not take {}", command.name, arg)),
+        };
+        if is_flag(option) {
+            options.values.insert(option, String::new());
+            continue;
+        }
+        match args.next() {
+            Some(value) => {
+                options.values.insert(option, value);
+            }
+            None => fail(&format!("{} requires a value", option)),
+        }
+    }
+    let arg_count = command.args.split_whitespace().count();
+    if options.paths.len() != arg_count {
+        fail(&format!("{} takes {}", command.name, command.args));
+    }
+    (command.run)(&options);
+}
+
+fn usage() -> String {
+    let mut usage = String::from("Usage: debughdr <command> [options] <files>\n\nCommands:\n");
+    for command in COMMANDS {
+        usage += &format!("  {:<9}{}\n", command.name, command.about);
+    }
+    usage += "\nRun 'debughdr <command> --help' for the options of a command.\n";
+    usage
+}
+
+fn command_usage(command: &Command) -> String {
+    let mut usage = format!(
+        "Usage: debughdr {} [options] {}\n\n{}\n\nOptions:\n",
+        command.name, command.args, command.about
+    );
+    for (option, value, about) in OPTIONS {
+        if command.options.contains(option) {
+            let about = match *option {
+                "--output-format" => format!("{}, {}", about, list_formats(command.formats)),
+                _ => about.to_string(),
+            };
+            let option = format!("{} {}", option, value).trim_end().to_string();
+            usage += &format!("  {:<27}{}\n", option, about);
+        }
+    }
+    usage += &format!("  {:<27}{}\n", "-h, --help", "prints this help");
+    usage
+}
+
+/* "text (default), csv or json" */
+fn list_formats(formats: &[&str]) -> String {
+    let mut list = String::new();
+    for (i, format) in formats.iter().enumerate() {
+        if i > 0 {
+            list += if i + 1 == formats.len() { " or " } else { ", " };
+        }
+        list += format;
+        if i == 0 {
+            list += " (default)";
+        }
+    }
+    list
+}
+
+pub fn is_flag(option: &str) -> bool {
+    OPTIONS
+        .iter()
+        .any(|(name, value, _)| *name == option && value.is_empty())
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("debughdr: {}", message);
+    eprintln!("Run 'debughdr --help' for usage.");
+    process::exit(1);
+}
+
+fn map_file(path: &str) -> memmap::Mmap {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("Failed to open file '{}': {}", path, err);
+            process::exit(1);
+        }
+    };
+    match unsafe { memmap::Mmap::map(&file) } {
+        Ok(mmap) => mmap,
+        Err(err) => {
+            eprintln!("Failed to map file '{}': {}", path, err);
+            process::exit(1);
+        }
+    }
+}
+
+fn parse_object<'data>(path: &str, data: &'data [u8]) -> object::File<'data> {
+    match object::File::parse(data) {
+        Ok(object) => object,
+        Err(err) => {
+            eprintln!("Failed to parse file '{}': {}", path, err);
+            process::exit(1);
+        }
+    }
+}
+
+fn read_file(path: &str) -> String {
+    match fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(err) => {
+            eprintln!("Failed to read file '{}': {}", path, err);
+            process::exit(1);
+        }
+    }
+}
+
+fn write_file(path: &str, data: impl AsRef<[u8]>) {
+    if let Err(err) = fs::write(path, data) {
+        eprintln!("Failed to write file '{}': {}", path, err);
+        process::exit(1);
+    }
+}
+
+fn get_arch(name: &str) -> Option<Architecture> {
+    match name {
+        "i386" | "x86" => Some(Architecture::I386),
+        "x86-64" | "x86_64" => Some(Architecture::X86_64),
+        "aarch64" => Some(Architecture::Aarch64),
+        "riscv32" => Some(Architecture::Riscv32),
+        "riscv64" => Some(Architecture::Riscv64),
+        _ => None,
+    }
+}
+
+fn get_output_format(options: &Options) -> OutputFormat {
+    let command = options.command;
+    let format = options
+        .value("--output-format")
+        .unwrap_or(command.formats[0]);
+    if !command.formats.contains(&format) {
+        fail(&format!(
+            "{} has no {} output ({})",
+            command.name,
+            format,
+            list_formats(command.formats)
+        ));
+    }
+    format.parse().unwrap_or_else(|err: String| fail(&err))
+}
+
+/* A rewriter of in_object with the mappings of the expression file and the options given */
+fn get_rewriter<'a, 'data>(
+    options: &Options,
+    in_object: &'a object::File<'data>,
+) -> DebugInfoRewriter<'a, 'data> {
+    let expression_file = match options.value("--expr-file") {
+        Some(path) => match fs::File::open(path) {
+            Ok(mut file) => ExpressionFile::read(&mut file),
+            Err(err) => {
+                eprintln!("Failed to open file '{}': {}", path, err);
+                process::exit(1);
+            }
+        },
+        None => ExpressionFile::read(&mut io::stdin()),
+    };
+    let expression_file = match expression_file {
+        Ok(expression_file) => expression_file,
+        Err(err) => {
+            eprintln!("Failed to read the expression file: {}", err);
+            process::exit(1);
+        }
+    };
+
+    let mut rewriter = DebugInfoRewriter::new(in_object);
+    if let Some(version) = options.value("--dwarf-version") {
+        match version.parse::<u16>() {
+            Ok(version) => rewriter.set_dwarf_version(version),
+            Err(_) => fail(&format!("invalid DWARF version '{}'", version)),
+        }
+    }
+    if let Some(arch) = options.value("--arch") {
+        match get_arch(arch) {
+            Some(arch) => rewriter.set_architecture(arch),
+            None => fail(&format!("unknown architecture '{}'", arch)),
+        }
+    }
+    rewriter.set_simplify(options.value("--simplify").is_some());
+    for function in expression_file.functions {
+        for mapping in function.entries {
+            rewriter.add(mapping);
+        }
+    }
+    rewriter
+}
+
+/* Rewrites the object, in place for linked files which keep their layout, writing the report
+ * if one is asked for */
+fn rewrite(options: &Options, in_file_path: &str, in_data: &[u8]) -> Vec<u8> {
+    let in_object = parse_object(in_file_path, in_data);
+    let rewriter = get_rewriter(options, &in_object);
+    let report_path = options.value("--report");
+    let (data, report) = match rewriter.finish_any(in_data, report_path.is_some()) {
+        Ok(result) => result,
+        Err(err) => {
+            eprintln!("Failed to rewrite file '{}': {}", in_file_path, err);
+            process::exit(1);
+        }
+    };
+    if let (Some(path), Some(report)) = (report_path, report) {
+        write_file(path, report.to_json());
+    }
+    data
+}
+
+fn run_rewrite(options: &Options) {
+    let in_file_path = &options.paths[0];
+    let out_file_path = &options.paths[1];
+    let in_data = map_file(in_file_path);
+    let out_data = rewrite(options, in_file_path, &in_data);
+    write_file(out_file_path, out_data);
+}
+
+fn run_check(options: &Options) {
+    let in_file_path = &options.paths[0];
+    let in_data = map_file(in_file_path);
+    rewrite(options, in_file_path, &in_data);
+    println!("{}: OK", in_file_path);
+}
+
+/* The result of the evaluation of the function, exiting if it failed */
+fn evaluated<T>(function: &str, result: Result<T, String>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(err) => {
+            eprintln!("Failed to evaluate {}: {}", function, err);
+            process::exit(1);
+        }
+    }
+}
+
+fn run_eval(options: &Options) {
+    let function = options.required("--function");
+    let insn_map = read_file(options.required("--insn-map"));
+    let before_data = map_file(&options.paths[0]);
+    let before_object = parse_object(&options.paths[0], &before_data);
+    let after_data = map_file(&options.paths[1]);
+    let after_object = parse_object(&options.paths[1], &after_data);
+
+    let before = evaluated(function, read_dwarf(&before_object, function, &insn_map));
+    let after = evaluated(function, read_dwarf(&after_object, function, &insn_map));
+    let evaluation = evaluate_coverage(function, &before, &after);
+    let format = get_output_format(options);
+    print!(
+        "{}",
+        evaluation.format(format, options.value("--variables").is_some())
+    );
+
+    let heatmap_path = options.value("--heatmap");
+    let listing_path = options.value("--listing");
+    if heatmap_path.is_some() || listing_path.is_some() {
+        let heatmap = evaluated(function, heatmap(&after_object, function, &before, &after));
+        if let Some(path) = heatmap_path {
+            write_file(path, heatmap.to_csv());
+        }
+        if let Some(path) = listing_path {
+            write_file(path, heatmap.to_listing());
+        }
+    }
+}
+
+/* The pairs are those of the instruction maps of the directory, <function>.<compiler>.insn.map */
+fn run_suite(options: &Options) {
+    let dir = &options.paths[0];
+    let before_dir = options.value("--before-dir").unwrap_or(dir);
+    let pairs = match find_pairs(Path::new(dir), Path::new(before_dir)) {
+        Ok(pairs) => pairs,
+        Err(err) => {
+            eprintln!("Failed to read directory '{}': {}", dir, err);
+            process::exit(1);
+        }
+    };
+    let format = get_output_format(options);
+    print!("{}", evaluate_suite(&pairs).format(format));
+}
+
+/* The ranges [begin, end) of the instruction map where each variable is available, and whether
+ * it is a constant there */
+type Coverage = HashMap<String, BTreeSet<(u64, u64, bool)>>;
+
+fn read_coverage(options: &Options, path: &str, insn_map: &str) -> Coverage {
+    let data = map_file(path);
+    let object = parse_object(path, &data);
+    let function = options.required("--function");
+    evaluated(function, read_dwarf(&object, function, insn_map)).0
+}
+
+fn run_dump(options: &Options) {
+    let insn_map = read_file(options.required("--insn-map"));
+    let coverage = read_coverage(options, &options.paths[0], &insn_map);
+
+    let format = get_output_format(options);
+    if format == OutputFormat::Csv {
+        println!("variable,begin,end,constant");
+    }
+    let mut names: Vec<&String> = coverage.keys().collect();
+    names.sort();
+    for name in names {
+        if format == OutputFormat::Text {
+            println!("{}", name);
+        }
+        for &(begin, end, constant) in &coverage[name] {
+            match format {
+                OutputFormat::Text => println!(
+                    "  [0x{:x}, 0x{:x}){}",
+                    begin,
+                    end,
+                    if constant { " constant" } else { "" }
+                ),
+                OutputFormat::Csv => {
+                    println!("{},0x{:x},0x{:x},{}", name, begin, end, constant)
+                }
+                OutputFormat::Json => unreachable!("rejected by get_output_format"),
+            }
+        }
+    }
+}
+
+fn run_diff(options: &Options) {
+    let insn_map = read_file(options.required("--insn-map"));
+    let before = read_coverage(options, &options.paths[0], &insn_map);
+    let after = read_coverage(options, &options.paths[1], &insn_map);
+
+    let format = get_output_format(options);
+    if format == OutputFormat::Csv {
+        println!("variable,change,begin,end,constant");
+    }
+    let empty = BTreeSet::new();
+    let names: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+    for name in names {
+        let before_ranges = before.get(name).unwrap_or(&empty);
+        let after_ranges = after.get(name).unwrap_or(&empty);
+        let changes = before_ranges
+            .difference(after_ranges)
+            .map(|range| ("removed", range))
+            .chain(
+                after_ranges
+                    .difference(before_ranges)
+                    .map(|range| ("added", range)),
+            );
+        for (change, &(begin, end, constant)) in changes {
+            match format {
+                OutputFormat::Text => println!(
+                    "{} {} [0x{:x}, 0x{:x}){}",
+                    if change == "added" { '+' } else { '-' },
+                    name,
+                    begin,
+                    end,
+                    if constant { " constant" } else { "" }
+                ),
+                OutputFormat::Csv => {
+                    println!("{},{},0x{:x},0x{:x},{}", name, change, begin, end, constant)
+                }
+                OutputFormat::Json => unreachable!("rejected by get_output_format"),
+            }
+        }
+    }
+}
@@ -0,0 +1,6 @@
+use std::env;
+
+fn main() {
+    env_logger::init();
+    debughdr::run(env::args().skip(1).collect());
+}
@@ -3,87 +3,177 @@ use std::borrow::Cow;
 use gimli::read::EndianSlice;
 use gimli::read::Reader;
 use gimli::{self, read, LittleEndian};
-use object::{self, Object, ObjectSection};
+use log::{trace, warn};
+use object::{self, Architecture, Object, ObjectSection, ObjectSymbol};
 use std::str;
 
 extern crate capstone;
 use capstone::prelude::*;
+use std::collections::BTreeMap;
 use std::collections::BTreeSet;
 use std::collections::HashMap;
 use std::ops::Bound::Excluded;
 use std::ops::Bound::Included;
 
+/* Unit offset, function DIE offset and its PC range */
+type FuncEntry<O> = (
+    gimli::DebugInfoOffset<O>,
+    gimli::read::UnitOffset<O>,
+    (u64, u64),
+);
+
+/* The value of an address attribute, given inline or as an index into .debug_addr, None for
+ * other forms */
+fn get_address<T: Reader>(
+    dwarf: &gimli::read::Dwarf<T>,
+    unit: &read::Unit<T>,
+    value: read::AttributeValue<T>,
+) -> read::Result<Option<u64>> {
+    match value {
+        read::AttributeValue::Addr(address) => Ok(Some(address)),
+        read::AttributeValue::DebugAddrIndex(index) => dwarf.address(unit, index).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/* The PC range [begin, end) of an entry from its DW_AT_low_pc and DW_AT_high_pc, the latter being
+ * either an address or an offset from low_pc of any constant form. None if the entry has no
+ * such range. */
+fn read_pc_range<T: Reader>(
+    dwarf: &gimli::read::Dwarf<T>,
+    unit: &read::Unit<T>,
+    entry: &read::DebuggingInformationEntry<T>,
+) -> read::Result<Option<(u64, u64)>> {
+    let low_pc = match entry.attr_value(gimli::DW_AT_low_pc)? {
+        Some(low_pc) => get_address(dwarf, unit, low_pc)?,
+        None => None,
+    };
+    let (low_pc, high_pc) = match (low_pc, entry.attr_value(gimli::DW_AT_high_pc)?) {
+        (Some(low_pc), Some(high_pc)) => (low_pc, high_pc),
+        _ => return Ok(None),
+    };
+    let high_pc = match high_pc.udata_value() {
+        Some(offset) => Some(low_pc.wrapping_add(offset)),
+        None => get_address(dwarf, unit, high_pc)?,
+    };
+    Ok(high_pc.map(|high_pc| (low_pc, high_pc)))
+}
+
+/* Searches the subprograms of all the units for the definition named func_name. Where it is
+ * defined in several units, the one whose PC range has pc (an instruction of the function) is
+ * taken. */
 fn get_func_entry_offset<T: Reader>(
     dwarf: &gimli::read::Dwarf<T>,
     func_name: &str,
-) -> (
-    Option<gimli::read::UnitOffset<T::Offset>>,
-    Option<u64>,
-    Option<u64>,
-) {
+    pc: Option<u64>,
+) -> Result<FuncEntry<T::Offset>, String> {
+    let mut definitions = Vec::new();
     let units = &mut dwarf.units();
-    let unit_header = units.next().unwrap();
-    let unit = dwarf.unit(unit_header.unwrap()).unwrap();
-    let mut entries = unit.entries();
-    let mut func_entry_offset = None;
-    let mut func_start_addr = None;
-    let mut func_end_offset = None;
-    loop {
-        if let Some((_, entry)) = entries.next_dfs().unwrap() {
+    while let Some(unit_header) = units.next().map_err(dwarf_error)? {
+        let unit_offset = unit_header.offset();
+        let unit = dwarf.unit(unit_header).map_err(dwarf_error)?;
+        let mut entries = unit.entries();
+        while let Some((_, entry)) = entries.next_dfs().map_err(dwarf_error)? {
             if entry.tag() == gimli::DW_TAG_subprogram {
                 let mut attrs = entry.attrs();
                 let mut flag = false;
-                while let Some(attr) = attrs.next().unwrap() {
-                    if attr.name().static_string().unwrap() == "DW_AT_name" {
+                while let Some(attr) = attrs.next().map_err(dwarf_error)? {
+                    if attr.name() == gimli::DW_AT_name {
                         if let read::AttributeValue::DebugStrRef(debug_str_offset) = attr.value() {
-                            let str_val = dwarf.string(debug_str_offset).unwrap();
-                            if str_val.to_string().unwrap() == func_name {
-                                //eprintln!("function {} FOUND!", func_name);
+                            let str_val = dwarf.string(debug_str_offset).map_err(dwarf_error)?;
+                            if str_val.to_string().map_err(dwarf_error)? == func_name {
                                 flag = true;
                                 //break;
                             }
                         } else if let read::AttributeValue::String(reader) = attr.value() {
-                            let s = reader.to_string().unwrap();
+                            let s = reader.to_string().map_err(dwarf_error)?;
                             if s.to_string() == func_name {
                                 flag = true;
                             }
                         } else {
-                            eprintln!("read::AttributeValue of this type not handled yet!\n");
-                        }
-                    } else if attr.name().static_string().unwrap() == "DW_AT_low_pc" {
-                        if let read::AttributeValue::Addr(start_addr) = attr.value() {
-                            func_start_addr = Some(start_addr);
-                            //eprintln!("func start addr: {:x}", start_addr);
-                        }
-                    } else if attr.name().static_string().unwrap() == "DW_AT_high_pc" {
-                        if let read::AttributeValue::Udata(end_offset) = attr.value() {
-                            func_end_offset = Some(end_offset);
-                            //eprintln!("func end offset: {}", end_offset);
+                            warn!(
+                                target: "eval::coverage",
+                                "read::AttributeValue of this type not handled yet!"
+                            );
                         }
                     }
                 }
-                if flag == true {
-                    func_entry_offset = Some(entry.offset());
-                    break;
+                /* Declarations (without a PC range) are skipped, the definition may be in another
+                 * unit */
+                let pc_range = read_pc_range(dwarf, &unit, entry).map_err(dwarf_error)?;
+                if let (true, Some(pc_range)) = (flag, pc_range) {
+                    definitions.push((unit_offset, entry.offset(), pc_range));
                 }
             }
-        } else {
+        }
+    }
+    if definitions.len() > 1 {
+        let units = definitions.len();
+        definitions.retain(|&(_, _, (begin, end))| pc.map_or(false, |pc| begin <= pc && pc < end));
+        if definitions.len() != 1 {
+            return Err(format!(
+                "function {} is defined in {} units, none having alone the PCs of the insn map",
+                func_name, units
+            ));
+        }
+    }
+    definitions
+        .pop()
+        .ok_or_else(|| format!("function {} not present in .debug_info", func_name))
+}
+
+fn dwarf_error(err: read::Error) -> String {
+    format!("failed to read DWARF: {}", err)
+}
+
+/* The PCs of an instruction map: a "=insn_pcs" line, then a line "<index>: <pc>" per
+ * instruction up to "=End", the PC 0x7fffffff marking instructions which have none */
+fn parse_insn_map(insn_map_str: &str) -> Result<BTreeSet<u64>, String> {
+    let mut insn_set = BTreeSet::new();
+    let mut lines = insn_map_str.lines();
+    if lines.next() != Some("=insn_pcs") {
+        return Err(String::from(
+            "invalid insn map: expected =insn_pcs on its first line",
+        ));
+    }
+    for (index, line) in lines.enumerate() {
+        if line == "=End" {
             break;
         }
+        let pc = match line.split(':').nth(1) {
+            Some(pc) => pc.trim().trim_start_matches("0x"),
+            None => {
+                return Err(format!(
+                    "invalid insn map line {}: expected <index>: <pc>",
+                    index + 2
+                ))
+            }
+        };
+        trace!(target: "eval::coverage", "pc: {}", pc);
+        let pc = u64::from_str_radix(pc, 16)
+            .map_err(|err| format!("invalid insn map line {}: {}", index + 2, err))?;
+        if pc != 0x7fffffff {
+            insn_set.insert(pc);
+        }
     }
-    //let func_entry_offset = func_entry.unwrap().offset();
-    (func_entry_offset, func_start_addr, func_end_offset)
+    Ok(insn_set)
 }
 
+/* What the DWARF of an object says of a function over the PCs of its instruction map: the ranges
+ * [begin, end) where each variable is available and whether it is a constant there, the PCs, the
+ * number of PC-variable pairs covered, and that at each PC */
+pub type DwarfCoverage = (
+    HashMap<String, BTreeSet<(u64, u64, bool)>>,
+    BTreeSet<u64>,
+    i64,
+    BTreeMap<u64, i64>,
+);
+
 pub fn read_dwarf(
     file: &object::File<'_>,
     func_name: &str,
     insn_map_str: &str,
-) -> (
-    HashMap<String, BTreeSet<(u64, u64, bool)>>,
-    BTreeSet<u64>,
-    i64,
-) {
+) -> Result<DwarfCoverage, String> {
     fn get_reader<'a>(
         data: &'a [u8],
         relocations: &'a ReadRelocationMap,
@@ -95,24 +185,24 @@ pub fn read_dwarf(
             section,
             reader,
         }
-    };
+    }
 
     let no_section = (Cow::Borrowed(&[][..]), ReadRelocationMap::default());
 
-    let (debug_abbrev_data, debug_abbrev_relocs) = get_section(file, ".debug_abbrev");
-    let (debug_addr_data, debug_addr_relocs) = get_section(file, ".debug_addr");
-    let (debug_info_data, debug_info_relocs) = get_section(file, ".debug_info");
-    let (debug_line_data, debug_line_relocs) = get_section(file, ".debug_line");
-    let (debug_line_str_data, debug_line_str_relocs) = get_section(file, ".debug_line_str");
-    let (debug_loc_data, debug_loc_relocs) = get_section(file, ".debug_loc");
-    let (debug_loclists_data, debug_loclists_relocs) = get_section(file, ".debug_loclists");
-    let (debug_ranges_data, debug_ranges_relocs) = get_section(file, ".debug_ranges");
+    let (debug_abbrev_data, debug_abbrev_relocs) = get_section(file, ".debug_abbrev")?;
+    let (debug_addr_data, debug_addr_relocs) = get_section(file, ".debug_addr")?;
+    let (debug_info_data, debug_info_relocs) = get_section(file, ".debug_info")?;
+    let (debug_line_data, debug_line_relocs) = get_section(file, ".debug_line")?;
+    let (debug_line_str_data, debug_line_str_relocs) = get_section(file, ".debug_line_str")?;
+    let (debug_loc_data, debug_loc_relocs) = get_section(file, ".debug_loc")?;
+    let (debug_loclists_data, debug_loclists_relocs) = get_section(file, ".debug_loclists")?;
+    let (debug_ranges_data, debug_ranges_relocs) = get_section(file, ".debug_ranges")?;
     /*let (debug_aranges_data, debug_aranges_relocs) = get_section(file, ".debug_aranges");*/
-    let (debug_rnglists_data, debug_rnglists_relocs) = get_section(file, ".debug_rnglists");
-    let (debug_str_data, debug_str_relocs) = get_section(file, ".debug_str");
+    let (debug_rnglists_data, debug_rnglists_relocs) = get_section(file, ".debug_rnglists")?;
+    let (debug_str_data, debug_str_relocs) = get_section(file, ".debug_str")?;
     let (debug_str_offsets_data, debug_str_offsets_relocs) =
-        get_section(file, ".debug_str_offsets");
-    let (debug_types_data, debug_types_relocs) = get_section(file, ".debug_types");
+        get_section(file, ".debug_str_offsets")?;
+    let (debug_types_data, debug_types_relocs) = get_section(file, ".debug_types")?;
     let debug_addr = read::DebugAddr::from(get_reader(&debug_addr_data, &debug_addr_relocs));
     let dwarf = read::Dwarf {
         debug_abbrev: read::DebugAbbrev::from(get_reader(&debug_abbrev_data, &debug_abbrev_relocs)),
@@ -140,79 +230,40 @@ pub fn read_dwarf(
         ),
     };
 
-    let (func_entry_offset, func_start_addr, func_end_offset) =
-        get_func_entry_offset(&dwarf, func_name);
-    let func_start_addr = func_start_addr.unwrap();
-    let func_end_offset = func_end_offset.unwrap();
-    let func_end_addr = func_start_addr + func_end_offset;
-
     /* Instructions Disassembly using Capstone */
-    let mut text_data = None;
-    if let Some(section) = file.section_by_name(".text") {
-        text_data = Some(section.uncompressed_data().unwrap());
-    }
-    let cs = Capstone::new()
-        .x86()
-        .mode(arch::x86::ArchMode::Mode32)
-        .syntax(arch::x86::ArchSyntax::Att)
-        .detail(true)
-        .build()
-        .expect("Failed to create capstone object");
-    let insns = cs.disasm_all(&text_data.unwrap(), 0x0000).unwrap();
-    /*let insns = cs.disasm_count(&text_data.unwrap(), 0x3e00, func_end_offset.try_into().unwrap())
-    .expect("Failed to disassemble");*/
-    //eprintln!("Found {} instructions", insns.len());
-
-    let mut insn_map = HashMap::new();
-    let mut insn_set = BTreeSet::new();
-
-    let mut lines = insn_map_str/*.as_str()*/.lines();
-    let line = lines.next().unwrap();
-    assert!(line == "=insn_pcs", "Invalid insn-map-file format!");
-    for line in lines {
-        if line == "=End" {
-            break;
-        }
-        let index_and_pc: Vec<&str> = line.trim().split(':').collect();
-        let pc_str = index_and_pc[1];
-        let pc = pc_str.trim().trim_start_matches("0x");
-        eprintln!("pc: {}", pc);
-        let pc = u64::from_str_radix(pc, 16).unwrap();
-        if pc != 0x7fffffff {
-            insn_map.insert(pc, 0);
-            insn_set.insert(pc);
-        }
-    }
-
-
-    let mut insn_size = HashMap::new();
-    for i in insns.iter() {
-        ////eprintln!("{:x}", i.address());
-        if i.address() >= func_start_addr && i.address() < func_end_addr {
-            //eprintln!("{}", i);
-            /* Not using insn_map and insn_set here now */
-            //insn_map.insert(i.address(), 0);
-            //insn_set.insert(i.address());
-            insn_size.insert(i.address(), i.bytes().len());
-        }
-    }
-
+    let (text_data, text_address) = get_text(file)?;
+    let insn_set = parse_insn_map(insn_map_str)?;
+    let mut insn_map: HashMap<u64, i64> = insn_set.iter().map(|&pc| (pc, 0)).collect();
+
+    let (unit_offset, func_entry_offset, pc_range) =
+        get_func_entry_offset(&dwarf, func_name, insn_set.iter().next().copied())?;
+    let (func_start_addr, func_end_addr) = pc_range;
+
+    let insn_size = get_insn_sizes(
+        file,
+        &text_data,
+        text_address,
+        func_start_addr,
+        func_end_addr,
+    )?;
 
     let mut results_map = HashMap::new();
 
-    let units = &mut dwarf.units();
-    let unit_header = units.next().unwrap();
-    let unit = dwarf.unit(unit_header.unwrap()).unwrap();
+    let unit_header = dwarf
+        .debug_info
+        .header_from_offset(unit_offset)
+        .map_err(dwarf_error)?;
+    let unit = dwarf.unit(unit_header).map_err(dwarf_error)?;
     let encoding = unit.encoding();
 
-    let mut entries = unit.entries_at_offset(func_entry_offset.unwrap()).unwrap();
+    let mut entries = unit
+        .entries_at_offset(func_entry_offset)
+        .map_err(dwarf_error)?;
 
     let mut depth = 0;
     let mut first = true;
-    let mut low_pc = None;
-    let mut high_pc = None;
     let mut scope_ranges: Option<Vec<(u64, u64)>> = None;
-    while let Some((index, entry)) = entries.next_dfs().unwrap() {
+    while let Some((index, entry)) = entries.next_dfs().map_err(dwarf_error)? {
         let mut var_name = None;
         let mut var_info = None;
         depth += index;
@@ -222,40 +273,36 @@ pub fn read_dwarf(
         if first == true {
             first = false;
         }
-        //eprintln!("Entry tag: {:?}", entry.tag().static_string());
-        //eprintln!("Index : {}", index);
         if true {
             let mut attrs = entry.attrs();
-            let mut low_pc_attr_present = false;
-            let mut high_pc_attr_present = false;
             let mut ranges_attr_present = false;
-            while let Some(attr) = attrs.next().unwrap() {
-                if attr.name().static_string().unwrap() == "DW_AT_name" {
+            while let Some(attr) = attrs.next().map_err(dwarf_error)? {
+                if attr.name() == gimli::DW_AT_name {
                     if let read::AttributeValue::DebugStrRef(debug_str_offset) = attr.value() {
-                        let str_val = dwarf.string(debug_str_offset).unwrap();
-                        var_name = Some(str_val.to_string().unwrap().into_owned());
-                    //eprintln!("Variable name: {:?}", str_val.to_string().unwrap());
-                    //eprintln!("Name: {:?}", str_val.to_string().unwrap());
+                        let str_val = dwarf.string(debug_str_offset).map_err(dwarf_error)?;
+                        var_name = Some(str_val.to_string().map_err(dwarf_error)?.into_owned());
                     } else if let read::AttributeValue::String(reader) = attr.value() {
-                        let s = reader.to_string().unwrap();
+                        let s = reader.to_string().map_err(dwarf_error)?;
                         var_name = Some(s.to_string());
                     } else {
-                        eprintln!("AttributeValue of this type is not handled yet!\n");
+                        warn!(
+                            target: "eval::coverage",
+                            "AttributeValue of this type is not handled yet!"
+                        );
                     }
-                //eprintln!("Variable Name: {:?}", attr.value());
-                } else if attr.name().static_string().unwrap() == "DW_AT_location" {
+                } else if attr.name() == gimli::DW_AT_location {
                     if let read::AttributeValue::LocationListsRef(location_lists_offset) =
                         attr.value()
                     {
-                        let mut loclist_iter =
-                            dwarf.locations(&unit, location_lists_offset).unwrap();
+                        let mut loclist_iter = dwarf
+                            .locations(&unit, location_lists_offset)
+                            .map_err(dwarf_error)?;
                         let mut locations = BTreeSet::new();
-                        while let Some(loclist_entry) = loclist_iter.next().unwrap() {
-                            //eprintln!("loc range: {:?}", loclist_entry.range);
+                        while let Some(loclist_entry) = loclist_iter.next().map_err(dwarf_error)? {
                             //let mut write_expr = gimli::write::Expression::from(loclist_entry.data.clone(), encoding, None, None, None, &convert_address).unwrap();
                             let mut ops_iter = loclist_entry.data.operations(encoding);
                             let mut is_const = false;
-                            if let Some(op) = ops_iter.next().unwrap() {
+                            if let Some(op) = ops_iter.next().map_err(dwarf_error)? {
                                 if let gimli::read::Operation::SignedConstant { value: _ } =
                                     op.clone()
                                 {
@@ -267,7 +314,7 @@ pub fn read_dwarf(
                                     is_const = true;
                                 }
                             }
-                            if let Some(op) = ops_iter.next().unwrap() {
+                            if let Some(op) = ops_iter.next().map_err(dwarf_error)? {
                                 if let gimli::read::Operation::StackValue = op {
                                     is_const = is_const && true;
                                 } else {
@@ -296,7 +343,6 @@ pub fn read_dwarf(
                                     }
                                     if count_this_pc {
                                         contiguous_range_so_far.push(pc);
-                                        //eprintln!("{}", pc);
                                         let pc_cnt = insn_map.get_mut(&pc).unwrap();
                                         *pc_cnt = *pc_cnt + 1;
                                     //tmp += 1;
@@ -330,7 +376,10 @@ pub fn read_dwarf(
                                     contiguous_range_so_far.clear();
                                 }
                             } else {
-                                eprintln!("WARNING: No scope defined for current variable!");
+                                warn!(
+                                    target: "eval::coverage",
+                                    "No scope defined for current variable!"
+                                );
                             }
                             /*println!(
                                 "actual++: {} | {}->{}",
@@ -344,7 +393,7 @@ pub fn read_dwarf(
                             //let mut write_expr = gimli::write::Expression::from(dwarf_expr.clone(), encoding, None, None, None, &convert_address).unwrap();
                             let mut ops_iter = dwarf_expr.operations(encoding);
                             let mut is_const = false;
-                            if let Some(op) = ops_iter.next().unwrap() {
+                            if let Some(op) = ops_iter.next().map_err(dwarf_error)? {
                                 if let gimli::read::Operation::SignedConstant { value: _ } =
                                     op.clone()
                                 {
@@ -356,7 +405,7 @@ pub fn read_dwarf(
                                     is_const = true;
                                 }
                             }
-                            if let Some(op) = ops_iter.next().unwrap() {
+                            if let Some(op) = ops_iter.next().map_err(dwarf_error)? {
                                 if let gimli::read::Operation::StackValue = op {
                                     is_const = is_const && true;
                                 } else {
@@ -367,24 +416,25 @@ pub fn read_dwarf(
                                 locations.insert((begin, end, is_const));
                                 //let mut tmp = 0;
                                 for &pc in insn_set.range((Included(&begin), Excluded(&end))) {
-                                    //eprintln!("{}", pc);
                                     let pc_cnt = insn_map.get_mut(&pc).unwrap();
                                     *pc_cnt = *pc_cnt + 1;
                                     //tmp += 1;
                                 }
                             }
                             var_info = Some(locations);
-
-                        //println!("actual++: {} | {}->{}", tmp, low_pc, high_pc);
                         } else {
-                            eprintln!("read::AttributeValue -- location -- not handled yet!\n");
+                            warn!(
+                                target: "eval::coverage",
+                                "read::AttributeValue -- location -- not handled yet!"
+                            );
                         }
                     } else {
-                        eprintln!(
-                            "WARNING: Possibly because no scope defined for current variable!?"
+                        warn!(
+                            target: "eval::coverage",
+                            "Possibly because no scope defined for current variable!?"
                         );
                     }
-                } else if attr.name().static_string().unwrap() == "DW_AT_const_value" {
+                } else if attr.name() == gimli::DW_AT_const_value {
                     if scope_ranges != None {
                         let mut error = false;
                         let _data = match attr.value() {
@@ -400,7 +450,7 @@ pub fn read_dwarf(
                             }
                         };
                         if error {
-                            eprintln!("ERROR: Invalid data in DW_AT_const_value!");
+                            warn!(target: "eval::coverage", "Invalid data in DW_AT_const_value!");
                         }
                         let mut locations = BTreeSet::new();
                         /*let mut write_expr = gimli::write::Expression::new();
@@ -410,59 +460,32 @@ pub fn read_dwarf(
                             locations.insert((begin, end, /*is_const*/ true));
                             //let mut tmp = 0;
                             for &pc in insn_set.range((Included(&begin), Excluded(&end))) {
-                                //eprintln!("{}", pc);
                                 let pc_cnt = insn_map.get_mut(&pc).unwrap();
                                 *pc_cnt = *pc_cnt + 1;
                                 //tmp += 1;
                             }
                         }
                         var_info = Some(locations);
-                    //println!("actual++: {} | {}->{}", tmp, low_pc, high_pc);
                     } else {
-                        eprintln!("WARNING: No scope defined for current variable!");
+                        warn!(target: "eval::coverage", "No scope defined for current variable!");
                     }
-                } else if attr.name().static_string().unwrap() == "DW_AT_low_pc" {
-                    if let read::AttributeValue::Addr(_addr) = attr.value() {
-                        low_pc_attr_present = true;
-                        /*low_pc = Some(addr);*/
-                    }
-                } else if attr.name().static_string().unwrap() == "DW_AT_high_pc" {
-                    if let read::AttributeValue::Udata(_offset) = attr.value() {
-                        high_pc_attr_present = true;
-                        /*high_pc = Some(offset);*/
-                    }
-                } else if attr.name().static_string().unwrap() == "DW_AT_ranges" {
+                } else if attr.name() == gimli::DW_AT_ranges {
                     if let read::AttributeValue::RangeListsRef(_offset) = attr.value() {
                         ranges_attr_present = true;
                     }
                 }
             }
-            let low_pc_val = entry.attr_value(gimli::DW_AT_low_pc).unwrap();
-            let high_pc_val = entry.attr_value(gimli::DW_AT_high_pc).unwrap();
-            if low_pc_attr_present && high_pc_attr_present {
-                let low_pc_val = low_pc_val.unwrap();
-                let high_pc_val = high_pc_val.unwrap();
-                if let read::AttributeValue::Addr(addr) = low_pc_val {
-                    low_pc = Some(addr);
-                }
-                if let read::AttributeValue::Udata(offset) = high_pc_val {
-                    high_pc = Some(offset);
-                }
-                if low_pc != None && high_pc != None {
-                    let low_pc = low_pc.unwrap();
-                    let high_pc = low_pc + high_pc.unwrap();
-                    let scope_vec = vec![(low_pc, high_pc)];
-                    scope_ranges = Some(scope_vec);
-                }
+            if let Some(pc_range) = read_pc_range(&dwarf, &unit, entry).map_err(dwarf_error)? {
+                scope_ranges = Some(vec![pc_range]);
             }
 
-            let ranges_val = entry.attr_value(gimli::DW_AT_ranges).unwrap();
+            let ranges_val = entry.attr_value(gimli::DW_AT_ranges).map_err(dwarf_error)?;
             if ranges_attr_present {
                 let ranges_val = ranges_val.unwrap();
                 if let read::AttributeValue::RangeListsRef(offset) = ranges_val {
-                    let mut rangelist_iter = dwarf.ranges(&unit, offset).unwrap();
+                    let mut rangelist_iter = dwarf.ranges(&unit, offset).map_err(dwarf_error)?;
                     let mut scope_vec = Vec::new();
-                    while let Some(range_entry) = rangelist_iter.next().unwrap() {
+                    while let Some(range_entry) = rangelist_iter.next().map_err(dwarf_error)? {
                         scope_vec.push((range_entry.begin, range_entry.end));
                     }
                     if scope_vec.is_empty() {
@@ -485,32 +508,163 @@ pub fn read_dwarf(
             }
         }
     }
-    //eprintln!("While loop exited!");
-    //eprintln!("insn_map = {:?}", insn_map);
-    //eprintln!("insn_set = {:?}", insn_set);
     let mut summation: i64 = 0;
-    //println!("=actual_coverage");
     for key in &insn_set {
         let val = insn_map.get(&key).unwrap();
-        //println!("{:x}: {}", key, val);
         summation += val;
     }
     let cumulative_actual_count: i64 = summation;
-    //println!("=actual_total\n{}", summation);
-    (results_map, insn_set, cumulative_actual_count)
+    let pc_counts = insn_set.iter().map(|pc| (*pc, insn_map[pc])).collect();
+    Ok((results_map, insn_set, cumulative_actual_count, pc_counts))
 }
 
 type ReadRelocationMap = HashMap<usize, object::Relocation>;
 
+/* The data of .text and its address, that of its first byte: 0 in relocatable objects, where
+ * the PCs are offsets into the section */
+fn get_text<'data>(file: &object::File<'data>) -> Result<(Cow<'data, [u8]>, u64), String> {
+    match file.section_by_name(".text") {
+        Some(section) => match section.uncompressed_data() {
+            Ok(data) => Ok((data, section.address())),
+            Err(err) => Err(format!("failed to read .text: {}", err)),
+        },
+        None => Err(String::from("no .text section")),
+    }
+}
+
+/* The size of the first instruction of the RISC-V code at pc, if .text has it. Its length is
+ * encoded in the low bits of its first parcel: anything other than 0b11 is a compressed one. */
+fn get_riscv_insn_size(text_data: &[u8], text_address: u64, pc: u64) -> Option<usize> {
+    let offset = pc.checked_sub(text_address)? as usize;
+    let parcel = text_data.get(offset)?;
+    Some(if parcel & 0b11 == 0b11 { 4 } else { 2 })
+}
+
+fn get_insn_sizes(
+    file: &object::File,
+    text_data: &[u8],
+    text_address: u64,
+    func_start_addr: u64,
+    func_end_addr: u64,
+) -> Result<HashMap<u64, usize>, String> {
+    let mut insn_size = HashMap::new();
+    let cs = match get_capstone(file) {
+        Some(cs) => cs,
+        None => {
+            /* Capstone has no RISC-V backend */
+            let mut address = func_start_addr;
+            while let Some(size) = get_riscv_insn_size(text_data, text_address, address) {
+                if address >= func_end_addr {
+                    break;
+                }
+                insn_size.insert(address, size);
+                address += size as u64;
+            }
+            return Ok(insn_size);
+        }
+    };
+    let insns = cs
+        .disasm_all(text_data, text_address)
+        .map_err(|err| format!("failed to disassemble .text: {}", err))?;
+    for i in insns.iter() {
+        if i.address() >= func_start_addr && i.address() < func_end_addr {
+            insn_size.insert(i.address(), i.bytes().len());
+        }
+    }
+    Ok(insn_size)
+}
+
+/* The text of the instructions of .text at the PCs given. Without a Capstone backend (RISC-V),
+ * instructions are written as their bytes, like objdump does for unknown ones. */
+pub(crate) fn get_insn_texts(
+    file: &object::File,
+    pcs: &BTreeSet<u64>,
+) -> Result<HashMap<u64, String>, String> {
+    let mut insn_text = HashMap::new();
+    let (text_data, text_address) = get_text(file)?;
+    let cs = match get_capstone(file) {
+        Some(cs) => cs,
+        None => {
+            for &pc in pcs {
+                let size = match get_riscv_insn_size(&text_data, text_address, pc) {
+                    Some(size) => size,
+                    None => continue,
+                };
+                let offset = (pc - text_address) as usize;
+                if let Some(bytes) = text_data.get(offset..offset + size) {
+                    let mut value = [0; 4];
+                    value[..size].copy_from_slice(bytes);
+                    let value = u32::from_le_bytes(value);
+                    let text = if size == 4 {
+                        format!(".4byte 0x{:08x}", value)
+                    } else {
+                        format!(".2byte 0x{:04x}", value)
+                    };
+                    insn_text.insert(pc, text);
+                }
+            }
+            return Ok(insn_text);
+        }
+    };
+    let insns = cs
+        .disasm_all(&text_data, text_address)
+        .map_err(|err| format!("failed to disassemble .text: {}", err))?;
+    for i in insns.iter() {
+        if pcs.contains(&i.address()) {
+            let text = format!(
+                "{} {}",
+                i.mnemonic().unwrap_or(""),
+                i.op_str().unwrap_or("")
+            );
+            insn_text.insert(i.address(), text.trim_end().to_string());
+        }
+    }
+    Ok(insn_text)
+}
+
+/* None for RISC-V, which Capstone does not support */
+fn get_capstone(file: &object::File) -> Option<Capstone> {
+    let cs = match file.architecture() {
+        Architecture::X86_64 => Capstone::new()
+            .x86()
+            .mode(arch::x86::ArchMode::Mode64)
+            .syntax(arch::x86::ArchSyntax::Att)
+            .detail(true)
+            .build(),
+        Architecture::Aarch64 => Capstone::new()
+            .arm64()
+            .mode(arch::arm64::ArchMode::Arm)
+            .detail(true)
+            .build(),
+        Architecture::Arm => Capstone::new()
+            .arm()
+            .mode(arch::arm::ArchMode::Arm)
+            .detail(true)
+            .build(),
+        Architecture::Riscv32 | Architecture::Riscv64 => return None,
+        _ => Capstone::new()
+            .x86()
+            .mode(arch::x86::ArchMode::Mode32)
+            .syntax(arch::x86::ArchSyntax::Att)
+            .detail(true)
+            .build(),
+    }
+    .expect("Failed to create capstone object");
+    Some(cs)
+}
+
 fn get_section<'data>(
     file: &object::File<'data>,
     name: &str,
-) -> (Cow<'data, [u8]>, ReadRelocationMap) {
+) -> Result<(Cow<'data, [u8]>, ReadRelocationMap), String> {
     let mut relocations = ReadRelocationMap::default();
     let section = match file.section_by_name(name) {
         Some(section) => section,
-        None => return (Cow::Borrowed(&[][..]), relocations),
+        None => return Ok((Cow::Borrowed(&[][..]), relocations)),
     };
+    let mut data = section
+        .uncompressed_data()
+        .map_err(|err| format!("failed to read {}: {}", name, err))?;
     for (offset64, mut relocation) in section.relocations() {
         let offset = offset64 as usize;
         if offset as u64 != offset64 {
@@ -524,7 +678,6 @@ fn get_section<'data>(
                         if let Ok(symbol) = file.symbol_by_index(symbol) {
                             let addend = symbol.address().wrapping_add(relocation.addend() as u64);
                             relocation.set_addend(addend as i64);
-                            //println!("Adding reloc {} {:?}", offset, relocation);
                             if relocations.insert(offset, relocation).is_some() {
                                 /*println!(
                                     "Multiple relocations for section {} at offset 0x{:08x}",
@@ -549,6 +702,9 @@ fn get_section<'data>(
                     }
                 }
             }
+            object::RelocationKind::Elf(r_type) if is_riscv_add_sub(file, r_type) => {
+                apply_riscv_add_sub(file, data.to_mut(), offset, &relocation);
+            }
             _ => {
                 /*println!(
                     "Unsupported relocation kind for section {} at offset 0x{:08x}",
@@ -559,8 +715,60 @@ fn get_section<'data>(
         }
     }
 
-    let data = section.uncompressed_data().unwrap();
-    (data, relocations)
+    Ok((data, relocations))
+}
+
+fn is_riscv_add_sub(file: &object::File, r_type: u32) -> bool {
+    match file.architecture() {
+        object::Architecture::Riscv32 | object::Architecture::Riscv64 => {
+            (object::elf::R_RISCV_ADD8..=object::elf::R_RISCV_SUB64).contains(&r_type)
+        }
+        _ => false,
+    }
+}
+
+/* RISC-V objects describe label differences (e.g. DW_AT_high_pc and location list bounds) as a
+ * pair of ADD/SUB relocations against a zero field, since linker relaxation may still move the
+ * labels. Fold each of them into the section data so that the field reads as the difference. */
+fn apply_riscv_add_sub(
+    file: &object::File,
+    data: &mut [u8],
+    offset: usize,
+    relocation: &object::Relocation,
+) -> bool {
+    let symbol = match relocation.target() {
+        object::RelocationTarget::Symbol(symbol) => match file.symbol_by_index(symbol) {
+            Ok(symbol) => symbol,
+            Err(_) => return false,
+        },
+        _ => return false,
+    };
+    let r_type = match relocation.kind() {
+        object::RelocationKind::Elf(r_type) => r_type,
+        _ => return false,
+    };
+    /* object leaves the size of these relocations unset; the type encodes it instead */
+    let size = match r_type {
+        object::elf::R_RISCV_ADD8 | object::elf::R_RISCV_SUB8 => 1,
+        object::elf::R_RISCV_ADD16 | object::elf::R_RISCV_SUB16 => 2,
+        object::elf::R_RISCV_ADD32 | object::elf::R_RISCV_SUB32 => 4,
+        _ => 8,
+    };
+    let field = match data.get_mut(offset..offset + size) {
+        Some(field) => field,
+        None => return false,
+    };
+    let value = symbol.address().wrapping_add(relocation.addend() as u64);
+    let mut bytes = [0; 8];
+    bytes[..size].copy_from_slice(field);
+    let old = u64::from_le_bytes(bytes);
+    let new = if r_type >= object::elf::R_RISCV_SUB8 {
+        old.wrapping_sub(value)
+    } else {
+        old.wrapping_add(value)
+    };
+    field.copy_from_slice(&new.to_le_bytes()[..size]);
+    true
 }
 
 #[derive(Debug, Clone)]
@@ -602,14 +810,12 @@ impl<'a, R: read::Reader<Offset = usize>> read::Reader for ReaderRelocate<'a, R>
     fn read_offset(&mut self, format: gimli::Format) -> read::Result<usize> {
         let offset = self.reader.offset_from(&self.section);
         let value = self.reader.read_offset(format)?;
-        //println!("read_offset {} {}", offset, value);
         <usize as read::ReaderOffset>::from_u64(self.relocate(offset, value as u64))
     }
 
     fn read_sized_offset(&mut self, size: u8) -> read::Result<usize> {
         let offset = self.reader.offset_from(&self.section);
         let value = self.reader.read_sized_offset(size)?;
-        //println!("read_sized_offset {} {}", offset, value);
         <usize as read::ReaderOffset>::from_u64(self.relocate(offset, value as u64))
     }
 
@@ -688,3 +894,97 @@ impl<'a, R: read::Reader<Offset = usize>> read::Reader for ReaderRelocate<'a, R>
         self.reader.read_slice(buf)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* A DWARF 5 unit with a subprogram for each form of PC range: high_pc as an address, high_pc
+     * as an offset, low_pc as an index into .debug_addr, that index being out of range, and no
+     * low_pc at all */
+    const DEBUG_ABBREV: &[u8] = &[
+        1, 0x11, 1, 0x73, 0x17, 0, 0, // compile_unit: addr_base sec_offset
+        2, 0x2e, 0, 0x11, 0x01, 0x12, 0x01, 0, 0, // subprogram: low_pc addr, high_pc addr
+        3, 0x2e, 0, 0x11, 0x01, 0x12, 0x06, 0, 0, // subprogram: low_pc addr, high_pc data4
+        4, 0x2e, 0, 0x11, 0x1b, 0x12, 0x0f, 0, 0, // subprogram: low_pc addrx, high_pc udata
+        5, 0x2e, 0, 0x12, 0x0f, 0, 0, // subprogram: high_pc udata
+        0,
+    ];
+    const DEBUG_ADDR: &[u8] = &[
+        20, 0, 0, 0, 5, 0, 8, 0, // header
+        0, 0, 0, 0, 0, 0, 0, 0, // 0x0
+        0, 0x30, 0, 0, 0, 0, 0, 0, // 0x3000
+    ];
+
+    fn debug_info() -> Vec<u8> {
+        let mut entries = vec![1, 8, 0, 0, 0];
+        entries.push(2);
+        entries.extend_from_slice(&0x1000u64.to_le_bytes());
+        entries.extend_from_slice(&0x1010u64.to_le_bytes());
+        entries.push(3);
+        entries.extend_from_slice(&0x2000u64.to_le_bytes());
+        entries.extend_from_slice(&0x20u32.to_le_bytes());
+        entries.extend_from_slice(&[4, 1, 0x30]);
+        entries.extend_from_slice(&[4, 2, 0x30]);
+        entries.extend_from_slice(&[5, 0x40]);
+        entries.push(0);
+        let mut data = ((entries.len() + 8) as u32).to_le_bytes().to_vec();
+        data.extend_from_slice(&[5, 0, 1, 8, 0, 0, 0, 0]);
+        data.extend(entries);
+        data
+    }
+
+    #[test]
+    fn pc_ranges_are_read_in_any_form() {
+        let debug_info = debug_info();
+        let dwarf = read::Dwarf {
+            debug_abbrev: read::DebugAbbrev::new(DEBUG_ABBREV, LittleEndian),
+            debug_addr: read::DebugAddr::from(EndianSlice::new(DEBUG_ADDR, LittleEndian)),
+            debug_info: read::DebugInfo::new(&debug_info, LittleEndian),
+            ..Default::default()
+        };
+        let header = dwarf.units().next().unwrap().unwrap();
+        let unit = dwarf.unit(header).unwrap();
+        let mut entries = unit.entries();
+        entries.next_dfs().unwrap();
+        let mut ranges = Vec::new();
+        while let Some((_, entry)) = entries.next_dfs().unwrap() {
+            ranges.push(read_pc_range(&dwarf, &unit, entry));
+        }
+        assert_eq!(ranges.len(), 5);
+        assert_eq!(ranges[0], Ok(Some((0x1000, 0x1010))));
+        assert_eq!(ranges[1], Ok(Some((0x2000, 0x2020))));
+        assert_eq!(ranges[2], Ok(Some((0x3000, 0x3030))));
+        assert!(ranges[3].is_err());
+        assert_eq!(ranges[4], Ok(None));
+    }
+
+    #[test]
+    fn insn_maps_are_parsed() {
+        let insn_map = "=insn_pcs\n0: 0x10\n1: 0x7fffffff\n2: 0x1a\n=End\n3: 0x20\n";
+        let pcs: Vec<u64> = parse_insn_map(insn_map).unwrap().into_iter().collect();
+        assert_eq!(pcs, vec![0x10, 0x1a]);
+    }
+
+    #[test]
+    fn malformed_insn_maps_are_rejected() {
+        assert!(parse_insn_map("0: 0x10\n").is_err());
+        assert_eq!(
+            parse_insn_map("=insn_pcs\n0: 0x10\n1 0x14\n"),
+            Err(String::from(
+                "invalid insn map line 3: expected <index>: <pc>"
+            ))
+        );
+        assert!(parse_insn_map("=insn_pcs\n0: 0xzz\n").is_err());
+    }
+
+    #[test]
+    fn riscv_insns_are_read_from_the_address_of_text() {
+        /* An addi, then a compressed c.li */
+        let text = [0x13, 0x05, 0x10, 0x00, 0x05, 0x45];
+        assert_eq!(get_riscv_insn_size(&text, 0x10000, 0x10000), Some(4));
+        assert_eq!(get_riscv_insn_size(&text, 0x10000, 0x10004), Some(2));
+        assert_eq!(get_riscv_insn_size(&text, 0x10000, 0x10006), None);
+        assert_eq!(get_riscv_insn_size(&text, 0x10000, 0x4), None);
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::dwarf::{get_insn_texts, DwarfCoverage};
+
+/* Where a variable is available at a PC, if it is */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Availability {
+    Missing,
+    Constant,
+    Location,
+}
+
+/* The availability of each variable of a function at each PC of its instruction map, before
+ * and after the rewrite of its debug info */
+#[derive(Debug, Clone)]
+pub struct Heatmap {
+    pub function: String,
+    /* Sorted by name, the columns of the rows */
+    pub variables: Vec<String>,
+    pub rows: Vec<HeatmapRow>,
+}
+
+#[derive(Debug, Clone)]
+pub struct HeatmapRow {
+    pub pc: u64,
+    /* The instruction as disassembled, if it could be */
+    pub instruction: Option<String>,
+    /* The number of locations covering the PC, as counted by the evaluation */
+    pub before_count: i64,
+    pub after_count: i64,
+    pub before: Vec<Availability>,
+    pub after: Vec<Availability>,
+}
+
+impl Availability {
+    fn at(ranges: Option<&BTreeSet<(u64, u64, bool)>>, pc: u64) -> Self {
+        let mut availability = Availability::Missing;
+        for &(begin, end, is_const) in ranges.into_iter().flatten() {
+            if pc >= begin && pc < end {
+                if !is_const {
+                    return Availability::Location;
+                }
+                availability = Availability::Constant;
+            }
+        }
+        availability
+    }
+
+    /* The value of a cell of the CSV */
+    fn level(self) -> u8 {
+        match self {
+            Availability::Missing => 0,
+            Availability::Constant => 1,
+            Availability::Location => 2,
+        }
+    }
+}
+
+impl HeatmapRow {
+    /* Whether the variable at index is newly available, or no longer a constant */
+    fn gained(&self, index: usize) -> bool {
+        let (before, after) = (self.before[index], self.after[index]);
+        (before != Availability::Location && after == Availability::Location)
+            || (before == Availability::Missing && after == Availability::Constant)
+    }
+}
+
+impl Heatmap {
+    /* A row per PC and two columns per variable, 0 where it is not available, 1 where it is a
+     * constant and 2 where it is in a location */
+    pub fn to_csv(&self) -> String {
+        let mut text = String::from("pc,before_count,after_count");
+        for variable in &self.variables {
+            text += &format!(",{}:before,{}:after", variable, variable);
+        }
+        text.push('\n');
+        for row in &self.rows {
+            text += &format!("0x{:x},{},{}", row.pc, row.before_count, row.after_count);
+            for (before, after) in row.before.iter().zip(&row.after) {
+                text += &format!(",{},{}", before.level(), after.level());
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /* The disassembly of the function, each instruction followed by the variables available
+     * there before and after, and those it gained */
+    pub fn to_listing(&self) -> String {
+        let mut text = format!(
+            "{}: variables before -> after (* for a constant), + for those gained\n",
+            self.function
+        );
+        for row in &self.rows {
+            let names = |availability: &[Availability]| {
+                let names: Vec<String> = self
+                    .variables
+                    .iter()
+                    .zip(availability)
+                    .filter_map(|(name, availability)| match availability {
+                        Availability::Missing => None,
+                        Availability::Constant => Some(format!("{}*", name)),
+                        Availability::Location => Some(name.clone()),
+                    })
+                    .collect();
+                if names.is_empty() {
+                    String::from("-")
+                } else {
+                    names.join(" ")
+                }
+            };
+            let gained: Vec<&str> = (0..self.variables.len())
+                .filter(|&index| row.gained(index))
+                .map(|index| self.variables[index].as_str())
+                .collect();
+            text += &format!(
+                "{:>8x}:  {:<40} {} -> {}",
+                row.pc,
+                row.instruction.as_deref().unwrap_or("?"),
+                names(&row.before),
+                names(&row.after)
+            );
+            if !gained.is_empty() {
+                text += &format!("  +{}", gained.join(" +"));
+            }
+            text.push('\n');
+        }
+        text
+    }
+}
+
+/* The heatmap of func_name over the PCs of its instruction map from the DWARF of the objects
+ * before and after the rewrite, as read by read_dwarf, the instructions being disassembled from
+ * the object after the rewrite, whose code is the same */
+pub fn heatmap(
+    after_obj: &object::File<'_>,
+    func_name: &str,
+    before: &DwarfCoverage,
+    after: &DwarfCoverage,
+) -> Result<Heatmap, String> {
+    let (before_map, _, _, before_counts) = before;
+    let (after_map, insn_set, _, after_counts) = after;
+    let mut insn_texts: HashMap<u64, String> = get_insn_texts(after_obj, insn_set)?;
+
+    let names: BTreeSet<&String> = before_map.keys().chain(after_map.keys()).collect();
+    let variables: Vec<String> = names.into_iter().cloned().collect();
+    let rows = insn_set
+        .iter()
+        .map(|&pc| HeatmapRow {
+            pc,
+            instruction: insn_texts.remove(&pc),
+            before_count: before_counts.get(&pc).copied().unwrap_or(0),
+            after_count: after_counts.get(&pc).copied().unwrap_or(0),
+            before: variables
+                .iter()
+                .map(|name| Availability::at(before_map.get(name), pc))
+                .collect(),
+            after: variables
+                .iter()
+                .map(|name| Availability::at(after_map.get(name), pc))
+                .collect(),
+        })
+        .collect();
+    Ok(Heatmap {
+        function: func_name.to_string(),
+        variables,
+        rows,
+    })
+}
@@ -0,0 +1,438 @@
+use std::cmp;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::ops::Bound::{Excluded, Included};
+use std::str::FromStr;
+
+use serde::Serialize;
+
+mod dwarf;
+mod heatmap;
+mod suite;
+pub use dwarf::{read_dwarf, DwarfCoverage};
+pub use heatmap::{heatmap, Availability, Heatmap, HeatmapRow};
+pub use suite::{evaluate_suite, find_pairs, SuiteEvaluation, SuitePair, SuiteRow, SuiteSummary};
+
+/* The coverage of the variables of a function over the PCs of its instruction map, before and
+ * after the rewrite of its debug info */
+#[derive(Debug, Clone, Serialize)]
+pub struct Evaluation {
+    pub function: String,
+    /* The PCs of the instruction map */
+    pub total_pcs: usize,
+    /* The PCs where some variable was improved (from a constant) or newly covered, and those
+     * variables */
+    pub improved_pcs: usize,
+    pub improved_vars: usize,
+    /* The PC-variable pairs covered before, and how many more are covered after */
+    pub before_count: i64,
+    pub count_increase: i64,
+    /* The improved or newly covered PC-variable pairs the increase does not account for */
+    pub other_pairs: i64,
+    #[serde(skip)]
+    pub variables: Vec<VariableEvaluation>,
+}
+
+/* An evaluation as written in JSON, with its variables if they are asked for */
+#[derive(Serialize)]
+struct EvaluationJson<'a> {
+    #[serde(flatten)]
+    evaluation: &'a Evaluation,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    variables: Option<&'a [VariableEvaluation]>,
+}
+
+/* The PCs of the instruction map where a variable is available before and after the rewrite */
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct VariableEvaluation {
+    pub name: String,
+    pub before_pcs: usize,
+    pub after_pcs: usize,
+    /* The PCs where it was a constant before and is not after */
+    pub improved_pcs: usize,
+    /* The PCs where it was not available before */
+    pub new_pcs: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Csv,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, String> {
+        match format {
+            "text" => Ok(OutputFormat::Text),
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("unknown output format '{}'", format)),
+        }
+    }
+}
+
+impl fmt::Display for Evaluation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}, {}", self.function, self.columns())
+    }
+}
+
+/* The names of the columns of an evaluation in CSV, after that of the function */
+const CSV_COLUMNS: &[&str] = &[
+    "total_pcs",
+    "improved_pcs",
+    "improved_vars",
+    "before_count",
+    "count_increase",
+    "other_pairs",
+];
+
+impl Evaluation {
+    /* The columns of tables 2 and 3 of the paper: T/U, V, O/M/I */
+    pub(crate) fn columns(&self) -> String {
+        format!(
+            "{}/{}, {}, {}/{}/{}",
+            self.total_pcs,
+            self.improved_pcs,
+            self.improved_vars,
+            self.before_count,
+            self.count_increase,
+            self.other_pairs
+        )
+    }
+
+    pub(crate) fn csv_columns(&self) -> String {
+        format!(
+            "{},{},{},{},{},{}",
+            self.total_pcs,
+            self.improved_pcs,
+            self.improved_vars,
+            self.before_count,
+            self.count_increase,
+            self.other_pairs
+        )
+    }
+
+    /* The evaluation in the format given, with a line per variable if variables is set. In CSV,
+     * the lines of the variables replace that of the function, the columns being different. */
+    pub fn format(&self, format: OutputFormat, variables: bool) -> String {
+        let mut text = String::new();
+        match format {
+            OutputFormat::Text => {
+                text += &format!("{}\n", self);
+                if variables {
+                    text += &format!(
+                        "{:<16} {:>8} {:>8} {:>8} {:>8}\n",
+                        "variable", "before", "after", "improved", "new"
+                    );
+                    for variable in &self.variables {
+                        text += &format!(
+                            "{:<16} {:>8} {:>8} {:>8} {:>8}\n",
+                            variable.name,
+                            variable.before_pcs,
+                            variable.after_pcs,
+                            variable.improved_pcs,
+                            variable.new_pcs
+                        );
+                    }
+                }
+            }
+            OutputFormat::Csv if variables => {
+                text += "function,variable,before_pcs,after_pcs,improved_pcs,new_pcs\n";
+                for variable in &self.variables {
+                    text += &format!(
+                        "{},{},{},{},{},{}\n",
+                        self.function,
+                        variable.name,
+                        variable.before_pcs,
+                        variable.after_pcs,
+                        variable.improved_pcs,
+                        variable.new_pcs
+                    );
+                }
+            }
+            OutputFormat::Csv => {
+                text += &format!("function,{}\n", CSV_COLUMNS.join(","));
+                text += &format!("{},{}\n", self.function, self.csv_columns());
+            }
+            OutputFormat::Json => {
+                let json = EvaluationJson {
+                    evaluation: self,
+                    variables: if variables {
+                        Some(&self.variables)
+                    } else {
+                        None
+                    },
+                };
+                text +=
+                    &serde_json::to_string_pretty(&json).expect("an evaluation is serializable");
+                text.push('\n');
+            }
+        }
+        text
+    }
+}
+
+/* Compares the debug info of func_name in the objects before and after the rewrite. Fails if
+ * the insn map or the DWARF cannot be read, or func_name is not defined in the objects. */
+pub fn evaluate(
+    before_obj: &object::File<'_>,
+    after_obj: &object::File<'_>,
+    func_name: &str,
+    insn_map: &str,
+) -> Result<Evaluation, String> {
+    let before = read_dwarf(before_obj, func_name, insn_map)?;
+    let after = read_dwarf(after_obj, func_name, insn_map)?;
+    Ok(evaluate_coverage(func_name, &before, &after))
+}
+
+/* The evaluation of func_name from the DWARF of the objects before and after the rewrite, as
+ * read by read_dwarf */
+pub fn evaluate_coverage(
+    func_name: &str,
+    before: &DwarfCoverage,
+    after: &DwarfCoverage,
+) -> Evaluation {
+    let (before_results_map, _, before_actual_count, _) = before;
+    let (after_results_map, insns_set, after_actual_count, _) = after;
+    let (before_actual_count, after_actual_count) = (*before_actual_count, *after_actual_count);
+
+    let mut improv_or_missing_pcs = BTreeSet::new(); // unique PCs count considering both Improved and Missing debug info updates
+    let mut improv_or_missing_vars = BTreeSet::new(); // variables having either Improved or Missing debug info update
+    let mut improv_or_missing_pc_var_pairs: i64 = 0; // no of pc-var pairs (cumulative counting of PCs considering both Improved and Missing updates)
+
+    for (var_name, before_var_info) in before_results_map {
+        let mut count_var = false;
+        let after_var_info = match after_results_map.get(var_name) {
+            Some(after_var_info) => after_var_info,
+            None => continue,
+        };
+        for insn in insns_set {
+            let mut const_at_src = false;
+            for (begin, end, is_const) in before_var_info {
+                if insn >= begin && insn < end && *is_const {
+                    const_at_src = true;
+                    break;
+                }
+            }
+            if const_at_src {
+                for (begin, end, is_const) in after_var_info {
+                    if insn >= begin && insn < end && !*is_const {
+                        improv_or_missing_pcs.insert(insn);
+                        improv_or_missing_pc_var_pairs += 1;
+                        count_var = true;
+                        break;
+                    }
+                }
+            }
+        }
+        if count_var {
+            improv_or_missing_vars.insert(var_name);
+        }
+    }
+    for (var_name, var_info) in after_results_map {
+        let mut count_var = false;
+        match before_results_map.get(var_name) {
+            None => {
+                for insn in insns_set {
+                    for (begin, end, _is_const) in var_info {
+                        if insn >= begin && insn < end {
+                            improv_or_missing_pcs.insert(insn);
+                            improv_or_missing_pc_var_pairs += 1;
+                            count_var = true;
+                            break;
+                        }
+                    }
+                }
+            }
+            Some(before_var_info) => {
+                for (begin, end, _) in var_info {
+                    for insn in insns_set.range((Included(begin), Excluded(end))) {
+                        let mut found = false;
+                        for (before_begin, before_end, _) in before_var_info {
+                            if insn >= before_begin && insn < before_end {
+                                found = true;
+                                break;
+                            }
+                        }
+                        if !found {
+                            improv_or_missing_pcs.insert(insn);
+                            improv_or_missing_pc_var_pairs += 1;
+                            count_var = true;
+                        }
+                    }
+                }
+            }
+        }
+        if count_var {
+            improv_or_missing_vars.insert(var_name);
+        }
+    }
+    let empty = BTreeSet::new();
+    let names: BTreeSet<&String> = before_results_map
+        .keys()
+        .chain(after_results_map.keys())
+        .collect();
+    let variables = names
+        .into_iter()
+        .map(|name| {
+            let before = before_results_map.get(name).unwrap_or(&empty);
+            let after = after_results_map.get(name).unwrap_or(&empty);
+            let mut variable = VariableEvaluation {
+                name: name.clone(),
+                ..VariableEvaluation::default()
+            };
+            for &pc in insns_set {
+                let (covered_before, covered_after) = (covers(before, pc), covers(after, pc));
+                variable.before_pcs += covered_before as usize;
+                variable.after_pcs += covered_after as usize;
+                if !covered_before && covered_after {
+                    variable.new_pcs += 1;
+                }
+                let constant_before = before
+                    .iter()
+                    .any(|&(begin, end, is_const)| is_const && pc >= begin && pc < end);
+                let variable_after = after
+                    .iter()
+                    .any(|&(begin, end, is_const)| !is_const && pc >= begin && pc < end);
+                if constant_before && variable_after {
+                    variable.improved_pcs += 1;
+                }
+            }
+            variable
+        })
+        .collect();
+    Evaluation {
+        function: func_name.to_string(),
+        total_pcs: insns_set.len(),
+        improved_pcs: improv_or_missing_pcs.len(),
+        improved_vars: improv_or_missing_vars.len(),
+        before_count: before_actual_count,
+        count_increase: after_actual_count - before_actual_count,
+        other_pairs: cmp::max(0, improv_or_missing_pc_var_pairs - (cmp::max(0, after_actual_count - before_actual_count))),
+        variables,
+    }
+}
+
+fn covers(ranges: &BTreeSet<(u64, u64, bool)>, pc: u64) -> bool {
+    ranges
+        .iter()
+        .any(|&(begin, end, _)| pc >= begin && pc < end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluation() -> Evaluation {
+        Evaluation {
+            function: String::from("s000"),
+            total_pcs: 15,
+            improved_pcs: 7,
+            improved_vars: 1,
+            before_count: 15,
+            count_increase: 7,
+            other_pairs: 0,
+            variables: vec![
+                VariableEvaluation {
+                    name: String::from("a"),
+                    before_pcs: 3,
+                    after_pcs: 10,
+                    improved_pcs: 2,
+                    new_pcs: 7,
+                },
+                VariableEvaluation {
+                    name: String::from("i"),
+                    before_pcs: 15,
+                    after_pcs: 15,
+                    improved_pcs: 0,
+                    new_pcs: 0,
+                },
+            ],
+        }
+    }
+
+    fn keys(value: &serde_json::Value) -> Vec<&str> {
+        let mut keys: Vec<&str> = value
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        keys.sort_unstable();
+        keys
+    }
+
+    #[test]
+    fn text_has_the_columns_of_the_tables() {
+        let evaluation = evaluation();
+        assert_eq!(
+            evaluation.format(OutputFormat::Text, false),
+            "s000, 15/7, 1, 15/7/0\n"
+        );
+        assert_eq!(
+            evaluation.format(OutputFormat::Text, true),
+            "s000, 15/7, 1, 15/7/0\n\
+             variable           before    after improved      new\n\
+             a                       3       10        2        7\n\
+             i                      15       15        0        0\n"
+        );
+    }
+
+    #[test]
+    fn csv_has_a_header_and_a_row() {
+        assert_eq!(
+            evaluation().format(OutputFormat::Csv, false),
+            "function,total_pcs,improved_pcs,improved_vars,before_count,count_increase,\
+             other_pairs\n\
+             s000,15,7,1,15,7,0\n"
+        );
+    }
+
+    #[test]
+    fn csv_has_a_row_per_variable() {
+        assert_eq!(
+            evaluation().format(OutputFormat::Csv, true),
+            "function,variable,before_pcs,after_pcs,improved_pcs,new_pcs\n\
+             s000,a,3,10,2,7\n\
+             s000,i,15,15,0,0\n"
+        );
+    }
+
+    #[test]
+    fn json_has_the_fields_of_the_evaluation() {
+        let evaluation = evaluation();
+        let fields = [
+            "before_count",
+            "count_increase",
+            "function",
+            "improved_pcs",
+            "improved_vars",
+            "other_pairs",
+            "total_pcs",
+        ];
+
+        let json = evaluation.format(OutputFormat::Json, false);
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(keys(&value), fields);
+        assert_eq!(value["function"], "s000");
+        assert_eq!(value["count_increase"], 7);
+
+        let json = evaluation.format(OutputFormat::Json, true);
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let mut with_variables = fields.to_vec();
+        with_variables.push("variables");
+        assert_eq!(keys(&value), with_variables);
+        let variables = value["variables"].as_array().unwrap();
+        assert_eq!(variables.len(), 2);
+        assert_eq!(
+            keys(&variables[0]),
+            ["after_pcs", "before_pcs", "improved_pcs", "name", "new_pcs"]
+        );
+        assert_eq!(variables[0]["name"], "a");
+        assert_eq!(variables[0]["new_pcs"], 7);
+    }
+}
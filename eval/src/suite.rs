@@ -0,0 +1,298 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use log::warn;
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::{evaluate, Evaluation, OutputFormat, CSV_COLUMNS};
+
+/* The objects of a function built with a compiler, named as in the rewrites directories: the
+ * instruction map <function>.<compiler>.insn.map, the object after the rewrite
+ * <function>.<compiler>-rewrite.o next to it, and the object before <function>.<compiler>.o */
+#[derive(Debug, Clone)]
+pub struct SuitePair {
+    pub function: String,
+    pub compiler: String,
+    pub before: PathBuf,
+    pub after: PathBuf,
+    pub insn_map: PathBuf,
+}
+
+/* The evaluations of the functions of a benchmark suite, a column per compiler as in tables 2
+ * and 3 of the paper, sorted by name. The means are geometric means shifted by one,
+ * exp(mean(ln(1 + x))) - 1, so that the counts which are 0 can be part of them. */
+#[derive(Debug, Clone, Serialize)]
+pub struct SuiteEvaluation {
+    pub compilers: Vec<String>,
+    pub functions: Vec<SuiteRow>,
+    pub totals: Vec<SuiteSummary>,
+    pub shifted_geometric_means: Vec<SuiteSummary>,
+}
+
+/* The evaluation of a function for each compiler, None where there is none */
+#[derive(Debug, Clone, Serialize)]
+pub struct SuiteRow {
+    pub function: String,
+    pub evaluations: Vec<Option<Evaluation>>,
+}
+
+/* The totals or shifted geometric means of the counts of the functions evaluated for a compiler */
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SuiteSummary {
+    pub compiler: String,
+    pub functions: usize,
+    pub total_pcs: f64,
+    pub improved_pcs: f64,
+    pub improved_vars: f64,
+    pub before_count: f64,
+    pub count_increase: f64,
+    pub other_pairs: f64,
+}
+
+/* The pairs of the instruction maps of dir whose objects exist, looking for the objects before
+ * in before_dir */
+pub fn find_pairs(dir: &Path, before_dir: &Path) -> io::Result<Vec<SuitePair>> {
+    let mut pairs = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let file_name = entry?.file_name();
+        let stem = match file_name
+            .to_str()
+            .and_then(|name| name.strip_suffix(".insn.map"))
+        {
+            Some(stem) => stem,
+            None => continue,
+        };
+        let (function, compiler) = match stem.find('.') {
+            Some(index) => (&stem[..index], &stem[index + 1..]),
+            None => continue,
+        };
+        let pair = SuitePair {
+            function: function.to_string(),
+            compiler: compiler.to_string(),
+            before: before_dir.join(format!("{}.o", stem)),
+            after: dir.join(format!("{}-rewrite.o", stem)),
+            insn_map: dir.join(&file_name),
+        };
+        if !pair.before.is_file() || !pair.after.is_file() {
+            warn!(
+                target: "eval::suite",
+                "{} or {} is missing, skipping {}",
+                pair.before.display(),
+                pair.after.display(),
+                stem
+            );
+            continue;
+        }
+        pairs.push(pair);
+    }
+    pairs.sort_by(|a, b| (&a.function, &a.compiler).cmp(&(&b.function, &b.compiler)));
+    Ok(pairs)
+}
+
+/* Evaluates the pairs in parallel. Pairs which cannot be evaluated are left out. */
+pub fn evaluate_suite(pairs: &[SuitePair]) -> SuiteEvaluation {
+    let evaluations: Vec<(&SuitePair, Evaluation)> = pairs
+        .par_iter()
+        .filter_map(|pair| match evaluate_pair(pair) {
+            Ok(evaluation) => Some((pair, evaluation)),
+            Err(err) => {
+                warn!(
+                    target: "eval::suite",
+                    "Failed to evaluate {}: {}",
+                    pair.after.display(),
+                    err
+                );
+                None
+            }
+        })
+        .collect();
+
+    let compilers: Vec<String> = evaluations
+        .iter()
+        .map(|(pair, _)| pair.compiler.clone())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    let mut rows: BTreeMap<&str, Vec<Option<Evaluation>>> = BTreeMap::new();
+    for (pair, evaluation) in evaluations {
+        let column = compilers.binary_search(&pair.compiler).unwrap();
+        rows.entry(&pair.function)
+            .or_insert_with(|| vec![None; compilers.len()])[column] = Some(evaluation);
+    }
+    let functions: Vec<SuiteRow> = rows
+        .into_iter()
+        .map(|(function, evaluations)| SuiteRow {
+            function: function.to_string(),
+            evaluations,
+        })
+        .collect();
+
+    let mut totals = Vec::new();
+    let mut shifted_geometric_means = Vec::new();
+    for (column, compiler) in compilers.iter().enumerate() {
+        let column: Vec<&Evaluation> = functions
+            .iter()
+            .filter_map(|row| row.evaluations[column].as_ref())
+            .collect();
+        totals.push(SuiteSummary::new(compiler, &column, |values| {
+            values.iter().sum()
+        }));
+        shifted_geometric_means.push(SuiteSummary::new(compiler, &column, shifted_geometric_mean));
+    }
+    SuiteEvaluation {
+        compilers,
+        functions,
+        totals,
+        shifted_geometric_means,
+    }
+}
+
+fn evaluate_pair(pair: &SuitePair) -> Result<Evaluation, String> {
+    let insn_map = fs::read_to_string(&pair.insn_map).map_err(|err| err.to_string())?;
+    let before_file = fs::read(&pair.before).map_err(|err| err.to_string())?;
+    let after_file = fs::read(&pair.after).map_err(|err| err.to_string())?;
+    let before_obj = object::File::parse(&*before_file).map_err(|err| err.to_string())?;
+    let after_obj = object::File::parse(&*after_file).map_err(|err| err.to_string())?;
+    evaluate(&before_obj, &after_obj, &pair.function, &insn_map)
+}
+
+/* Shifted by one for the counts which are 0, negative ones (a count which decreased) counting
+ * as 0 */
+fn shifted_geometric_mean(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    let sum: f64 = values.iter().map(|value| value.max(0.0).ln_1p()).sum();
+    (sum / values.len() as f64).exp_m1()
+}
+
+impl SuiteSummary {
+    fn new(compiler: &str, evaluations: &[&Evaluation], reduce: impl Fn(&[f64]) -> f64) -> Self {
+        let field = |get: fn(&Evaluation) -> f64| {
+            let values: Vec<f64> = evaluations.iter().copied().map(get).collect();
+            reduce(&values)
+        };
+        SuiteSummary {
+            compiler: compiler.to_string(),
+            functions: evaluations.len(),
+            total_pcs: field(|evaluation| evaluation.total_pcs as f64),
+            improved_pcs: field(|evaluation| evaluation.improved_pcs as f64),
+            improved_vars: field(|evaluation| evaluation.improved_vars as f64),
+            before_count: field(|evaluation| evaluation.before_count as f64),
+            count_increase: field(|evaluation| evaluation.count_increase as f64),
+            other_pairs: field(|evaluation| evaluation.other_pairs as f64),
+        }
+    }
+
+    /* The columns of the summary in text, written like those of an evaluation */
+    fn to_text(&self, precision: usize) -> String {
+        format!(
+            "{:.*}/{:.*}, {:.*}, {:.*}/{:.*}/{:.*}",
+            precision,
+            self.total_pcs,
+            precision,
+            self.improved_pcs,
+            precision,
+            self.improved_vars,
+            precision,
+            self.before_count,
+            precision,
+            self.count_increase,
+            precision,
+            self.other_pairs
+        )
+    }
+
+    fn to_csv(&self, precision: usize) -> String {
+        format!(
+            "{:.*},{:.*},{:.*},{:.*},{:.*},{:.*}",
+            precision,
+            self.total_pcs,
+            precision,
+            self.improved_pcs,
+            precision,
+            self.improved_vars,
+            precision,
+            self.before_count,
+            precision,
+            self.count_increase,
+            precision,
+            self.other_pairs
+        )
+    }
+}
+
+impl SuiteEvaluation {
+    /* In text, a line per function with the columns of the tables (T/U, V, O/M/I) for each
+     * compiler, "-" where a compiler has no evaluation, then the totals and shifted geometric means */
+    pub fn format(&self, format: OutputFormat) -> String {
+        let mut text = String::new();
+        match format {
+            OutputFormat::Text => {
+                text += "Fn";
+                for compiler in &self.compilers {
+                    text += &format!(", {0} T/U, {0} V, {0} O/M/I", compiler);
+                }
+                text.push('\n');
+                for row in &self.functions {
+                    text += &row.function;
+                    for evaluation in &row.evaluations {
+                        match evaluation {
+                            Some(evaluation) => text += &format!(", {}", evaluation.columns()),
+                            None => text += ", -, -, -",
+                        }
+                    }
+                    text.push('\n');
+                }
+                let summaries = [
+                    ("Total", &self.totals, 0),
+                    ("Geomean+1", &self.shifted_geometric_means, 2),
+                ];
+                for (name, summaries, precision) in summaries.iter() {
+                    text += name;
+                    for summary in summaries.iter() {
+                        text += &format!(", {}", summary.to_text(*precision));
+                    }
+                    text.push('\n');
+                }
+            }
+            OutputFormat::Csv => {
+                text += "function";
+                for compiler in &self.compilers {
+                    for column in CSV_COLUMNS {
+                        text += &format!(",{}_{}", compiler, column);
+                    }
+                }
+                text.push('\n');
+                for row in &self.functions {
+                    text += &row.function;
+                    for evaluation in &row.evaluations {
+                        match evaluation {
+                            Some(evaluation) => text += &format!(",{}", evaluation.csv_columns()),
+                            None => text += ",,,,,,",
+                        }
+                    }
+                    text.push('\n');
+                }
+                let summaries = [
+                    ("total", &self.totals, 0),
+                    ("geomean_plus_1", &self.shifted_geometric_means, 2),
+                ];
+                for (name, summaries, precision) in summaries.iter() {
+                    text += name;
+                    for summary in summaries.iter() {
+                        text += &format!(",{}", summary.to_csv(*precision));
+                    }
+                    text.push('\n');
+                }
+            }
+            OutputFormat::Json => {
+                text += &serde_json::to_string_pretty(self).expect("an evaluation is serializable");
+                text.push('\n');
+            }
+        }
+        text
+    }
+}
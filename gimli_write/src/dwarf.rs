@@ -1,46 +1,99 @@
 use std::borrow::Cow;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use gimli::constants::{
-    DW_AT_const_value, DW_AT_high_pc, DW_AT_location, DW_AT_low_pc, DW_OP_div, DW_OP_minus,
-    DW_OP_mod, DW_OP_mul, DW_OP_plus, DW_OP_stack_value,
+    DW_AT_const_value, DW_AT_frame_base, DW_AT_high_pc, DW_AT_location, DW_AT_low_pc,
+    DW_OP_stack_value,
 };
 use gimli::read::EndianSlice;
 use gimli::read::Reader;
 use gimli::write::{
     Address, Attribute, AttributeValue, DebuggingInformationEntry, EndianVec, StringTable, Unit,
-    UnitEntryId,
+    UnitEntryId, UnitId,
 };
 use gimli::{self, read, write, LittleEndian};
+use log::{debug, info, trace, warn};
 use object::write as object_write;
-use object::{self, Object, ObjectSection, SymbolIndex};
-use std::convert::TryInto;
-use std::io::Read;
+use object::{self, Architecture, Object, ObjectSection, ObjectSymbol, SymbolIndex};
+use std::convert::TryFrom;
 use std::str;
 
+use crate::dwarf_version::{
+    add_addr_bases, check_dwarf_version, find_pinned_units, index_base_addresses, upgrade_units,
+};
+use crate::error::RewriteError;
+use crate::location_map::{Bound, LocationMap, Override};
+use crate::pieces::{
+    compose, get_var_bit_size, locate_members, split_members, Dropped, ExistingLocation, Piece,
+    PieceOverride,
+};
+use crate::postfix::PostfixExpression;
+use crate::report::{
+    format_expression, LocationReport, MappingReport, MappingStatus, RewriteReport, VariableReport,
+};
+use crate::{Mapping, VariableRef};
+
 /* See if using write::Address::Constant(addr) is correct and if we can use this for relocatable
  * addresses! */
 /* See if low_pc + high_pc or just high_pc, wherever it is used, whether it is correct or not */
 
-#[derive(Clone)]
-struct Location<R: Reader> {
-    expression: read::Expression<R>,
-    begin: u64,
-    end: u64,
+/* A variable of a function being rewritten and its existing locations, which its mappings are
+ * spliced into. The locations are converted when read, but a conversion error is only reported if
+ * the variable is rewritten. */
+struct VarLocation {
+    name: Option<String>,
+    /* The .debug_info offset of its entry */
+    offset: u64,
+    locations: write::ConvertResult<LocationMap>,
+    /* The existing locations, to take the parts of the variable no mapping sets from */
+    existing: Vec<ExistingLocation>,
+    /* The existing locations as reported, with absolute PCs */
+    before: Vec<LocationReport>,
+    /* The PC range [begin, end) of the innermost entry around the variable which has one */
+    scope: Option<(u64, u64)>,
+    /* Whether the variable had a DW_AT_const_value, to be removed once it has a location */
+    const_value: bool,
+    /* Whether the innermost scope around the variable has no PC at all (an empty DW_AT_ranges) */
+    empty_scope: bool,
 }
-#[derive(Clone)]
-struct WriteLocation {
-    expression: write::Expression,
-    begin: u64,
-    end: u64,
+
+/* The variables of a function being rewritten, keyed by the offsets of their entries when read
+ * and by the ids of their entries once converted for writing. entry is the function itself. */
+struct FunctionVars<K> {
+    start: u64,
+    unit_base: Option<u64>,
+    unit_index: usize,
+    unit_offset: gimli::DebugInfoOffset,
+    entry: K,
+    vars: HashMap<K, VarLocation>,
+}
+
+impl FunctionVars<read::UnitOffset> {
+    fn into_entry_ids(
+        self,
+        entry_ids: &HashMap<read::UnitOffset, UnitEntryId>,
+    ) -> FunctionVars<UnitEntryId> {
+        FunctionVars {
+            start: self.start,
+            unit_base: self.unit_base,
+            unit_index: self.unit_index,
+            unit_offset: self.unit_offset,
+            entry: entry_ids[&self.entry],
+            vars: self
+                .vars
+                .into_iter()
+                .map(|(offset, var)| (entry_ids[&offset], var))
+                .collect(),
+        }
+    }
 }
-#[derive(Clone)]
-enum LocationInfo<R: Reader> {
-    LocList(Vec<read::LocationListEntry<R>>),
-    Loc(Location<R>),
-    WLoc(WriteLocation),
-    IntLocList(Vec<write::Location>), /*Intermediate Location List*/
+
+/* An entry with a PC range or DW_AT_ranges, which the entries below it are in */
+struct Scope {
+    depth: isize,
+    pc_range: Option<PcRange>,
+    ranges: Vec<(u64, u64)>,
 }
 
 fn get_die<'a>(unit: &'a mut Unit, entity_id: &UnitEntryId) -> &'a mut DebuggingInformationEntry {
@@ -70,33 +123,12 @@ fn get_var(
     no_of_vars: u8,
 ) -> (Option<UnitEntryId>, Option<UnitEntryId>) {
     let depth = 0;
-    let mut low_pc = None;
-    let mut high_pc = None;
-    {
-        let func_die = unit.get(*func_id);
-        let attr_val = func_die.get(DW_AT_low_pc);
-        if let Some(write::AttributeValue::Address(addr)) = attr_val {
-            let addr = get_addr(*addr);
-            low_pc = Some(addr);
-        } else {
-            eprintln!("function's low_pc attribute unexpected! -- not handled!");
-        }
-        let attr_val = func_die.get(DW_AT_high_pc);
-        if let Some(write::AttributeValue::Udata(offset)) = attr_val {
-            high_pc = Some(*offset);
-        } else {
-            eprintln!("function's high_pc attribute unexpected! -- not handled!");
-        }
+    let pc_range = get_pc_range(unit.get(*func_id));
+    if pc_range.is_none() {
+        warn!(target: "rewrite::lookup", "function has no PC range!");
     }
     let (var_id, parent_id, _depth) = get_var_depth(
-        unit,
-        func_id,
-        strings,
-        var_name,
-        depth,
-        expr_rng,
-        (low_pc, high_pc),
-        no_of_vars,
+        unit, func_id, strings, var_name, depth, expr_rng, pc_range, no_of_vars,
     );
     (var_id, parent_id)
 }
@@ -108,7 +140,7 @@ fn get_var_depth(
     var_name: &str,
     depth: i64,
     expr_rng: (u64, u64),
-    curr_rng: (Option<u64>, Option<u64>),
+    curr_rng: Option<(u64, u64)>,
     no_of_vars: u8,
 ) -> (Option<UnitEntryId>, Option<UnitEntryId>, i64) {
     let lex_blk_die = unit.get(*func_id);
@@ -116,101 +148,83 @@ fn get_var_depth(
     for child in lex_blk_die.children() {
         let child_die = unit.get(*child);
 
-        let mut low_pc = curr_rng.0;
-        let mut high_pc = curr_rng.1;
-
-        let attr_val = child_die.get(DW_AT_low_pc);
-        if let Some(write::AttributeValue::Address(addr)) = attr_val {
-            let addr = get_addr(*addr);
-            low_pc = Some(addr);
-            println!("got a new low_pc : {}", addr);
-        } else {
-            println!("low_pc: Unexpected AttributeValue type! {:?}", attr_val);
-        }
-        let attr_val = child_die.get(DW_AT_high_pc);
-        if let Some(write::AttributeValue::Udata(offset)) = attr_val {
-            high_pc = Some(*offset);
-            println!("got a new high_pc : {}", offset);
-        } else {
-            println!("high_pc: Unexpected AttributeValue type! {:?}", attr_val);
+        let mut pc_range = curr_rng;
+        if let Some((low_pc, high_pc)) = get_pc_range(child_die) {
+            pc_range = Some((low_pc, high_pc));
+            trace!(target: "rewrite::lookup", "got a new PC range : {:x}->{:x}", low_pc, high_pc);
         }
 
         let s = child_die.tag().static_string().unwrap();
         if s == "DW_TAG_variable" || s == "DW_TAG_formal_parameter" {
-            let attr_val = child_die.get(gimli::constants::DW_AT_name).unwrap();
-            println!("attr_val = {:?}", attr_val);
+            let attr_val = match child_die.get(gimli::constants::DW_AT_name) {
+                Some(attr_val) => attr_val,
+                /* unnamed (e.g. artificial) variables can't be named by an expression file */
+                None => continue,
+            };
+            trace!(target: "rewrite::lookup", "attr_val = {:?}", attr_val);
             if let AttributeValue::StringRef(string_id) = attr_val {
-                println!(
+                trace!(target: "rewrite::lookup",
                     "string {} {}",
                     str::from_utf8(strings.get(*string_id)).unwrap(),
                     var_name
                 );
                 //let tmpstr: &str = str::from_utf8(strings.get(*string_id)).unwrap();
-                //println!("length {} {}", String::from(tmpstr).len(), String::from(var_name.trim()).len());
                 if str::from_utf8(strings.get(*string_id)).unwrap() == var_name.trim() {
-                    println!("matched variable name -- strings section");
+                    debug!(target: "rewrite::lookup", "matched variable name -- strings section");
                     if no_of_vars == 1 {
-                        if curr_rng.0 != None && curr_rng.1 != None {
-                            if !(curr_rng.0.unwrap() >= expr_rng.1
-                                || (curr_rng.0.unwrap() + curr_rng.1.unwrap()) <= expr_rng.0)
-                            {
-                                eprintln!("curr_rng_low: {}, curr_rng_low+high: {}, expr_rng_low: {}, expr_rng_high: {}", curr_rng.0.unwrap(), curr_rng.0.unwrap() + curr_rng.1.unwrap(), expr_rng.0, expr_rng.1);
+                        if let Some((low_pc, high_pc)) = curr_rng {
+                            if !(low_pc >= expr_rng.1 || high_pc <= expr_rng.0) {
+                                trace!(target: "rewrite::lookup", "curr_rng_low: {}, curr_rng_high: {}, expr_rng_low: {}, expr_rng_high: {}", low_pc, high_pc, expr_rng.0, expr_rng.1);
                                 var_id_depth = (Some(*child), Some(*func_id), depth + 1);
                                 return var_id_depth;
                             }
                         }
                     } else {
-                        if curr_rng.0 != None && curr_rng.1 != None {
-                            if curr_rng.0.unwrap() <= expr_rng.0
-                                && (curr_rng.0.unwrap() + curr_rng.1.unwrap()) >= expr_rng.1
-                            {
+                        if let Some((low_pc, high_pc)) = curr_rng {
+                            if low_pc <= expr_rng.0 && high_pc >= expr_rng.1 {
                                 if var_id_depth.2 == -1 || depth + 1 > var_id_depth.2 {
                                     var_id_depth = (Some(*child), Some(*func_id), depth + 1);
                                 } else {
-                                    println!("depth outside!");
+                                    debug!(target: "rewrite::lookup", "depth outside!");
                                 }
                             } else {
-                                println!("range outside!");
+                                debug!(target: "rewrite::lookup", "range outside!");
                             }
                         } else {
                             /*if var_id_depth.1 == -1 || depth + 1 > var_id_depth.1 {
                                 var_id_depth = (Some(*child), depth + 1);
                             }
                             else {
-                                println!("depth outside!");
+                                debug!(target: "rewrite::lookup", "depth outside!");
                             }*/
                         }
                     }
                 }
             /*else {
-                println!("not matched");
+                trace!(target: "rewrite::lookup", "not matched");
             }*/
             } else if let AttributeValue::String(vec_bytes) = attr_val {
                 if String::from_utf8(vec_bytes.to_vec()).unwrap() == var_name.trim() {
                     /* same code block as present in above if StringRef clause */
-                    println!("matched variable name -- direct string");
+                    debug!(target: "rewrite::lookup", "matched variable name -- direct string");
                     if no_of_vars == 1 {
-                        if curr_rng.0 != None && curr_rng.1 != None {
-                            if !(curr_rng.0.unwrap() >= expr_rng.1
-                                || (curr_rng.0.unwrap() + curr_rng.1.unwrap()) <= expr_rng.0)
-                            {
-                                eprintln!("curr_rng_low: {}, curr_rng_low+high: {}, expr_rng_low: {}, expr_rng_high: {}", curr_rng.0.unwrap(), curr_rng.0.unwrap() + curr_rng.1.unwrap(), expr_rng.0, expr_rng.1);
+                        if let Some((low_pc, high_pc)) = curr_rng {
+                            if !(low_pc >= expr_rng.1 || high_pc <= expr_rng.0) {
+                                trace!(target: "rewrite::lookup", "curr_rng_low: {}, curr_rng_high: {}, expr_rng_low: {}, expr_rng_high: {}", low_pc, high_pc, expr_rng.0, expr_rng.1);
                                 var_id_depth = (Some(*child), Some(*func_id), depth + 1);
                                 return var_id_depth;
                             }
                         }
                     } else {
-                        if curr_rng.0 != None && curr_rng.1 != None {
-                            if curr_rng.0.unwrap() <= expr_rng.0
-                                && (curr_rng.0.unwrap() + curr_rng.1.unwrap()) >= expr_rng.1
-                            {
+                        if let Some((low_pc, high_pc)) = curr_rng {
+                            if low_pc <= expr_rng.0 && high_pc >= expr_rng.1 {
                                 if var_id_depth.2 == -1 || depth + 1 > var_id_depth.2 {
                                     var_id_depth = (Some(*child), Some(*func_id), depth + 1);
                                 } else {
-                                    println!("depth outside!");
+                                    debug!(target: "rewrite::lookup", "depth outside!");
                                 }
                             } else {
-                                println!(
+                                debug!(target: "rewrite::lookup",
                                     "range outside! curr_rng: {:?} expr_rng: {:?}",
                                     curr_rng, expr_rng
                                 );
@@ -220,13 +234,13 @@ fn get_var_depth(
                                 var_id_depth = (Some(*child), depth + 1);
                             }
                             else {
-                                println!("depth outside!");
+                                debug!(target: "rewrite::lookup", "depth outside!");
                             }*/
                         }
                     }
                 }
             } else {
-                println!("Attribute Value of this type not handled yet!\n");
+                warn!(target: "rewrite::lookup", "Attribute Value of this type not handled yet!");
             }
         }
 
@@ -237,7 +251,7 @@ fn get_var_depth(
             var_name,
             depth + 1,
             expr_rng,
-            (low_pc, high_pc),
+            pc_range,
             no_of_vars,
         );
         if var_id_rec != None {
@@ -253,239 +267,332 @@ fn get_var_depth(
     var_id_depth
 }
 
-fn get_func_id(
+/* The PC range [begin, end) of an entry being written. write::Dwarf::from has already resolved
+ * the DW_FORM_addrx values, so low_pc is an address and high_pc either an address too or an
+ * offset from low_pc. */
+fn get_pc_range(entry: &DebuggingInformationEntry) -> Option<(u64, u64)> {
+    let begin = match entry.get(DW_AT_low_pc)? {
+        AttributeValue::Address(address) => get_addr(*address),
+        _ => return None,
+    };
+    let end = match *entry.get(DW_AT_high_pc)? {
+        AttributeValue::Address(address) => get_addr(address),
+        AttributeValue::Data1(offset) => begin.wrapping_add(offset.into()),
+        AttributeValue::Data2(offset) => begin.wrapping_add(offset.into()),
+        AttributeValue::Data4(offset) => begin.wrapping_add(offset.into()),
+        AttributeValue::Data8(offset) | AttributeValue::Udata(offset) => begin.wrapping_add(offset),
+        _ => return None,
+    };
+    Some((begin, end))
+}
+
+pub fn get_name<'a>(
+    entry: &'a DebuggingInformationEntry,
+    strings: &'a StringTable,
+) -> Option<&'a [u8]> {
+    match entry.get(gimli::DW_AT_name)? {
+        AttributeValue::StringRef(string_id) => Some(strings.get(*string_id)),
+        AttributeValue::String(bytes) => Some(bytes),
+        _ => None,
+    }
+}
+
+/* The variable at the end of a scope path from a function: the n-th lexical block (from 1) of
+ * the scope before it for each of blocks, then the variable of that name in the last scope */
+fn get_var_by_path(
     unit: &Unit,
+    func_id: UnitEntryId,
     strings: &StringTable,
-    function: &str,
-) -> Option<gimli::write::UnitEntryId> {
-    let root_id = unit.root();
-    let root_die = unit.get(root_id);
-    let mut var_id = None;
-    for child in root_die.children() {
-        let child_die = unit.get(*child);
-        let s = child_die.tag().static_string().unwrap();
-        if s == "DW_TAG_subprogram" {
-            for attr in child_die.attrs() {
-                let attr_type = attr.name().static_string().unwrap();
-                let attr_val = attr.get();
-                if attr_type == "DW_AT_name" {
-                    if let AttributeValue::StringRef(string_id) = attr_val {
-                        if str::from_utf8(strings.get(*string_id)).unwrap() == function.trim() {
-                            var_id = Some(*child);
-                        }
-                    } else if let AttributeValue::String(vec_bytes) = attr_val {
-                        if String::from_utf8(vec_bytes.to_vec()).unwrap() == function.trim() {
-                            var_id = Some(*child);
-                        }
-                    } else {
-                        println!("Attribute Value of this type not handled yet!\n");
-                    }
-                }
-            }
-        }
+    blocks: &[usize],
+    var_name: &str,
+) -> Option<UnitEntryId> {
+    let mut scope = func_id;
+    for &n in blocks {
+        scope = *unit
+            .get(scope)
+            .children()
+            .filter(|child| unit.get(**child).tag() == gimli::DW_TAG_lexical_block)
+            .nth(n - 1)?;
     }
-    var_id
-}
-
-fn get_register_mapping(reg_name: &str) -> u16 {
-    match reg_name {
-        "eax" => 0,
-        "ecx" => 1,
-        "edx" => 2,
-        "ebx" => 3,
-        "esp" => 4,
-        "ebp" => 5,
-        "esi" => 6,
-        "edi" => 7,
-        //"xmm0"|"xmm1"|"xmm2"|"xmm3"|"xmm4"|"xmm5"|"xmm6"|"xmm7" => 21,
-        //_ => 255,
-        "es" => 40,
-        "cs" => 41,
-        "ss" => 42,
-        "ds" => 43,
-        "fs" => 44,
-        "gs" => 45,
-        _ => {
-            if reg_name.starts_with("xmm") {
-                let num: u16 = reg_name[3..].parse().unwrap();
-                21 + num
-            } else {
-                255
-            }
+    unit.get(scope)
+        .children()
+        .find(|child| {
+            let child_die = unit.get(**child);
+            (child_die.tag() == gimli::DW_TAG_variable
+                || child_die.tag() == gimli::DW_TAG_formal_parameter)
+                && get_name(child_die, strings) == Some(var_name.as_bytes())
+        })
+        .copied()
+}
+
+/* The ids of the entries of a unit converted for writing, by their offsets in the unit read.
+ * write::Dwarf::from converts all the entries in order, so both trees have the same shape. */
+fn get_entry_ids<R: Reader<Offset = usize>>(
+    dwarf: &read::Dwarf<R>,
+    unit_offset: gimli::DebugInfoOffset,
+    unit: &Unit,
+) -> Result<HashMap<read::UnitOffset, UnitEntryId>, RewriteError> {
+    let read_unit = dwarf.unit(dwarf.debug_info.header_from_offset(unit_offset)?)?;
+    let mut tree = read_unit.entries_tree(None)?;
+    let mut entry_ids = HashMap::new();
+    add_entry_ids(tree.root()?, unit, unit.root(), &mut entry_ids)?;
+    Ok(entry_ids)
+}
+
+fn add_entry_ids<R: Reader<Offset = usize>>(
+    node: read::EntriesTreeNode<R>,
+    unit: &Unit,
+    id: UnitEntryId,
+    entry_ids: &mut HashMap<read::UnitOffset, UnitEntryId>,
+) -> Result<(), RewriteError> {
+    entry_ids.insert(node.entry().offset(), id);
+    let mut ids = unit.get(id).children();
+    let mut children = node.children();
+    while let Some(child) = children.next()? {
+        match ids.next() {
+            Some(child_id) => add_entry_ids(child, unit, *child_id, entry_ids)?,
+            None => break,
         }
     }
+    Ok(())
 }
 
-fn is_arith_op(s: &str) -> bool {
-    match s {
-        "+" | "*" | "/" | "-" | "%" => true,
-        _ => false,
+/* The position of each entry of the unit in depth-first order */
+fn get_entry_order(unit: &Unit) -> HashMap<UnitEntryId, usize> {
+    let mut order = HashMap::new();
+    let mut stack = vec![unit.root()];
+    while let Some(id) = stack.pop() {
+        order.insert(id, order.len());
+        stack.extend(unit.get(id).children().rev());
     }
+    order
 }
 
-fn get_arith_dwop(s: &str) -> Option<gimli::constants::DwOp> {
-    match s {
-        "+" => Some(DW_OP_plus),
-        "-" => Some(DW_OP_minus),
-        "*" => Some(DW_OP_mul),
-        "/" => Some(DW_OP_div),
-        "%" => Some(DW_OP_mod),
-        _ => None,
-    }
+/* A subprogram of .debug_info: the unit it belongs to, its DIE offset in that unit, its low_pc
+ * and its high_pc (as an offset from low_pc). Units are converted for writing in the order they
+ * are read, so unit_index also identifies the unit to rewrite. */
+struct FuncEntry<O: gimli::ReaderOffset> {
+    unit_index: usize,
+    unit_offset: gimli::DebugInfoOffset<O>,
+    entry_offset: gimli::read::UnitOffset<O>,
+    start_addr: Option<u64>,
+    end_offset: Option<u64>,
 }
 
-fn create_dwarf_expr(loc_expr: &str) -> write::Expression {
-    let loc_expr_vec: Vec<&str> = loc_expr.split_ascii_whitespace().collect();
-    let mut new_expr = gimli::write::Expression::new();
+/* The subprograms of each name (see get_func_entries) */
+type FuncEntries<O> = HashMap<String, Vec<FuncEntry<O>>>;
 
-    for component in loc_expr_vec {
-        if component.chars().nth(0).unwrap() == '%' {
-            //register
-            /*for regnum in 0..255 {
-                //new_expr.op(DW_OP_mul);
-                new_expr.op_breg(gimli::Register(regnum), 0);
-            }*/
-            let reg = get_register_mapping(&component[1..]);
-            new_expr.op_breg(gimli::Register(reg), 0);
-        } else if let Ok(number) = component.parse::<i64>() {
-            new_expr.op_consts(number);
-        } else if is_arith_op(component) {
-            new_expr.op(get_arith_dwop(component).unwrap());
-        } else {
-            println!("Invalid component string in location expression!");
-        }
+/* The PC range of an entry, whatever the forms of its DW_AT_low_pc (an address or an index into
+ * .debug_addr) and DW_AT_high_pc (an offset from low_pc of any constant form, or an address too).
+ * begin_index is the index of the start in the ReadAddressMap, to write it back with its
+ * relocation. */
+struct PcRange {
+    begin_index: u64,
+    begin: u64,
+    end: u64,
+}
+
+fn read_pc_range<R: Reader>(
+    dwarf: &read::Dwarf<R>,
+    unit: &read::Unit<R>,
+    entry: &read::DebuggingInformationEntry<R>,
+    addresses: &ReadAddressMap,
+) -> Result<Option<PcRange>, RewriteError> {
+    let begin_index = match entry.attr_value(gimli::DW_AT_low_pc)? {
+        Some(value) => match read_address_index(dwarf, unit, &value)? {
+            Some(index) => index,
+            None => return Ok(None),
+        },
+        None => return Ok(None),
+    };
+    let begin = get_addr(addresses.get(begin_index as usize));
+    let end = match entry.attr_value(gimli::DW_AT_high_pc)? {
+        Some(value) => match read_address_index(dwarf, unit, &value)? {
+            Some(index) => get_addr(addresses.get(index as usize)),
+            None => match value.udata_value() {
+                Some(offset) => begin.wrapping_add(offset),
+                None => return Ok(None),
+            },
+        },
+        None => return Ok(None),
+    };
+    Ok(Some(PcRange {
+        begin_index,
+        begin,
+        end,
+    }))
+}
+
+/* Addresses are read as their index in the ReadAddressMap, see ReaderRelocate::read_address */
+fn read_address_index<R: Reader>(
+    dwarf: &read::Dwarf<R>,
+    unit: &read::Unit<R>,
+    value: &read::AttributeValue<R>,
+) -> Result<Option<u64>, RewriteError> {
+    match *value {
+        read::AttributeValue::Addr(index) => Ok(Some(index)),
+        read::AttributeValue::DebugAddrIndex(index) => Ok(Some(dwarf.address(unit, index)?)),
+        _ => Ok(None),
     }
-    new_expr.op(DW_OP_stack_value);
-    /*new_expr.op_breg(gimli::Register(0), 0);
-    new_expr.op_consts(128000);
-    new_expr.op(DW_OP_plus);
-    new_expr.op(DW_OP_lit4);
-    new_expr.op(DW_OP_div);
-    new_expr.op(DW_OP_stack_value);*/
-    new_expr
 }
 
-fn get_func_entry_offset<T: Reader>(
+/* Indexes the subprograms of all the units by name in a single pass, so that any number of
+ * functions can be looked up (see find_func_entry). Each name has the entries of all the units
+ * defining or declaring it, in the order of the units. */
+fn get_func_entries<T: Reader>(
     dwarf: &gimli::read::Dwarf<T>,
-    func_name: &str,
-) -> (
-    Option<gimli::read::UnitOffset<T::Offset>>,
-    Option<u64>,
-    Option<u64>,
-) {
-    let units = &mut dwarf.units();
-    let unit_header = units.next().unwrap();
-    let unit = dwarf.unit(unit_header.unwrap()).unwrap();
-    let mut entries = unit.entries();
-    let mut func_entry_offset = None;
-    let mut func_start_addr = None;
-    let mut func_end_offset = None;
-    loop {
-        if let Some((_, entry)) = entries.next_dfs().unwrap() {
-            if entry.tag() == gimli::DW_TAG_subprogram {
-                let mut attrs = entry.attrs();
-                let mut flag = false;
-                while let Some(attr) = attrs.next().unwrap() {
-                    if attr.name().static_string().unwrap() == "DW_AT_name" {
-                        if let read::AttributeValue::DebugStrRef(debug_str_offset) = attr.value() {
-                            let str_val = dwarf.string(debug_str_offset).unwrap();
-                            if str_val.to_string().unwrap() == func_name {
-                                eprintln!("function {} FOUND!", func_name);
-                                flag = true;
-                                //break;
-                            }
-                        } else if let read::AttributeValue::String(reader) = attr.value() {
-                            let s = reader.to_string().unwrap();
-                            if s.to_string() == func_name {
-                                eprintln!("function {} FOUND!", func_name);
-                                flag = true;
-                            }
-                        } else {
-                            println!("read::AttributeValue of this type not handled yet!\n");
-                        }
-                    } else if attr.name().static_string().unwrap() == "DW_AT_low_pc" {
-                        if let read::AttributeValue::Addr(start_addr) = attr.value() {
-                            func_start_addr = Some(start_addr);
-                            eprintln!("func start addr: {:x}", start_addr);
-                        }
-                    } else if attr.name().static_string().unwrap() == "DW_AT_high_pc" {
-                        if let read::AttributeValue::Udata(end_offset) = attr.value() {
-                            func_end_offset = Some(end_offset);
-                            eprintln!("func end offset: {}", end_offset);
-                        }
+    addresses: &ReadAddressMap,
+) -> Result<FuncEntries<T::Offset>, RewriteError> {
+    let mut func_entries = FuncEntries::new();
+    let mut units = dwarf.units();
+    let mut unit_index = 0;
+    while let Some(unit_header) = units.next()? {
+        let unit_offset = unit_header.offset();
+        let unit = dwarf.unit(unit_header)?;
+        let mut entries = unit.entries();
+        while let Some((_, entry)) = entries.next_dfs()? {
+            if entry.tag() != gimli::DW_TAG_subprogram {
+                continue;
+            }
+            let mut attrs = entry.attrs();
+            let mut func_name = None;
+            while let Some(attr) = attrs.next()? {
+                if attr.name() == gimli::DW_AT_name {
+                    if let read::AttributeValue::DebugStrRef(debug_str_offset) = attr.value() {
+                        let str_val = dwarf.string(debug_str_offset)?;
+                        func_name = Some(str_val.to_string()?.into_owned());
+                    } else if let read::AttributeValue::String(reader) = attr.value() {
+                        func_name = Some(reader.to_string()?.into_owned());
+                    } else {
+                        warn!(target: "rewrite::lookup", "read::AttributeValue of this type not handled yet!");
                     }
                 }
-                if flag == true {
-                    func_entry_offset = Some(entry.offset());
-                    break;
-                }
             }
-        } else {
-            break;
+            let func_name = match func_name {
+                Some(func_name) => func_name,
+                None => continue,
+            };
+            let pc_range = read_pc_range(dwarf, &unit, entry, addresses)?;
+            let func_start_addr = pc_range.as_ref().map(|range| range.begin_index);
+            let func_end_offset = pc_range.map(|range| range.end.wrapping_sub(range.begin));
+            func_entries.entry(func_name).or_default().push(FuncEntry {
+                unit_index,
+                unit_offset,
+                entry_offset: entry.offset(),
+                start_addr: func_start_addr,
+                end_offset: func_end_offset,
+            });
         }
+        unit_index += 1;
     }
-    //let func_entry_offset = func_entry.unwrap().offset();
-    (func_entry_offset, func_start_addr, func_end_offset)
+    Ok(func_entries)
 }
 
-fn help_infer_generic_type<R: Reader>(_expr: &read::Expression<R>) {}
-
-fn help_infer_read_generic_type<R: Reader>(_expr: &read::Expression<R>) {}
-
-fn help_infer_write_generic_type(_expr: &write::Expression) {}
+/* The entry of the function of a mapping: its definition, or a declaration if there is none.
+ * Where the function is defined in several units, the one starting at the zero address of the
+ * mapping (or whose PC range has it, for a zero address past a prologue) is taken. */
+fn find_func_entry<'e, O: gimli::ReaderOffset>(
+    func_entries: &'e FuncEntries<O>,
+    mapping: &Mapping,
+    addresses: &ReadAddressMap,
+) -> Result<&'e FuncEntry<O>, RewriteError> {
+    let entries = func_entries
+        .get(&mapping.function)
+        .ok_or_else(|| RewriteError::UnknownFunction(mapping.function.clone()))?;
+    let definitions: Vec<_> = entries
+        .iter()
+        .filter(|entry| entry.start_addr.is_some())
+        .collect();
+    match definitions[..] {
+        [] => return Ok(&entries[0]),
+        [definition] => return Ok(definition),
+        _ => {}
+    }
+    let start = |entry: &FuncEntry<O>| get_addr(addresses.get(entry.start_addr.unwrap() as usize));
+    let candidates: Vec<_> = match mapping.zero_address {
+        Some(zero_address) => {
+            let starting: Vec<_> = definitions
+                .iter()
+                .filter(|entry| start(entry) == zero_address)
+                .collect();
+            if starting.is_empty() {
+                definitions
+                    .iter()
+                    .filter(|entry| {
+                        let offset = zero_address.wrapping_sub(start(entry));
+                        entry.end_offset.map_or(false, |end| offset < end)
+                    })
+                    .collect()
+            } else {
+                starting
+            }
+        }
+        None => Vec::new(),
+    };
+    match candidates[..] {
+        [definition] => Ok(definition),
+        _ => Err(RewriteError::AmbiguousFunction {
+            line: mapping.line,
+            function: mapping.function.clone(),
+            units: definitions.len(),
+        }),
+    }
+}
 
-//let get_addr = |addr: write::Address| -> u64 {
+/* The value of an address read from the input. Relocated addresses keep the value of their
+ * symbol in the addend, as an i64 which wraps for addresses from 2^63 up. */
 fn get_addr(addr: write::Address) -> u64 {
     match addr {
-        write::Address::Constant(value) => {
-            //eprintln!("value: {:x}", value);
-            value.try_into().unwrap()
-        }
-        write::Address::Symbol {
-            symbol: _, /*ignoring the field*/
-            addend,
-        } => {
-            //eprintln!("addend value: {:x}, symbol: {:?}", addend, symbol);
-            addend.try_into().unwrap()
-        }
+        write::Address::Constant(value) => value,
+        write::Address::Symbol { addend, .. } => addend as u64,
     }
 }
 
-fn read_existing_location_lists<R: Reader>(
+fn read_existing_location_lists<R: Reader<Offset = usize>>(
     dwarf: &read::Dwarf<R>,
-    function: &str,
+    func_entry: &FuncEntry<R::Offset>,
     addresses: &ReadAddressMap,
-    var_map: &mut HashMap<String, Vec<(LocationInfo<R>, (Option<u64>, Option<u64>))>>,
-    var_empty_scope: &mut HashMap<String, bool>,
-    zeroaddr: &mut u64,
-)
+) -> Result<FunctionVars<read::UnitOffset>, RewriteError>
 /* Reading location lists BEGIN */
 {
-    let (func_entry_offset, func_start_addr, _func_end_offset) =
-        get_func_entry_offset(&dwarf, function);
-    eprintln!("func_start_addr: {:x}", func_start_addr.unwrap());
-    eprintln!("zeroaddr: {:x}", zeroaddr);
-    let addr = addresses.get(func_start_addr.unwrap() as usize);
+    let unit_header = dwarf
+        .debug_info
+        .header_from_offset(func_entry.unit_offset)?;
+    let unit = dwarf.unit(unit_header)?;
+    let encoding = unit.encoding();
+
+    let func_start_addr = match func_entry.start_addr {
+        Some(func_start_addr) => func_start_addr,
+        None => {
+            return Err(RewriteError::UnsupportedAttributeForm {
+                attribute: gimli::DW_AT_low_pc,
+                value: format!(
+                    "{:?}",
+                    unit.entry(func_entry.entry_offset)?
+                        .attr_value(gimli::DW_AT_low_pc)?
+                ),
+            })
+        }
+    };
+    trace!(target: "rewrite::lookup", "func_start_addr: {:x}", func_start_addr);
+    if let Some(func_end_offset) = func_entry.end_offset {
+        trace!(target: "rewrite::lookup", "func end offset: {}", func_end_offset);
+    }
+    let addr = addresses.get(func_start_addr as usize);
     let value = get_addr(addr);
-    eprintln!("actual func_start_addr: {:x}", value);
-    *zeroaddr = *zeroaddr - value;
+    trace!(target: "rewrite::lookup", "actual func_start_addr: {:x}", value);
 
-    let units = &mut dwarf.units();
-    let unit_header = units.next().unwrap();
-    let unit = dwarf.unit(unit_header.unwrap()).unwrap();
-    eprintln!("unit low_pc: {}", unit.low_pc);
-    let addr = addresses.get(unit.low_pc as usize);
-    let unit_low_pc = get_addr(addr);
-    eprintln!("actual unit_low_pc = {}", unit_low_pc);
+    let unit_base_address = read_unit_base(dwarf, &unit, addresses)?;
+    trace!(target: "rewrite::lookup", "unit base: {:?}", unit_base_address);
+    let unit_base = get_unit_base(unit_base_address);
 
-    let mut entries = unit.entries_at_offset(func_entry_offset.unwrap()).unwrap();
+    let mut entries = unit.entries_at_offset(func_entry.entry_offset)?;
 
     let mut depth = 0;
     let mut first = true;
-    let mut low_pc = None;
-    let mut high_pc = None;
-    let mut scope_ranges: Option<Vec<(u64, u64)>> = None;
-    while let Some((index, entry)) = entries.next_dfs().unwrap() {
+    let mut scopes: Vec<Scope> = Vec::new();
+    let mut vars = HashMap::new();
+    while let Some((index, entry)) = entries.next_dfs()? {
         depth += index;
         if !first && depth <= 0 {
             break;
@@ -493,66 +600,60 @@ fn read_existing_location_lists<R: Reader>(
         if first == true {
             first = false;
         }
-        eprintln!("Entry tag: {:?}", entry.tag().static_string());
-        eprintln!("Index : {}", index);
+        while let Some(scope) = scopes.last() {
+            if scope.depth < depth {
+                break;
+            }
+            scopes.pop();
+        }
+        trace!(target: "rewrite::lookup", "Entry tag: {:?}", entry.tag().static_string());
+        trace!(target: "rewrite::lookup", "Index : {}", index);
         if true {
             let mut attrs = entry.attrs();
             let mut loclist_vec = Vec::new();
             let mut name = None;
             let mut expr = None;
-            let mut expr_present = false;
             let mut write_expr = None;
-            let mut write_expr_present = false;
-            let mut low_pc_attr_present = false;
-            let mut high_pc_attr_present = false;
-            let mut ranges_attr_present = false;
+            let mut const_data = None;
+            let mut ranges_offset = None;
             //let mut ss = "";
-            while let Some(attr) = attrs.next().unwrap() {
-                if attr.name().static_string().unwrap() == "DW_AT_name" {
+            while let Some(attr) = attrs.next()? {
+                if attr.name() == gimli::DW_AT_name {
                     if let read::AttributeValue::DebugStrRef(debug_str_offset) = attr.value() {
-                        let str_val = dwarf.string(debug_str_offset).unwrap();
-                        eprintln!("Name: {}", str_val.to_string().unwrap());
-                        let s = str_val.to_string().unwrap().clone();
+                        let str_val = dwarf.string(debug_str_offset)?;
+                        trace!(target: "rewrite::lookup", "Name: {}", str_val.to_string()?);
+                        let s = str_val.to_string()?.clone();
                         name = Some(s.into_owned());
                     //name = Some(str_val.to_string().clone().unwrap());
                     } else if let read::AttributeValue::String(reader) = attr.value() {
-                        let s = reader.to_string().unwrap();
+                        let s = reader.to_string()?;
                         name = Some(s.to_string());
                     } else {
-                        eprintln!("read::AttributeValue of this type not handled yet!\n");
+                        warn!(target: "rewrite::lookup", "read::AttributeValue of this type not handled yet!");
                     }
-                //eprintln!("Variable Name: {:?}", attr.value());
-                } else if attr.name().static_string().unwrap() == "DW_AT_location" {
-                    if let read::AttributeValue::LocationListsRef(location_lists_offset) =
-                        attr.value()
+                } else if attr.name() == gimli::DW_AT_location {
+                    if let Some(location_lists_offset) =
+                        get_location_lists_offset(dwarf, &unit, attr.value())?
                     {
-                        eprintln!("location lists offset: {:?}", location_lists_offset);
-                        let mut loclist_iter =
-                            dwarf.locations(&unit, location_lists_offset).unwrap();
-                        while let Some(loclist_entry) = loclist_iter.next().unwrap() {
-                            /* See -- no need to use actual unit_low_pc ? Seems yes */
-                            eprintln!(
-                                "loc range: {}, {}",
-                                loclist_entry.range.begin - unit.low_pc,
-                                loclist_entry.range.end - unit.low_pc
-                            );
-                            //eprintln!("addresses len + 1: {}", addresses.add(write::Address::Constant(5)));
-                            //eprintln!("begin: {:x}, end: {:x}", get_addr(addresses.get(loclist_entry.range.begin as usize)), get_addr(addresses.get(loclist_entry.range.end as usize)));
-                            let mut entry = loclist_entry.clone();
-                            entry.range.begin -= unit.low_pc;
-                            entry.range.end -= unit.low_pc;
-                            loclist_vec.push(entry);
+                        trace!(target: "rewrite::lookup", "location lists offset: {:?}", location_lists_offset);
+                        loclist_vec = read_location_entries(
+                            dwarf,
+                            &unit,
+                            location_lists_offset,
+                            unit_base_address,
+                            addresses,
+                        )?;
+                        for (begin, end, _) in &loclist_vec {
+                            trace!(target: "rewrite::lookup", "loc range: {:x}, {:x}", begin.value, end.value);
                         }
                     } else if let read::AttributeValue::Exprloc(expression) = attr.value() {
-                        expr_present = true;
                         expr = Some(expression);
                     } else {
-                        eprintln!(
-                            "read::AttributeValue -- location -- of this type not handled yet!\n"
+                        warn!(target: "rewrite::lookup",
+                            "read::AttributeValue -- location -- of this type not handled yet!"
                         );
                     }
-                } else if attr.name().static_string().unwrap() == "DW_AT_const_value" {
-                    write_expr_present = true;
+                } else if attr.name() == gimli::DW_AT_const_value {
                     let mut error = false;
                     let data = match attr.value() {
                         read::AttributeValue::Data1(data) => data as i64,
@@ -567,36 +668,23 @@ fn read_existing_location_lists<R: Reader>(
                         }
                     };
                     if error {
-                        eprintln!("ERROR: Invalid data in DW_AT_const_value!");
+                        warn!(target: "rewrite::lookup", "Invalid data in DW_AT_const_value!");
                     }
                     let mut expression = write::Expression::new();
                     expression.op_consts(data);
                     expression.op(DW_OP_stack_value);
                     write_expr = Some(expression);
+                    const_data = Some(data);
 
                 /*if low_pc != None && high_pc != None {
-                    eprintln!("low_pc and high_pc available!");
+                    trace!(target: "rewrite::lookup", "low_pc and high_pc available!");
                     let low_pc = low_pc.unwrap();
                     let high_pc = high_pc.unwrap();
                     let high_pc = low_pc + high_pc;
                 }*/
-                } else if attr.name().static_string().unwrap() == "DW_AT_low_pc" {
-                    if let read::AttributeValue::Addr(_addr) = attr.value() {
-                        low_pc_attr_present = true;
-                        /*low_pc = Some(addr);
-                        eprintln!("low_pc := {}", addr);*/
-                    }
-                } else if attr.name().static_string().unwrap() == "DW_AT_high_pc" {
-                    if let read::AttributeValue::Udata(_offset) = attr.value() {
-                        high_pc_attr_present = true;
-                    /*high_pc = Some(offset);
-                    eprintln!("high_pc := {}", offset);*/
-                    } else {
-                        eprintln!("ERROR: Expected offset in DW_AT_high_pc!");
-                    }
-                } else if attr.name().static_string().unwrap() == "DW_AT_ranges" {
-                    if let read::AttributeValue::RangeListsRef(_offset) = attr.value() {
-                        ranges_attr_present = true;
+                } else if attr.name() == gimli::DW_AT_ranges {
+                    if let Some(offset) = get_range_lists_offset(dwarf, &unit, attr.value())? {
+                        ranges_offset = Some(offset);
                     }
                 }
             }
@@ -604,166 +692,591 @@ fn read_existing_location_lists<R: Reader>(
             if entry.tag() == gimli::DW_TAG_formal_parameter
                 || entry.tag() == gimli::DW_TAG_variable
             {
-                if name != None {
-                    /* Multiple variables with same name not handled in this var_empty_scope map
-                     * yet! */
-                    if scope_ranges == None {
-                        var_empty_scope.insert(name.clone().unwrap(), true);
-                    } else {
-                        var_empty_scope.insert(name.clone().unwrap(), false);
+                let scope = scopes
+                    .iter()
+                    .rev()
+                    .find_map(|scope| scope.pc_range.as_ref());
+                let const_value = expr.is_none() && write_expr.is_some();
+                let empty_scope = match scopes.last() {
+                    Some(scope) => scope.ranges.is_empty(),
+                    None => true,
+                };
+                let address = |index: u64| get_addr(addresses.get(index as usize));
+                let before = if !loclist_vec.is_empty() {
+                    loclist_vec
+                        .iter()
+                        .map(|(begin, end, data)| LocationReport {
+                            begin: begin.value,
+                            end: end.value,
+                            expression: format_expression(data.clone(), encoding, &address),
+                        })
+                        .collect()
+                } else {
+                    let expression = match (&expr, const_data) {
+                        (Some(expression), _) => {
+                            Some(format_expression(expression.clone(), encoding, &address))
+                        }
+                        (None, Some(data)) => Some(format!("DW_AT_const_value {}", data)),
+                        (None, None) => None,
+                    };
+                    match (scope, expression) {
+                        (Some(scope), Some(expression)) if scope.begin < scope.end => {
+                            vec![LocationReport {
+                                begin: scope.begin,
+                                end: scope.end,
+                                expression,
+                            }]
+                        }
+                        _ => Vec::new(),
                     }
-                }
+                };
+                let existing = if !loclist_vec.is_empty() {
+                    loclist_vec
+                        .iter()
+                        .map(|(begin, end, data)| ExistingLocation {
+                            begin: begin.value,
+                            end: end.value,
+                            expression: PostfixExpression::from_dwarf(data.clone(), encoding),
+                        })
+                        .collect()
+                } else {
+                    let expression = match (&expr, const_data) {
+                        (Some(expression), _) => {
+                            PostfixExpression::from_dwarf(expression.clone(), encoding)
+                        }
+                        (None, Some(data)) => Some(PostfixExpression::constant(data)),
+                        (None, None) => None,
+                    };
+                    match scope {
+                        Some(scope) if expr.is_some() || const_data.is_some() => {
+                            vec![ExistingLocation {
+                                begin: scope.begin,
+                                end: scope.end,
+                                expression,
+                            }]
+                        }
+                        _ => Vec::new(),
+                    }
+                };
+                let locations = if !loclist_vec.is_empty() {
+                    trace!(target: "rewrite::lookup", "Trying to add a LocList..");
+                    read_location_list(loclist_vec, encoding, addresses)
+                } else {
+                    /* A single location holds over the whole scope */
+                    let expression = match expr {
+                        Some(expression) => {
+                            trace!(target: "rewrite::lookup", "Trying to add a Loc..");
+                            Some(convert_expression(expression, encoding, addresses))
+                        }
+                        None => write_expr.map(Ok),
+                    };
+                    let mut locations = LocationMap::new();
+                    match (scope, expression) {
+                        (_, Some(Err(err))) => Err(err),
+                        (Some(scope), Some(Ok(expression))) => {
+                            let begin = get_bound(addresses, scope.begin_index);
+                            locations.insert(begin, Bound::constant(scope.end), expression);
+                            Ok(locations)
+                        }
+                        _ => Ok(locations),
+                    }
+                };
+                vars.insert(
+                    entry.offset(),
+                    VarLocation {
+                        name,
+                        offset: (func_entry.unit_offset.0 + entry.offset().0) as u64,
+                        locations,
+                        existing,
+                        before,
+                        scope: scope.map(|scope| (scope.begin, scope.end)),
+                        const_value,
+                        empty_scope,
+                    },
+                );
             }
 
-            let low_pc_val = entry.attr_value(gimli::DW_AT_low_pc).unwrap();
-            let high_pc_val = entry.attr_value(gimli::DW_AT_high_pc).unwrap();
-            if low_pc_attr_present && high_pc_attr_present {
-                let low_pc_val = low_pc_val.unwrap();
-                let high_pc_val = high_pc_val.unwrap();
-                if let read::AttributeValue::Addr(addr) = low_pc_val {
-                    low_pc = Some(addr);
-                    eprintln!("low_pc := {}", addr);
-                }
-                if let read::AttributeValue::Udata(offset) = high_pc_val {
-                    high_pc = Some(offset);
-                    eprintln!("high_pc := {}", offset);
-                }
-                if low_pc != None && high_pc != None {
-                    let low_pc = low_pc.unwrap();
-                    let low_pc = get_addr(addresses.get(low_pc as usize));
-                    let high_pc = low_pc + high_pc.unwrap();
-                    let scope_vec = vec![(low_pc, high_pc)];
-                    scope_ranges = Some(scope_vec);
-                }
+            let pc_range = read_pc_range(dwarf, &unit, entry, addresses)?;
+            let mut ranges = Vec::new();
+            if let Some(pc_range) = &pc_range {
+                trace!(target: "rewrite::lookup", "low_pc := {}", pc_range.begin_index);
+                trace!(target: "rewrite::lookup", "high_pc := {}", pc_range.end.wrapping_sub(pc_range.begin));
+                ranges.push((pc_range.begin, pc_range.end));
             }
 
-            let ranges_val = entry.attr_value(gimli::DW_AT_ranges).unwrap();
-            if ranges_attr_present {
-                let ranges_val = ranges_val.unwrap();
-                if let read::AttributeValue::RangeListsRef(offset) = ranges_val {
-                    let mut rangelist_iter = dwarf.ranges(&unit, offset).unwrap();
-                    let mut scope_vec = Vec::new();
-                    while let Some(range_entry) = rangelist_iter.next().unwrap() {
-                        let begin =
-                            get_addr(addresses.get((range_entry.begin - unit.low_pc) as usize));
-                        let end = get_addr(addresses.get((range_entry.end - unit.low_pc) as usize));
-                        scope_vec.push((begin, end));
-                    }
-                    if scope_vec.is_empty() {
-                        scope_ranges = None;
-                    } else {
-                        scope_ranges = Some(scope_vec);
-                    }
-                }
+            if let Some(offset) = ranges_offset {
+                ranges = read_range_entries(dwarf, &unit, offset, unit_base_address, addresses)?;
+            }
+            if pc_range.is_some() || ranges_offset.is_some() {
+                scopes.push(Scope {
+                    depth,
+                    pc_range,
+                    ranges,
+                });
             }
+        }
+    }
+    Ok(FunctionVars {
+        start: value,
+        unit_base,
+        unit_index: func_entry.unit_index,
+        unit_offset: func_entry.unit_offset,
+        entry: func_entry.entry_offset,
+        vars,
+    })
+}
+/* Reading location lists END */
 
-            if name != None {
-                if !loclist_vec.is_empty() {
-                    eprintln!("Trying to add a LocList..");
-                    if let Some(v) = var_map.get_mut(&name.clone().unwrap()) {
-                        /* Look at the semantics of high_pc (whether it's just an offset or the
-                         * actual address?) throughout the file -- try to keep it
-                         * uniform */
-                        v.push((LocationInfo::LocList(loclist_vec), (low_pc, high_pc)));
-                    } else {
-                        var_map.insert(
-                            name.unwrap(),
-                            vec![(LocationInfo::LocList(loclist_vec), (low_pc, high_pc))],
-                        );
-                    }
-                } else if low_pc != None && high_pc != None {
-                    let low_pc = low_pc.unwrap();
-                    let high_pc = high_pc.unwrap();
-                    //let high_pc = low_pc + high_pc;
-                    if expr_present || write_expr_present {
-                        if expr_present {
-                            let location = Location {
-                                expression: expr.unwrap(),
-                                begin: low_pc,
-                                end: high_pc,
-                            };
-                            eprintln!("Trying to add a Loc..");
-                            if let Some(v) = var_map.get_mut(&name.clone().unwrap()) {
-                                /* Look at the semantics of high_pc (whether it's just an offset or the
-                                 * actual address?) throughout the file -- try to keep it
-                                 * uniform */
-                                v.push((
-                                    LocationInfo::Loc(location),
-                                    (Some(low_pc), Some(high_pc)),
-                                ));
-                            } else {
-                                var_map.insert(
-                                    name.unwrap(),
-                                    vec![(
-                                        LocationInfo::Loc(location),
-                                        (Some(low_pc), Some(high_pc)),
-                                    )],
-                                );
-                            }
-                        } else
-                        /*if write_expr_present*/
-                        {
-                            let location = WriteLocation {
-                                expression: write_expr.unwrap(),
-                                begin: low_pc,
-                                /* see if high_pc is right */
-                                end: high_pc,
-                            };
-                            eprintln!("write_expr_present: begin = {}, end = {}", low_pc, high_pc);
-                            eprintln!("Trying to add a WLoc..");
-                            if let Some(v) = var_map.get_mut(&name.clone().unwrap()) {
-                                /* Look at the semantics of high_pc (whether it's just an offset or the
-                                 * actual address?) throughout the file -- try to keep it
-                                 * uniform */
-                                v.push((
-                                    LocationInfo::WLoc(location),
-                                    (Some(low_pc), Some(high_pc)),
-                                ));
-                            } else {
-                                var_map.insert(
-                                    name.unwrap(),
-                                    vec![(
-                                        LocationInfo::WLoc(location),
-                                        (Some(low_pc), Some(high_pc)),
-                                    )],
-                                );
-                            }
-                        }
-                    } else {
-                        /* Make sure to iterate correctly over the IntLocList, whenever we get a
-                         * value from var_map, as we are adding an
-                         * empty list here */
-                        let location_list = Vec::new();
-                        if let Some(v) = var_map.get_mut(&name.clone().unwrap()) {
-                            /* Look at the semantics of high_pc (whether it's just an offset or the
-                             * actual address?) throughout the file -- try to keep it
-                             * uniform */
-                            v.push((
-                                LocationInfo::IntLocList(location_list),
-                                (Some(low_pc), Some(high_pc)),
-                            ));
-                        } else {
-                            var_map.insert(
-                                name.unwrap(),
-                                vec![(
-                                    LocationInfo::IntLocList(location_list),
-                                    (Some(low_pc), Some(high_pc)),
-                                )],
-                            );
-                        }
-                    }
+/* The mappings of a variable, gathered so that its location list is built once */
+struct VarMappings<'a> {
+    function: FunctionKey<'a>,
+    var_name: String,
+    var: UnitEntryId,
+    overrides: Vec<Override>,
+    /* The mappings of members of the variable, whose size in bits is var_size */
+    pieces: Vec<PieceOverride>,
+    var_size: u64,
+    /* Index in the outcomes of the mapping of each piece */
+    piece_outcomes: Vec<usize>,
+    /* Index in overrides (or pieces, for a member) and line of the mapping over each range */
+    lines: HashMap<MappingKey, (usize, Option<usize>)>,
+}
+
+/* The range of a mapping and, for a member, the piece of the variable it sets */
+type MappingKey = (u64, u64, Option<Piece>);
+
+/* A function by its name and the index of the unit defining it, as functions of several units
+ * may share a name */
+type FunctionKey<'a> = (&'a str, usize);
+
+/* What became of a mapping of a function, and the variable it was found to be */
+type Outcome<'a> = (FunctionKey<'a>, Option<UnitEntryId>, MappingReport);
+
+/* Logs that a mapping is skipped because of err, and records it for the report */
+fn skip<'a>(
+    outcomes: &mut Vec<Outcome<'a>>,
+    function: FunctionKey<'a>,
+    mapping: &Mapping,
+    var: Option<UnitEntryId>,
+    err: RewriteError,
+) {
+    warn!(target: "rewrite::splice", "{}! Skipping..", err);
+    let reason = Some(err.to_string());
+    outcomes.push((
+        function,
+        var,
+        MappingReport::new(mapping, MappingStatus::Skipped, reason),
+    ));
+}
+
+fn read_location_list<R: Reader<Offset = usize>>(
+    entries: Vec<LocationEntry<R>>,
+    encoding: gimli::Encoding,
+    addresses: &ReadAddressMap,
+) -> write::ConvertResult<LocationMap> {
+    let mut locations = LocationMap::new();
+    for (begin, end, data) in entries {
+        locations.insert(begin, end, convert_expression(data, encoding, addresses)?);
+    }
+    Ok(locations)
+}
+
+/* An entry of a location list: its range and its expression */
+type LocationEntry<R> = (Bound, Bound, read::Expression<R>);
+
+/* The offset of the location list a DW_AT_location refers to, by offset or (DWARF 5) by index */
+fn get_location_lists_offset<R: Reader<Offset = usize>>(
+    dwarf: &read::Dwarf<R>,
+    unit: &read::Unit<R>,
+    value: read::AttributeValue<R>,
+) -> Result<Option<gimli::LocationListsOffset>, RewriteError> {
+    match value {
+        read::AttributeValue::LocationListsRef(offset) => Ok(Some(offset)),
+        read::AttributeValue::DebugLocListsIndex(index) => {
+            Ok(Some(dwarf.locations_offset(unit, index)?))
+        }
+        _ => Ok(None),
+    }
+}
+
+/* The offset of the range list a DW_AT_ranges refers to, by offset or (DWARF 5) by index */
+fn get_range_lists_offset<R: Reader<Offset = usize>>(
+    dwarf: &read::Dwarf<R>,
+    unit: &read::Unit<R>,
+    value: read::AttributeValue<R>,
+) -> Result<Option<gimli::RangeListsOffset>, RewriteError> {
+    match value {
+        read::AttributeValue::RangeListsRef(offset) => Ok(Some(offset)),
+        read::AttributeValue::DebugRngListsIndex(index) => {
+            Ok(Some(dwarf.ranges_offset(unit, index)?))
+        }
+        _ => Ok(None),
+    }
+}
+
+/* The entries of a location list with their absolute bounds. The raw entries are read, as the
+ * addresses read are indices into addresses (see ReadAddressMap): the offsets of DW_LLE_offset_pair
+ * entries and of the pairs of .debug_loc are added here to the base address in effect, which is
+ * base (that of the unit) until a base address entry changes it. */
+fn read_location_entries<R: Reader<Offset = usize>>(
+    dwarf: &read::Dwarf<R>,
+    unit: &read::Unit<R>,
+    offset: gimli::LocationListsOffset,
+    mut base: Address,
+    addresses: &ReadAddressMap,
+) -> Result<Vec<LocationEntry<R>>, RewriteError> {
+    let address = |index: u64| addresses.get(index as usize);
+    let indexed =
+        |index| -> Result<Address, RewriteError> { Ok(address(dwarf.address(unit, index)?)) };
+    let base_marker = !0 >> (64 - u64::from(unit.encoding().address_size) * 8);
+    let mut entries = Vec::new();
+    let mut raw_entries = dwarf.locations.raw_locations(offset, unit.encoding())?;
+    while let Some(raw_entry) = raw_entries.next()? {
+        let (begin, end, data) = match raw_entry {
+            /* The base address selection entries of .debug_loc are only told apart by value */
+            read::RawLocListEntry::AddressOrOffsetPair { begin, end, .. }
+                if get_addr(address(begin)) == base_marker =>
+            {
+                base = address(end);
+                continue;
+            }
+            read::RawLocListEntry::AddressOrOffsetPair { begin, end, data } => (
+                add_base(base, address(begin)),
+                add_base(base, address(end)),
+                data,
+            ),
+            read::RawLocListEntry::BaseAddress { addr } => {
+                base = address(addr);
+                continue;
+            }
+            read::RawLocListEntry::BaseAddressx { addr } => {
+                base = indexed(addr)?;
+                continue;
+            }
+            read::RawLocListEntry::OffsetPair { begin, end, data } => {
+                (offset_address(base, begin), offset_address(base, end), data)
+            }
+            read::RawLocListEntry::StartEnd { begin, end, data } => {
+                (address(begin), address(end), data)
+            }
+            read::RawLocListEntry::StartLength {
+                begin,
+                length,
+                data,
+            } => (address(begin), offset_address(address(begin), length), data),
+            read::RawLocListEntry::StartxEndx { begin, end, data } => {
+                (indexed(begin)?, indexed(end)?, data)
+            }
+            read::RawLocListEntry::StartxLength {
+                begin,
+                length,
+                data,
+            } => {
+                let begin = indexed(begin)?;
+                (begin, offset_address(begin, length), data)
+            }
+            /* The location outside of all the ranges of the list has no range to splice into */
+            read::RawLocListEntry::DefaultLocation { .. } => {
+                warn!(target: "rewrite::lookup", "default location entry ignored");
+                continue;
+            }
+        };
+        entries.push((to_bound(begin), to_bound(end), data));
+    }
+    Ok(entries)
+}
+
+/* The ranges of a range list as absolute addresses, read like the location lists (see
+ * read_location_entries) */
+fn read_range_entries<R: Reader<Offset = usize>>(
+    dwarf: &read::Dwarf<R>,
+    unit: &read::Unit<R>,
+    offset: gimli::RangeListsOffset,
+    mut base: Address,
+    addresses: &ReadAddressMap,
+) -> Result<Vec<(u64, u64)>, RewriteError> {
+    let address = |index: u64| addresses.get(index as usize);
+    let indexed =
+        |index| -> Result<Address, RewriteError> { Ok(address(dwarf.address(unit, index)?)) };
+    let base_marker = !0 >> (64 - u64::from(unit.encoding().address_size) * 8);
+    let mut ranges = Vec::new();
+    let mut raw_ranges = dwarf.ranges.raw_ranges(offset, unit.encoding())?;
+    while let Some(raw_range) = raw_ranges.next()? {
+        let (begin, end) = match raw_range {
+            read::RawRngListEntry::AddressOrOffsetPair { begin, end }
+                if get_addr(address(begin)) == base_marker =>
+            {
+                base = address(end);
+                continue;
+            }
+            read::RawRngListEntry::AddressOrOffsetPair { begin, end } => {
+                (add_base(base, address(begin)), add_base(base, address(end)))
+            }
+            read::RawRngListEntry::BaseAddress { addr } => {
+                base = address(addr);
+                continue;
+            }
+            read::RawRngListEntry::BaseAddressx { addr } => {
+                base = indexed(addr)?;
+                continue;
+            }
+            read::RawRngListEntry::OffsetPair { begin, end } => {
+                (offset_address(base, begin), offset_address(base, end))
+            }
+            read::RawRngListEntry::StartEnd { begin, end } => (address(begin), address(end)),
+            read::RawRngListEntry::StartLength { begin, length } => {
+                (address(begin), offset_address(address(begin), length))
+            }
+            read::RawRngListEntry::StartxEndx { begin, end } => (indexed(begin)?, indexed(end)?),
+            read::RawRngListEntry::StartxLength { begin, length } => {
+                let begin = indexed(begin)?;
+                (begin, offset_address(begin, length))
+            }
+        };
+        ranges.push((get_addr(begin), get_addr(end)));
+    }
+    Ok(ranges)
+}
+
+/* An address read as an offset from base. Either of them may be relocated, not both. */
+fn add_base(base: Address, address: Address) -> Address {
+    match address {
+        Address::Constant(offset) => offset_address(base, offset),
+        Address::Symbol { .. } => offset_address(address, get_addr(base)),
+    }
+}
+
+fn offset_address(address: Address, offset: u64) -> Address {
+    match address {
+        Address::Constant(value) => Address::Constant(value.wrapping_add(offset)),
+        Address::Symbol { symbol, addend } => Address::Symbol {
+            symbol,
+            addend: addend.wrapping_add(offset as i64),
+        },
+    }
+}
+
+fn to_bound(address: Address) -> Bound {
+    Bound::new(get_addr(address), address)
+}
+
+fn convert_expression<R: Reader<Offset = usize>>(
+    expression: read::Expression<R>,
+    encoding: gimli::Encoding,
+    addresses: &ReadAddressMap,
+) -> write::ConvertResult<write::Expression> {
+    let convert_address = |index| Some(addresses.get(index as usize));
+    write::Expression::from(expression, encoding, None, None, None, &convert_address)
+}
+
+/* The bound of an existing location range, read as an address index */
+fn get_bound(addresses: &ReadAddressMap, index: u64) -> Bound {
+    let address = addresses.get(index as usize);
+    Bound::new(get_addr(address), address)
+}
+
+/* The base address of a unit, its DW_AT_low_pc, which the offsets of its location and range lists
+ * are relative to. 0 if it has none. */
+fn read_unit_base<R: Reader<Offset = usize>>(
+    dwarf: &read::Dwarf<R>,
+    unit: &read::Unit<R>,
+    addresses: &ReadAddressMap,
+) -> Result<Address, RewriteError> {
+    let mut entries = unit.entries();
+    let index = match entries.next_dfs()? {
+        Some((_, root)) => match root.attr_value(gimli::DW_AT_low_pc)? {
+            Some(value) => read_address_index(dwarf, unit, &value)?.unwrap_or(0),
+            None => 0,
+        },
+        None => 0,
+    };
+    Ok(addresses.get(index as usize))
+}
+
+/* The location lists are spliced with absolute addresses, this is the address of the unit base to
+ * subtract when writing them back, if the unit has one */
+fn get_unit_base(unit_base: Address) -> Option<u64> {
+    if unit_base == Address::Constant(0) {
+        None
+    } else {
+        Some(get_addr(unit_base))
+    }
+}
+
+/* Writes the entries of a new location list as offsets from the unit base, if there is one. When
+ * compact (DWARF 5 output), lists without a unit base get their own base address entry instead of
+ * absolute addresses. */
+fn rebase_location_list(
+    location_list: Vec<write::Location>,
+    unit_base: Option<u64>,
+    compact: bool,
+) -> write::LocationList {
+    let mut rebased = Vec::new();
+    let base = match unit_base {
+        Some(unit_base) => unit_base,
+        None if compact => {
+            let begins = location_list.iter().filter_map(|location| match location {
+                write::Location::StartEnd { begin, .. } => Some(*begin),
+                _ => None,
+            });
+            /* The address itself, to keep its relocation */
+            match begins.min_by_key(|&begin| get_addr(begin)) {
+                Some(address) => {
+                    rebased.push(write::Location::BaseAddress { address });
+                    get_addr(address)
                 }
+                None => return write::LocationList(location_list),
             }
         }
+        None => return write::LocationList(location_list),
+    };
+    rebased.extend(location_list.into_iter().map(|location| match location {
+        write::Location::StartEnd { begin, end, data } => write::Location::OffsetPair {
+            begin: get_addr(begin).wrapping_sub(base),
+            end: get_addr(end).wrapping_sub(base),
+            data,
+        },
+        location => location,
+    }));
+    write::LocationList(rebased)
+}
+
+type WriteSections = write::Sections<WriterRelocate<EndianVec<LittleEndian>>>;
+
+/* The sections written, with .debug_addr which gimli does not write (see index_base_addresses).
+ * It is visited first, as a section must be defined before those referring to it. */
+struct DwarfSections {
+    debug_addr: WriterRelocate<EndianVec<LittleEndian>>,
+    sections: WriteSections,
+}
+
+impl DwarfSections {
+    fn for_each<F, E>(&self, mut f: F) -> Result<(), E>
+    where
+        F: FnMut(gimli::SectionId, &WriterRelocate<EndianVec<LittleEndian>>) -> Result<(), E>,
+    {
+        f(gimli::SectionId::DebugAddr, &self.debug_addr)?;
+        self.sections.for_each(f)
+    }
+
+    fn for_each_mut<F, E>(&mut self, mut f: F) -> Result<(), E>
+    where
+        F: FnMut(gimli::SectionId, &mut WriterRelocate<EndianVec<LittleEndian>>) -> Result<(), E>,
+    {
+        f(gimli::SectionId::DebugAddr, &mut self.debug_addr)?;
+        self.sections.for_each_mut(f)
     }
 }
-/* Reading location lists END */
 
+/* How the DWARF is written: the version to upgrade the units to, if any, the architecture whose
+ * register names the expressions use, and whether their operations are folded (see simplify) */
+pub struct DwarfOptions {
+    pub dwarf_version: Option<u16>,
+    pub arch: Architecture,
+    pub simplify: bool,
+}
+
+/* Rewrites the DWARF of a relocatable object into out_object, symbols mapping the symbols of
+ * in_object to those of out_object. What became of the mappings is recorded in report, if there
+ * is one. */
 pub fn rewrite_dwarf(
     in_object: &object::File<'_>,
     out_object: &mut object_write::Object,
     symbols: &HashMap<SymbolIndex, object_write::SymbolId>,
-) {
+    mappings: &[Mapping],
+    options: &DwarfOptions,
+    report: Option<&mut RewriteReport>,
+) -> Result<(), RewriteError> {
+    let mut sections = write_dwarf(in_object, mappings, options, report)?;
+    let mut section_symbols = HashMap::new();
+
+    sections.for_each_mut(|id, w| {
+        define(
+            id,
+            out_object,
+            &mut section_symbols,
+            symbols,
+            w.writer.take(),
+            &w.relocations,
+        )
+    })?;
+
+    /*
+    let frame = write::FrameTable::from(&eh_frame, &convert_address).unwrap();
+    let mut out_eh_frame = write::EhFrame(WriterRelocate::new(EndianVec::new(LittleEndian)));
+    frame.write_eh_frame(&mut out_eh_frame).unwrap();
+    define(
+        gimli::SectionId::EhFrame,
+        out_object,
+        &mut section_symbols,
+        symbols,
+        out_eh_frame.0.writer.take(),
+        &out_eh_frame.0.relocations,
+    );
+    */
+    Ok(())
+}
+
+/* Rewrites the DWARF of a linked object, returning the new contents of its sections. There are
+ * no symbols to relocate against, so the offsets between the sections are resolved here. */
+pub fn rewrite_dwarf_in_place(
+    in_object: &object::File<'_>,
+    mappings: &[Mapping],
+    options: &DwarfOptions,
+    report: Option<&mut RewriteReport>,
+) -> Result<Vec<(&'static str, Vec<u8>)>, RewriteError> {
+    let mut sections = write_dwarf(in_object, mappings, options, report)?;
+    let mut contents = Vec::new();
+
+    sections.for_each_mut(|id, w| -> Result<(), RewriteError> {
+        let data = resolve_in_place(id, w)?;
+        if !data.is_empty() {
+            contents.push((id.name(), data));
+        }
+        Ok(())
+    })?;
+    Ok(contents)
+}
+
+/* The contents of a section of a linked object, its offsets into the other sections resolved */
+fn resolve_in_place(
+    id: gimli::SectionId,
+    w: &mut WriterRelocate<EndianVec<LittleEndian>>,
+) -> Result<Vec<u8>, RewriteError> {
+    let mut data = w.writer.take();
+    for relocation in &w.relocations {
+        match *relocation {
+            Relocation::Section {
+                offset,
+                addend,
+                size,
+                ..
+            } => {
+                let offset = offset as usize;
+                let size = size as usize;
+                let value = (addend as u64).to_le_bytes();
+                data[offset..offset + size].copy_from_slice(&value[..size]);
+            }
+            Relocation::Symbol { .. } => {
+                return Err(RewriteError::UnsupportedObject(format!(
+                    "symbol relocation in {} of a linked object",
+                    id.name()
+                )))
+            }
+        }
+    }
+    Ok(data)
+}
+
+fn write_dwarf(
+    in_object: &object::File<'_>,
+    mappings: &[Mapping],
+    options: &DwarfOptions,
+    report: Option<&mut RewriteReport>,
+) -> Result<DwarfSections, RewriteError> {
+    if let Some(version) = options.dwarf_version {
+        check_dwarf_version(version)?;
+    }
     /*
     // Define the sections we can't convert yet.
     for section in in_object.sections() {
@@ -791,25 +1304,25 @@ pub fn rewrite_dwarf(
             section,
             reader,
         }
-    };
+    }
 
     let addresses = ReadAddressMap::default();
     let no_section = (Cow::Borrowed(&[][..]), ReadRelocationMap::default());
-    let (debug_abbrev_data, debug_abbrev_relocs) = get_section(in_object, ".debug_abbrev");
-    let (debug_addr_data, debug_addr_relocs) = get_section(in_object, ".debug_addr");
-    let (debug_info_data, debug_info_relocs) = get_section(in_object, ".debug_info");
-    let (debug_line_data, debug_line_relocs) = get_section(in_object, ".debug_line");
-    let (debug_line_str_data, debug_line_str_relocs) = get_section(in_object, ".debug_line_str");
-    let (debug_loc_data, debug_loc_relocs) = get_section(in_object, ".debug_loc");
-    let (debug_loclists_data, debug_loclists_relocs) = get_section(in_object, ".debug_loclists");
-    let (debug_ranges_data, debug_ranges_relocs) = get_section(in_object, ".debug_ranges");
+    let (debug_abbrev_data, debug_abbrev_relocs) = get_section(in_object, ".debug_abbrev")?;
+    let (debug_addr_data, debug_addr_relocs) = get_section(in_object, ".debug_addr")?;
+    let (debug_info_data, debug_info_relocs) = get_section(in_object, ".debug_info")?;
+    let (debug_line_data, debug_line_relocs) = get_section(in_object, ".debug_line")?;
+    let (debug_line_str_data, debug_line_str_relocs) = get_section(in_object, ".debug_line_str")?;
+    let (debug_loc_data, debug_loc_relocs) = get_section(in_object, ".debug_loc")?;
+    let (debug_loclists_data, debug_loclists_relocs) = get_section(in_object, ".debug_loclists")?;
+    let (debug_ranges_data, debug_ranges_relocs) = get_section(in_object, ".debug_ranges")?;
     /*let (debug_aranges_data, debug_aranges_relocs) = get_section(in_object, ".debug_aranges");*/
-    let (debug_rnglists_data, debug_rnglists_relocs) = get_section(in_object, ".debug_rnglists");
-    let (debug_str_data, debug_str_relocs) = get_section(in_object, ".debug_str");
+    let (debug_rnglists_data, debug_rnglists_relocs) = get_section(in_object, ".debug_rnglists")?;
+    let (debug_str_data, debug_str_relocs) = get_section(in_object, ".debug_str")?;
     let (debug_str_offsets_data, debug_str_offsets_relocs) =
-        get_section(in_object, ".debug_str_offsets");
-    let (debug_types_data, debug_types_relocs) = get_section(in_object, ".debug_types");
-    let dwarf = read::Dwarf {
+        get_section(in_object, ".debug_str_offsets")?;
+    let (debug_types_data, debug_types_relocs) = get_section(in_object, ".debug_types")?;
+    let read_dwarf = read::Dwarf {
         debug_abbrev: read::DebugAbbrev::from(get_reader(
             &debug_abbrev_data,
             &debug_abbrev_relocs,
@@ -878,905 +1391,609 @@ pub fn rewrite_dwarf(
                           &addresses,
     ));*/
 
-    let mut buffer = String::new();
-    std::io::stdin().read_to_string(&mut buffer).unwrap();
-    //println!("{}", buffer);
-    let mut lines = buffer.as_str().lines();
-    let line = lines.next().unwrap();
-    //println!("first line: {}", first_line);
-    //let function: Vec<&str> = first_line.split(':').collect();
-    //let function = function[1];
-    assert!(line == "=ZeroAddress", "Invalid expr-file-format!");
-    let line = lines.next().unwrap();
-    let zeroaddr = line;
-    let zeroaddr = zeroaddr.trim_start_matches("0x");
-    let mut zeroaddr = u64::from_str_radix(zeroaddr, 16).unwrap();
-    let line = lines.next().unwrap();
-    assert!(line == "=TotalPCs", "Invalid expr-file-format!");
-    let line = lines.next().unwrap();
-    let _total_pcs = line.parse::<u32>().unwrap();
-    let line = lines.next().unwrap();
-    assert!(line == "=Function", "Invalid expr-file-format!");
-    let line = lines.next().unwrap();
-    let function = line;
-    println!("[LOG]: function: {}", function);
-
-    let mut var_map = HashMap::new();
-    let mut var_empty_scope = HashMap::new();
-
-    read_existing_location_lists(
-        &dwarf,
-        &function,
-        &addresses,
-        &mut var_map,
-        &mut var_empty_scope,
-        &mut zeroaddr,
-    );
-
-    //REMOVE this
-    //eprintln!("addresses len + 1: {}", addresses.add(write::Address::Constant(5)));
+    /* The existing locations of the variables of each function are read up front, as the DWARF
+     * is only converted for writing once */
+    let func_entries = get_func_entries(&read_dwarf, &addresses)?;
+    let mut functions = HashMap::new();
+    let mut function_keys = Vec::with_capacity(mappings.len());
+    for mapping in mappings {
+        let func_entry = find_func_entry(&func_entries, mapping, &addresses)?;
+        let function = (mapping.function.as_str(), func_entry.unit_index);
+        function_keys.push(function);
+        if functions.contains_key(&function) {
+            continue;
+        }
+        info!(target: "rewrite::splice", "function: {}", mapping.function);
+        let vars = read_existing_location_lists(&read_dwarf, func_entry, &addresses)?;
+        functions.insert(function, vars);
+    }
 
     let convert_address = |index| Some(addresses.get(index as usize));
 
-    let mut dwarf = match write::Dwarf::from(&dwarf, &convert_address) {
-        Ok(dwarf) => dwarf,
-        Err(write::ConvertError::Read(err)) => {
-            eprintln!("dwarf convert: {}", dwarf.format_error(err));
-            panic!();
-        }
-        _ => panic!(),
+    let pinned_units = match options.dwarf_version {
+        Some(_) => find_pinned_units(&read_dwarf)?,
+        None => HashSet::new(),
     };
-
-    //let test = 130;
-    //eprintln!("testaddr: {:x}", get_addr(addresses.get(test as usize)));
-    //REMOVE this
-    //eprintln!("addresses len + 1: {}", addresses.add(write::Address::Constant(5)));
-
-    let line = lines.next().unwrap();
-    assert!(line == "=Expressions", "Invalid expr-file-format!");
-    for line in lines {
-        //print!("{}", line);
-        //print!("\n");
-        let pc_var_expr: Vec<&str> = line.split('\t').collect();
-        let pc_range: Vec<&str> = pc_var_expr[1].split("->").collect();
-        let var_and_expr: Vec<&str> = pc_var_expr[0].split('=').collect();
-        let mut var_name = var_and_expr[0];
-        let loc_expr = var_and_expr[1];
+    let compact = options.dwarf_version.is_some();
+    let mut dwarf = write::Dwarf::from(&read_dwarf, &convert_address)?;
+
+    /* From here on, the entries are those converted for writing */
+    let units: HashSet<_> = functions
+        .values()
+        .map(|vars| (vars.unit_index, vars.unit_offset))
+        .collect();
+    let mut entry_ids = HashMap::new();
+    for (unit_index, unit_offset) in units {
+        let unit = dwarf.units.get(dwarf.units.id(unit_index));
+        entry_ids.insert(unit_index, get_entry_ids(&read_dwarf, unit_offset, unit)?);
+    }
+    let functions: HashMap<FunctionKey, FunctionVars<UnitEntryId>> = functions
+        .into_iter()
+        .map(|(function, vars)| {
+            let ids = &entry_ids[&vars.unit_index];
+            (function, vars.into_entry_ids(ids))
+        })
+        .collect();
+
+    let mut groups: Vec<VarMappings> = Vec::new();
+    let mut group_index = HashMap::new();
+    let mut outcomes: Vec<Outcome> = Vec::new();
+    for (mapping, &function) in mappings.iter().zip(&function_keys) {
+        let line_no = mapping.line;
+        let vars = &functions[&function];
+        let var_name = mapping.variable.as_str();
+        let loc_expr = mapping.expression.as_str();
         if var_name.contains("(")
-            || loc_expr.contains("(")
-            || var_name.contains("phi")
-            || var_name.starts_with("symbol")
-            || var_name.starts_with("%")
-            || var_name.starts_with("input.dst.")
-            || var_name.starts_with("input.src.")
-        {
-            println!("[LOG]: Skipping {}: {}", var_and_expr[0], var_and_expr[1]);
-            continue;
-        }
-        println!("[LOG]: Processing {}: {}", var_and_expr[0], var_and_expr[1]);
-        if var_name.contains(".") {
-            let tokens: Vec<&str> = var_name.split('.').collect();
-            var_name = tokens[0];
-        }
-        println!("[LOG]: varname: {}", var_name);
-
-        let units = &mut dwarf.units;
-        let strings = &mut dwarf.strings;
-        let unit = units.get_mut(units.id(0));
-        let func_id = get_func_id(unit, strings, function); //.expect("Provided function not present in .debug_info!");
-        if func_id == None {
-            println!(
-                "[LOG]: Provided function: {} not present in .debug_info! Skipping..",
-                function
-            );
-            continue;
-        }
-        let begin = pc_range[0].trim_start_matches("0x");
-        let begin = u64::from_str_radix(begin, 16).unwrap();
-        let end = pc_range[1].trim_start_matches("0x");
-        let end = u64::from_str_radix(end, 16).unwrap();
-
-        let mut start = begin - zeroaddr;
-        let mut end = end - zeroaddr;
-
-        let key_present = var_map.contains_key(var_name);
-        println!("key_present = {}", key_present);
-        //assert!(key_present == true, "var_name not present in var_map!");
-        if key_present == false {
-            eprintln!(
-                "[LOG]: Variable: {} not present in the var_map! Skipping..",
-                var_name
-            );
-            continue;
-        }
-        let no_of_vars = var_map.get(&var_name.to_string()).unwrap().len();
-
-        let (var, parent) = get_var(
-            unit,
-            &func_id.unwrap(),
-            strings,
-            var_name,
-            (start, end),
-            no_of_vars as u8,
-        ); //.expect("Variable not present in the lexical block!");
-        if var == None {
-            println!(
-                "[LOG]: Variable: {} not present in the function! Skipping..",
-                var_name
-            );
-            continue;
-        }
-        if no_of_vars == 1 {
-            let vec_loc_info = var_map.get(&var_name.to_string()).unwrap();
-            let (_, (var_low, var_high)) = vec_loc_info[0];
-
-            let var_low = var_low.unwrap();
-            let var_low = get_addr(addresses.get(var_low as usize));
-            let var_high = var_high.unwrap();
-            eprintln!(
-                "start: {}, end: {}, var_low: {}, var_low+var_high: {}",
-                start,
-                end,
-                var_low,
-                var_low + var_high
-            );
-            if start < var_low {
-                start = var_low;
-            }
-            if end > var_low + var_high {
-                end = var_low + var_high;
-            }
-            assert!(start < end, "Error: start >= end!");
-        }
-
-        let new_dwarf_expr = create_dwarf_expr(loc_expr);
-
-        let mut new_loc_list = Vec::new();
-        let encoding = unit.encoding();
-
-        let mut process_location = |data, locbegin: u64, locend: u64| {
-            /* Note: Don't remove below line - it is used to circumvent adding a new function
-             * instead of a closure and it's allowing type inference for the parameter 'data'.
-             * This has to do with Closures with Generics. So far, there is no way to specify
-             * generic type as part of a closure. */
-            eprintln!("process_location BEGIN");
-            help_infer_generic_type(data);
-
-            let rangebegin = locbegin as usize;
-            let rangeend = locend as usize;
-            let rangebegin = get_addr(addresses.get(rangebegin));
-            let rangeend = get_addr(addresses.get(rangeend));
-
-            let write_expr =
-                write::Expression::from(data.clone(), encoding, None, None, None, &convert_address);
-            let write_expr = write_expr.unwrap();
-
-            if (start < rangebegin && end <= rangebegin) || (start >= rangeend && end > rangeend) {
-                // do as normal
-                eprintln!("Do as normal");
-                eprintln!(
-                    "start: {:x}, end: {:x}, rangebegin: {:x}, rangeend: {:x}",
-                    start, end, rangebegin, rangeend
-                );
-
-                let rangebegin = locbegin as usize;
-                let rangeend = locend as usize;
-
-                let new_loc = write::Location::StartEnd {
-                    begin: addresses.get(rangebegin),
-                    end: addresses.get(rangeend),
-                    data: write_expr,
-                };
-                new_loc_list.push(new_loc);
-            } else if start <= rangebegin && end >= rangeend {
-                // ignore this range
-                eprintln!("Ignore this change");
-            } else if start >= rangebegin && end <= rangeend {
-                if start == rangebegin
-                /* && end < rangeend */
-                {
-                    // split into two
-                    // start-end, end-rangeend
-                    eprintln!("Split into two");
-
-                    //let rangebegin = locbegin as usize;
-                    let rangeend = locend as usize;
-
-                    let new_loc = write::Location::StartEnd {
-                        begin: write::Address::Constant(end),
-                        end: addresses.get(rangeend),
-                        data: write_expr,
-                    };
-                    new_loc_list.push(new_loc);
-                } else if end == rangeend
-                /* && start > rangebegin */
-                {
-                    // split into two
-                    // rangebegin-start, start-end
-                    eprintln!("Split into two");
-
-                    let rangebegin = locbegin as usize;
-                    //let rangeend = locend as usize;
-
-                    let new_loc = write::Location::StartEnd {
-                        begin: addresses.get(rangebegin),
-                        end: write::Address::Constant(start),
-                        data: write_expr,
-                    };
-                    new_loc_list.push(new_loc);
-                } else {
-                    // split into three
-                    // rangebegin-start, start-end, end-rangeend
-                    eprintln!("Split into three");
-                    eprintln!(
-                        "start: {:x}, end: {:x}, rangebegin: {:x}, rangeend: {:x}",
-                        start, end, rangebegin, rangeend
-                    );
-
-                    let rangebegin = locbegin as usize;
-                    let rangeend = locend as usize;
-
-                    let new_loc = write::Location::StartEnd {
-                        begin: addresses.get(rangebegin),
-                        end: write::Address::Constant(start),
-                        data: write_expr.clone(),
-                    };
-                    new_loc_list.push(new_loc);
-                    let new_loc = write::Location::StartEnd {
-                        begin: write::Address::Constant(end),
-                        end: addresses.get(rangeend),
-                        data: write_expr,
-                    };
-                    new_loc_list.push(new_loc);
-                }
-            } else if start < rangebegin && end >= rangebegin && end < rangeend {
-                // split into two
-                // start-end, end-rangeend
-                eprintln!("Split into two");
-                //let rangebegin = locbegin as usize;
-                let rangeend = locend as usize;
-                let new_loc = write::Location::StartEnd {
-                    begin: write::Address::Constant(end),
-                    end: addresses.get(rangeend),
-                    data: write_expr,
-                };
-                new_loc_list.push(new_loc);
-            } else if end > rangeend && start > rangebegin && start < rangeend {
-                // split into two
-                // rangebegin-start, start-end
-                eprintln!("Split into two");
-                let rangebegin = locbegin as usize;
-                //let rangeend = locend as usize;
-                let new_loc = write::Location::StartEnd {
-                    begin: addresses.get(rangebegin),
-                    end: write::Address::Constant(start),
-                    data: write_expr,
-                };
-                new_loc_list.push(new_loc);
-            } else {
-                // unreachable
-                assert!(false, "Unreachable block executed!");
-            }
-            eprintln!("process_location END");
-        };
-        /* Rust tricks - to separate out the gimli::read::Expression and gimli::write::Expression,
-         * had to create a new closure - otherwise, the type inferencing doesn't work -
-         * Additionally, separating them into two based on read::Expression and loclist and
-         * single location */
-        let mut new_read_loc_list = Vec::new();
-        let mut process_read_location = |data, locbegin: u64, locend: u64| {
-            /* Note: Don't remove below line - it is used to circumvent adding a new function
-             * instead of a closure and it's allowing type inference for the parameter 'data'.
-             * This has to do with Closures with Generics. So far, there is no way to specify
-             * generic type as part of a closure. */
-            help_infer_read_generic_type(data);
-
-            let rangebegin = locbegin as usize;
-            let rangeend = locend as usize;
-            let rangebegin = get_addr(addresses.get(rangebegin));
-            //let rangeend = get_addr(addresses.get(rangeend));
-            //let rangebegin = rangebegin as u64;
-            let rangeend = rangeend as u64;
-
-            let write_expr =
-                write::Expression::from(data.clone(), encoding, None, None, None, &convert_address);
-            let write_expr = write_expr.unwrap();
-
-            if (start < rangebegin && end <= rangebegin) || (start >= rangeend && end > rangeend) {
-                // do as normal
-                eprintln!("Do as normal");
-                eprintln!(
-                    "start: {:x}, end: {:x}, rangebegin: {:x}, rangeend: {:x}",
-                    start, end, rangebegin, rangeend
-                );
-
-                let rangebegin = locbegin as usize;
-                let rangeend = locend as usize;
-
-                let new_loc = write::Location::StartEnd {
-                    begin: addresses.get(rangebegin),
-                    end: write::Address::Constant(rangeend as u64),
-                    data: write_expr,
-                };
-                new_read_loc_list.push(new_loc);
-            } else if start <= rangebegin && end >= rangeend {
-                // ignore this range
-                eprintln!("Ignore this change");
-            } else if start >= rangebegin && end <= rangeend {
-                if start == rangebegin
-                /* && end < rangeend */
-                {
-                    // split into two
-                    // start-end, end-rangeend
-                    eprintln!("Split into two");
-
-                    //let rangebegin = locbegin as usize;
-                    let rangeend = locend as usize;
-
-                    let new_loc = write::Location::StartEnd {
-                        begin: write::Address::Constant(end),
-                        end: write::Address::Constant(rangeend as u64),
-                        data: write_expr,
-                    };
-                    new_read_loc_list.push(new_loc);
-                } else if end == rangeend
-                /* && start > rangebegin */
-                {
-                    // split into two
-                    // rangebegin-start, start-end
-                    eprintln!("Split into two");
-
-                    let rangebegin = locbegin as usize;
-                    //let rangeend = locend as usize;
-
-                    let new_loc = write::Location::StartEnd {
-                        begin: addresses.get(rangebegin),
-                        end: write::Address::Constant(start),
-                        data: write_expr,
-                    };
-                    new_read_loc_list.push(new_loc);
-                } else {
-                    // split into three
-                    // rangebegin-start, start-end, end-rangeend
-                    eprintln!("Split into three");
-                    eprintln!(
-                        "start: {:x}, end: {:x}, rangebegin: {:x}, rangeend: {:x}",
-                        start, end, rangebegin, rangeend
-                    );
-
-                    let rangebegin = locbegin as usize;
-                    let rangeend = locend as usize;
-
-                    let new_loc = write::Location::StartEnd {
-                        begin: addresses.get(rangebegin),
-                        end: write::Address::Constant(start),
-                        data: write_expr.clone(),
-                    };
-                    new_read_loc_list.push(new_loc);
-                    let new_loc = write::Location::StartEnd {
-                        begin: write::Address::Constant(end),
-                        end: write::Address::Constant(rangeend as u64),
-                        data: write_expr,
-                    };
-                    new_read_loc_list.push(new_loc);
-                }
-            } else if start < rangebegin && end >= rangebegin && end < rangeend {
-                // split into two
-                // start-end, end-rangeend
-                eprintln!("Split into two");
-                //let rangebegin = locbegin as usize;
-                let rangeend = locend as usize;
-                let new_loc = write::Location::StartEnd {
-                    begin: write::Address::Constant(end),
-                    end: write::Address::Constant(rangeend as u64),
-                    data: write_expr,
+            || loc_expr
+                .split_ascii_whitespace()
+                .any(|component| component.contains('(') && !component.starts_with("entry("))
+            || var_name.contains("phi")
+            || var_name.starts_with("symbol")
+            || var_name.starts_with("%")
+            || var_name.starts_with("input.dst.")
+            || var_name.starts_with("input.src.")
+        {
+            info!(target: "rewrite::splice", "Skipping {}: {}", var_name, loc_expr);
+            outcomes.push((
+                function,
+                None,
+                MappingReport::new(
+                    mapping,
+                    MappingStatus::Skipped,
+                    Some("unsupported variable or expression".to_string()),
+                ),
+            ));
+            continue;
+        }
+        info!(target: "rewrite::splice", "Processing {}: {}", var_name, loc_expr);
+        let variable = match mapping.variable_ref() {
+            Ok(variable) => variable,
+            Err(err) => {
+                skip(&mut outcomes, function, mapping, None, err);
+                continue;
+            }
+        };
+        let mut new_expr = match PostfixExpression::parse(loc_expr, options.arch, options.simplify)
+        {
+            Ok(new_expr) => new_expr,
+            Err(reason) => {
+                let err = RewriteError::InvalidExpression {
+                    line: line_no,
+                    expression: mapping.expression.clone(),
+                    reason,
                 };
-                new_read_loc_list.push(new_loc);
-            } else if end > rangeend && start > rangebegin && start < rangeend {
-                // split into two
-                // rangebegin-start, start-end
-                eprintln!("Split into two");
-                let rangebegin = locbegin as usize;
-                //let rangeend = locend as usize;
-                let new_loc = write::Location::StartEnd {
-                    begin: addresses.get(rangebegin),
-                    end: write::Address::Constant(start),
-                    data: write_expr,
+                skip(&mut outcomes, function, mapping, None, err);
+                continue;
+            }
+        };
+        let (var_name, members) = match variable.name().map(split_members) {
+            Some(Ok(split)) => split,
+            Some(Err(reason)) => {
+                let err = RewriteError::UnknownMember {
+                    line: line_no,
+                    variable: mapping.variable.clone(),
+                    reason,
                 };
-                new_read_loc_list.push(new_loc);
-            } else {
-                // unreachable
-                assert!(false, "Unreachable block executed!");
+                skip(&mut outcomes, function, mapping, None, err);
+                continue;
             }
+            None => (var_name, Vec::new()),
         };
-        /* Rust tricks - to separate out the gimli::read::Expression and gimli::write::Expression,
-         * had to create a new closure - otherwise, the type inferencing doesn't work */
-        let mut new_write_loc_list = Vec::new();
-        let mut process_write_location = |data, locbegin: u64, locend: u64| {
-            /* Note: Don't remove below line - it is used to circumvent adding a new function
-             * instead of a closure and it's allowing type inference for the parameter 'data'.
-             * This has to do with Closures with Generics. So far, there is no way to specify
-             * generic type as part of a closure. */
-            help_infer_write_generic_type(data);
-
-            let rangebegin = locbegin as usize;
-            let rangeend = locend as usize;
-            let rangebegin = get_addr(addresses.get(rangebegin));
-            //let rangeend = get_addr(addresses.get(rangeend));
-            //let rangebegin = rangebegin as u64;
-            let rangeend = rangeend as u64;
-
-            let write_expr = data.clone();
-
-            if (start < rangebegin && end <= rangebegin) || (start >= rangeend && end > rangeend) {
-                // do as normal
-                eprintln!("Do as normal");
-                eprintln!(
-                    "start: {:x}, end: {:x}, rangebegin: {:x}, rangeend: {:x}",
-                    start, end, rangebegin, rangeend
-                );
-
-                let rangebegin = locbegin as usize;
-                let rangeend = locend as usize;
+        debug!(target: "rewrite::splice", "varname: {}", var_name);
+
+        let unit = dwarf.units.get(dwarf.units.id(vars.unit_index));
+        let strings = &dwarf.strings;
+        let (begin, end) = mapping.pc_range;
+        if begin >= end {
+            let err = RewriteError::RangeInversion {
+                line: line_no,
+                begin,
+                end,
+            };
+            skip(&mut outcomes, function, mapping, None, err);
+            continue;
+        }
 
-                let new_loc = write::Location::StartEnd {
-                    begin: addresses.get(rangebegin),
-                    end: write::Address::Constant(rangeend as u64),
-                    data: write_expr,
-                };
-                new_write_loc_list.push(new_loc);
-            } else if start <= rangebegin && end >= rangeend {
-                // ignore this range
-                eprintln!("Ignore this change");
-            } else if start >= rangebegin && end <= rangeend {
-                if start == rangebegin
-                /* && end < rangeend */
-                {
-                    // split into two
-                    // start-end, end-rangeend
-                    eprintln!("Split into two");
-
-                    //let rangebegin = locbegin as usize;
-                    let rangeend = locend as usize;
-
-                    let new_loc = write::Location::StartEnd {
-                        begin: write::Address::Constant(end),
-                        end: write::Address::Constant(rangeend as u64),
-                        data: write_expr,
-                    };
-                    new_write_loc_list.push(new_loc);
-                } else if end == rangeend
-                /* && start > rangebegin */
-                {
-                    // split into two
-                    // rangebegin-start, start-end
-                    eprintln!("Split into two");
-
-                    let rangebegin = locbegin as usize;
-                    //let rangeend = locend as usize;
-
-                    let new_loc = write::Location::StartEnd {
-                        begin: addresses.get(rangebegin),
-                        end: write::Address::Constant(start),
-                        data: write_expr,
-                    };
-                    new_write_loc_list.push(new_loc);
+        let mut start = vars.start.wrapping_add(begin);
+        let mut end = vars.start.wrapping_add(end);
+
+        let var = match &variable {
+            VariableRef::Name(_) => {
+                let no_of_vars = vars
+                    .vars
+                    .values()
+                    .filter(|var| var.name.as_deref() == Some(var_name))
+                    .count();
+                if no_of_vars == 0 {
+                    None
                 } else {
-                    // split into three
-                    // rangebegin-start, start-end, end-rangeend
-                    eprintln!("Split into three");
-                    eprintln!(
-                        "start: {:x}, end: {:x}, rangebegin: {:x}, rangeend: {:x}",
-                        start, end, rangebegin, rangeend
-                    );
-
-                    let rangebegin = locbegin as usize;
-                    let rangeend = locend as usize;
-
-                    let new_loc = write::Location::StartEnd {
-                        begin: addresses.get(rangebegin),
-                        end: write::Address::Constant(start),
-                        data: write_expr.clone(),
-                    };
-                    new_write_loc_list.push(new_loc);
-                    let new_loc = write::Location::StartEnd {
-                        begin: write::Address::Constant(end),
-                        end: write::Address::Constant(rangeend as u64),
-                        data: write_expr,
+                    get_var(
+                        unit,
+                        &vars.entry,
+                        strings,
+                        var_name,
+                        (start, end),
+                        no_of_vars as u8,
+                    )
+                    .0
+                }
+            }
+            VariableRef::Offset { name, offset } => offset
+                .checked_sub(vars.unit_offset.0 as u64)
+                .and_then(|offset| {
+                    entry_ids[&vars.unit_index].get(&read::UnitOffset(offset as usize))
+                })
+                .copied()
+                .filter(|var| match vars.vars.get(var) {
+                    Some(var_location) => {
+                        name.is_none() || var_location.name.as_deref() == Some(var_name)
+                    }
+                    None => false,
+                }),
+            VariableRef::Path {
+                function: path_function,
+                blocks,
+                ..
+            } => {
+                if path_function != function.0 {
+                    let err = RewriteError::InvalidVariable {
+                        line: line_no,
+                        variable: mapping.variable.clone(),
+                        reason: format!("scope path outside of function {}", function.0),
                     };
-                    new_write_loc_list.push(new_loc);
+                    skip(&mut outcomes, function, mapping, None, err);
+                    continue;
                 }
-            } else if start < rangebegin && end >= rangebegin && end < rangeend {
-                // split into two
-                // start-end, end-rangeend
-                eprintln!("Split into two");
-                //let rangebegin = locbegin as usize;
-                let rangeend = locend as usize;
-                let new_loc = write::Location::StartEnd {
-                    begin: write::Address::Constant(end),
-                    end: write::Address::Constant(rangeend as u64),
-                    data: write_expr,
+                get_var_by_path(unit, vars.entry, strings, blocks, var_name)
+            }
+        };
+        let var = match var {
+            Some(var) => var,
+            None => {
+                let err = RewriteError::UnknownVariable {
+                    line: line_no,
+                    name: variable.to_string(),
                 };
-                new_write_loc_list.push(new_loc);
-            } else if end > rangeend && start > rangebegin && start < rangeend {
-                // split into two
-                // rangebegin-start, start-end
-                eprintln!("Split into two");
-                let rangebegin = locbegin as usize;
-                //let rangeend = locend as usize;
-                let new_loc = write::Location::StartEnd {
-                    begin: addresses.get(rangebegin),
-                    end: write::Address::Constant(start),
-                    data: write_expr,
+                skip(&mut outcomes, function, mapping, None, err);
+                continue;
+            }
+        };
+        /* A member is set as a piece of the variable */
+        let piece = match locate_members(unit, strings, var, &members) {
+            Ok(piece) => piece,
+            Err(reason) => {
+                let err = RewriteError::UnknownMember {
+                    line: line_no,
+                    variable: mapping.variable.clone(),
+                    reason,
                 };
-                new_write_loc_list.push(new_loc);
-            } else {
-                // unreachable
-                assert!(false, "Unreachable block executed!");
+                skip(&mut outcomes, function, mapping, Some(var), err);
+                continue;
             }
         };
-        let mut new_int_write_loc_list = Vec::new();
-        let mut process_int_write_location =
-            |data, locbegin: write::Address, locend: write::Address| {
-                /* Note: Don't remove below line - it is used to circumvent adding a new function
-                 * instead of a closure and it's allowing type inference for the parameter 'data'.
-                 * This has to do with Closures with Generics. So far, there is no way to specify
-                 * generic type as part of a closure. */
-                help_infer_write_generic_type(data);
-
-                let rangebegin = get_addr(locbegin);
-                let rangeend = get_addr(locend);
-
-                let write_expr = data.clone();
-
-                if (start < rangebegin && end <= rangebegin)
-                    || (start >= rangeend && end > rangeend)
-                {
-                    // do as normal
-                    eprintln!("Do as normal");
-                    eprintln!(
-                        "start: {:x}, end: {:x}, rangebegin: {:x}, rangeend: {:x}",
-                        start, end, rangebegin, rangeend
-                    );
-
-                    let new_loc = write::Location::StartEnd {
-                        begin: locbegin,
-                        end: locend,
-                        data: write_expr,
-                    };
-                    new_int_write_loc_list.push(new_loc);
-                } else if start <= rangebegin && end >= rangeend {
-                    // ignore this range
-                    eprintln!("Ignore this change");
-                } else if start >= rangebegin && end <= rangeend {
-                    if start == rangebegin
-                    /* && end < rangeend */
-                    {
-                        // split into two
-                        // start-end, end-rangeend
-                        eprintln!("Split into two");
-
-                        let new_loc = write::Location::StartEnd {
-                            begin: write::Address::Constant(end),
-                            end: locend,
-                            data: write_expr,
-                        };
-                        new_int_write_loc_list.push(new_loc);
-                    } else if end == rangeend
-                    /* && start > rangebegin */
-                    {
-                        // split into two
-                        // rangebegin-start, start-end
-                        eprintln!("Split into two");
-
-                        let new_loc = write::Location::StartEnd {
-                            begin: locbegin,
-                            end: write::Address::Constant(start),
-                            data: write_expr,
-                        };
-                        new_int_write_loc_list.push(new_loc);
-                    } else {
-                        // split into three
-                        // rangebegin-start, start-end, end-rangeend
-                        eprintln!("Split into three");
-                        eprintln!(
-                            "start: {:x}, end: {:x}, rangebegin: {:x}, rangeend: {:x}",
-                            start, end, rangebegin, rangeend
-                        );
-
-                        let new_loc = write::Location::StartEnd {
-                            begin: locbegin,
-                            end: write::Address::Constant(start),
-                            data: write_expr.clone(),
-                        };
-                        new_int_write_loc_list.push(new_loc);
-                        let new_loc = write::Location::StartEnd {
-                            begin: write::Address::Constant(end),
-                            end: locend,
-                            data: write_expr,
-                        };
-                        new_int_write_loc_list.push(new_loc);
-                    }
-                } else if start < rangebegin && end >= rangebegin && end < rangeend {
-                    // split into two
-                    // start-end, end-rangeend
-                    eprintln!("Split into two");
-
-                    let new_loc = write::Location::StartEnd {
-                        begin: write::Address::Constant(end),
-                        end: locend,
-                        data: write_expr,
-                    };
-                    new_int_write_loc_list.push(new_loc);
-                } else if end > rangeend && start > rangebegin && start < rangeend {
-                    // split into two
-                    // rangebegin-start, start-end
-                    eprintln!("Split into two");
-
-                    let new_loc = write::Location::StartEnd {
-                        begin: locbegin,
-                        end: write::Address::Constant(start),
-                        data: write_expr,
-                    };
-                    new_int_write_loc_list.push(new_loc);
-                } else {
-                    // unreachable
-                    assert!(false, "Unreachable block executed!");
-                }
+        if new_expr.uses_frame_base() && unit.get(vars.entry).get(DW_AT_frame_base).is_none() {
+            let err = RewriteError::InvalidExpression {
+                line: line_no,
+                expression: mapping.expression.clone(),
+                reason: format!("{} has no frame base", function.0),
             };
-        let mut const_value_attr = false;
-        let mut read_location = false;
-        let mut int_loc_list = false;
-        let mut pc_range = (None, None);
-        /*if loclist_vec != None {}*/
-        /* can't be compared !! */
-        if key_present {
-            let vec_loc_info = var_map.get_mut(&var_name.to_string()).unwrap();
-            let (loc_info, pc_range_tmp) = {
-                let mut curr_pc_range: (Option<u64>, Option<u64>) = (None, None);
-                let mut curr_loc_info = None;
-                let mut curr_i = None;
-                for (i, (loc_info, pc_range_tmp)) in vec_loc_info.iter().enumerate() {
-                    println!("vec_loc_info iter..");
-                    if let (Some(l), Some(h)) = pc_range_tmp {
-                        eprintln!("Found some l and h! l: {} h: {}", l, h);
-                        eprintln!("start: {}, end: {}", start, end);
-                        let l = get_addr(addresses.get(*l as usize));
-                        eprintln!("New l: {}", l);
-                        if start >= l && end <= l + h {
-                            if let (Some(curr_l), Some(curr_h)) = curr_pc_range {
-                                let curr_l = get_addr(addresses.get(curr_l as usize));
-                                if l >= curr_l && (l + h <= curr_l + curr_h) {
-                                    curr_pc_range = (pc_range_tmp.0, pc_range_tmp.1);
-                                    curr_loc_info = Some((*loc_info).clone());
-                                    curr_i = Some(i);
-                                }
-                            } else
-                            /* if curr_pc_range == (None, None) */
-                            {
-                                curr_pc_range = (pc_range_tmp.0, pc_range_tmp.1);
-                                curr_loc_info = Some((*loc_info).clone());
-                                curr_i = Some(i);
-                            }
-                        } else {
-                            eprintln!("l and h didn't satisfy constraints!");
-                        }
-                    }
-                }
-                if let Some(curr_i) = curr_i {
-                    vec_loc_info.remove(curr_i);
-                }
-                (curr_loc_info, curr_pc_range)
+            skip(&mut outcomes, function, mapping, Some(var), err);
+            continue;
+        }
+        /* A register location holds a value of the size of the variable, or of its member */
+        if new_expr.is_register() {
+            let bit_size = match piece {
+                Some((piece, _)) => Ok(piece.bit_size),
+                None => get_var_bit_size(unit, var),
             };
-            /*
-            let vec_loc_info = var_map.remove(var_name);
-            let (loc_info, pc_range_tmp) = &vec_loc_info.unwrap()[0];
-            */
-
-            if let Some(loc_info) = loc_info {
-                pc_range = pc_range_tmp;
-                eprintln!("Calling one of the process_locations..");
-                if let LocationInfo::LocList(loclist_vec) = loc_info {
-                    for (_i, loclist_entry) in loclist_vec.iter().enumerate() {
-                        process_location(
-                            &loclist_entry.data,
-                            loclist_entry.range.begin,
-                            loclist_entry.range.end,
-                        );
-                    }
-                } else if let LocationInfo::Loc(location) = loc_info {
-                    process_read_location(
-                        &location.expression,
-                        location.begin,
-                        /* See if below line is correct -- it corresponds to use of low_pc + high_pc or
-                         * just high_pc somewhere above in the code*/
-                        get_addr(addresses.get(location.begin as usize)) + location.end,
-                    );
-                    read_location = true;
-                } else if let LocationInfo::WLoc(location) = loc_info {
-                    process_write_location(
-                        &location.expression,
-                        location.begin,
-                        /* See if below line is correct -- it corresponds to use of low_pc + high_pc or
-                         * just high_pc somewhere above in the code*/
-                        get_addr(addresses.get(location.begin as usize)) + location.end,
-                    );
-                    const_value_attr = true;
-                } else if let LocationInfo::IntLocList(loclist_vec) = loc_info {
-                    for loc_entry in loclist_vec.iter() {
-                        if let write::Location::StartEnd { begin, end, data } = loc_entry {
-                            /*let mut b = None;
-                            let mut e = None;
-                            if let write::Address::Constant(begin_const) = begin {
-                                b = Some(*begin_const);
-                            }
-                            if let write::Address::Constant(end_const) = end {
-                                e = Some(*end_const);
-                            }
-                            if b != None && e != None {
-                                process_int_write_location(
-                                    &data,
-                                    b.unwrap(),
-                                    e.unwrap(),
-                                );
-                            }*/
-                            process_int_write_location(&data, *begin, *end);
-                        }
-                    }
-                    int_loc_list = true;
-                }
+            if let Err(reason) = bit_size.and_then(|bit_size| new_expr.set_bit_size(bit_size)) {
+                let err = RewriteError::InvalidExpression {
+                    line: line_no,
+                    expression: mapping.expression.clone(),
+                    reason,
+                };
+                skip(&mut outcomes, function, mapping, Some(var), err);
+                continue;
             }
         }
-
-        let new_loc = gimli::write::Location::StartEnd {
-            begin: write::Address::Constant(start),
-            end: write::Address::Constant(end),
-            data: new_dwarf_expr,
-        };
-        /* Rust tricks - to separate out the mutable new_loc_list, using two different loc_lists
-         * for read and write */
-        if const_value_attr {
-            println!("got into new_write_loc_list..");
-            new_write_loc_list.push(new_loc);
-            if let Some(v) = var_map.get_mut(&var_name.to_string()) {
-                v.push((
-                    LocationInfo::IntLocList(new_write_loc_list.clone()),
-                    pc_range,
-                ));
-            } else {
-                var_map.insert(
-                    var_name.to_string(),
-                    vec![(
-                        LocationInfo::IntLocList(new_write_loc_list.clone()),
-                        pc_range,
-                    )],
-                );
+        let var_location = &vars.vars[&var];
+        if let Some((var_low, var_high)) = var_location.scope {
+            debug!(target: "rewrite::splice",
+                "start: {}, end: {}, var_low: {}, var_high: {}",
+                start, end, var_low, var_high
+            );
+            if start < var_low {
+                start = var_low;
             }
-        } else if read_location {
-            println!("got into new_read_loc_list..");
-            new_read_loc_list.push(new_loc);
-            if let Some(v) = var_map.get_mut(&var_name.to_string()) {
-                v.push((
-                    LocationInfo::IntLocList(new_read_loc_list.clone()),
-                    pc_range,
-                ));
-            } else {
-                var_map.insert(
-                    var_name.to_string(),
-                    vec![(
-                        LocationInfo::IntLocList(new_read_loc_list.clone()),
-                        pc_range,
-                    )],
-                );
+            if end > var_high {
+                end = var_high;
             }
-        } else if int_loc_list {
-            println!("got into int_loc_list..");
-            new_int_write_loc_list.push(new_loc);
-            if let Some(v) = var_map.get_mut(&var_name.to_string()) {
-                v.push((
-                    LocationInfo::IntLocList(new_int_write_loc_list.clone()),
-                    pc_range,
-                ));
-            } else {
-                var_map.insert(
-                    var_name.to_string(),
-                    vec![(
-                        LocationInfo::IntLocList(new_int_write_loc_list.clone()),
-                        pc_range,
-                    )],
-                );
+            /* A mapping entirely outside of the scope of the variable has nothing left to set */
+            if start >= end {
+                let err = RewriteError::RangeInversion {
+                    line: line_no,
+                    begin: start,
+                    end,
+                };
+                skip(&mut outcomes, function, mapping, Some(var), err);
+                continue;
             }
-        } else {
-            println!("got into new_loc_list..");
-            new_loc_list.push(new_loc);
-            if let Some(v) = var_map.get_mut(&var_name.to_string()) {
-                println!("push into new_loc_list..");
-                v.push((LocationInfo::IntLocList(new_loc_list.clone()), pc_range));
-            } else {
-                println!("insert into new_loc_list..");
-                var_map.insert(
-                    var_name.to_string(),
-                    vec![(LocationInfo::IntLocList(new_loc_list.clone()), pc_range)],
-                );
+        }
+
+        /* The mapping is spliced into the locations of the variable along with its other
+         * mappings */
+        let group = *group_index.entry((function, var)).or_insert_with(|| {
+            groups.push(VarMappings {
+                function,
+                var_name: var_name.to_string(),
+                var,
+                overrides: Vec::new(),
+                pieces: Vec::new(),
+                var_size: 0,
+                piece_outcomes: Vec::new(),
+                lines: HashMap::new(),
+            });
+            groups.len() - 1
+        });
+        let group = &mut groups[group];
+        let key = (start, end, piece.map(|(piece, _)| piece));
+        if let Some(&(index, other_line)) = group.lines.get(&key) {
+            let same = match piece {
+                None => group.overrides[index].expression == new_expr.to_expression(),
+                Some(_) => group.pieces[index].expression == new_expr,
+            };
+            if !same {
+                let err = RewriteError::ConflictingMappings {
+                    line: line_no,
+                    other_line,
+                    variable: mapping.variable.clone(),
+                    begin: mapping.pc_range.0,
+                    end: mapping.pc_range.1,
+                };
+                skip(&mut outcomes, function, mapping, Some(var), err);
+                continue;
+            }
+            let reason = other_line.map(|other_line| format!("same as line {}", other_line));
+            outcomes.push((
+                function,
+                Some(var),
+                MappingReport::new(mapping, MappingStatus::Duplicate, reason),
+            ));
+            continue;
+        }
+        match piece {
+            None => {
+                group.lines.insert(key, (group.overrides.len(), line_no));
+                group.overrides.push(Override {
+                    begin: start,
+                    end,
+                    expression: new_expr.to_expression(),
+                });
+            }
+            Some((piece, var_size)) => {
+                group.lines.insert(key, (group.pieces.len(), line_no));
+                group.pieces.push(PieceOverride {
+                    begin: start,
+                    end,
+                    piece,
+                    expression: new_expr,
+                });
+                group.var_size = var_size;
+                group.piece_outcomes.push(outcomes.len());
             }
         }
+        outcomes.push((
+            function,
+            Some(var),
+            MappingReport::new(mapping, MappingStatus::Applied, None),
+        ));
+    }
 
-        let locations = &mut unit.locations;
-        let new_loc_list_id = if const_value_attr {
-            locations.add(write::LocationList(new_write_loc_list))
-        } else if read_location {
-            locations.add(write::LocationList(new_read_loc_list))
-        } else if int_loc_list {
-            //println!("intermediate loc list : {:?}", new_int_write_loc_list);
-            locations.add(write::LocationList(new_int_write_loc_list))
-        } else {
-            locations.add(write::LocationList(new_loc_list))
-        };
+    /* The location list of each variable is built once, from all its mappings. The variables
+     * are taken in the order of their entries, whatever the order of the mappings. */
+    let mut entry_orders = HashMap::new();
+    for group in &groups {
+        let unit_index = functions[&group.function].unit_index;
+        entry_orders
+            .entry(unit_index)
+            .or_insert_with(|| get_entry_order(dwarf.units.get(dwarf.units.id(unit_index))));
+    }
+    groups.sort_by_key(|group| {
+        let unit_index = functions[&group.function].unit_index;
+        (unit_index, entry_orders[&unit_index][&group.var])
+    });
+    for group in groups {
+        let vars = &functions[&group.function];
+        let var_name = &group.var_name;
+        let var_location = &vars.vars[&group.var];
+        let mut locations = var_location.locations.clone()?;
+        let mut overrides = group.overrides;
+        let (composed, dropped) = compose(&group.pieces, group.var_size, &var_location.existing);
+        overrides.extend(composed);
+        /* The mappings of the pieces left out were not applied after all */
+        for (index, reason) in dropped {
+            let mapping = &outcomes[group.piece_outcomes[index]].2;
+            let err = match reason {
+                Dropped::Overlap(other) => {
+                    let other = &outcomes[group.piece_outcomes[other]].2;
+                    RewriteError::OverlappingMembers {
+                        line: mapping.line,
+                        other_line: other.line,
+                        variable: mapping.variable.clone(),
+                        other: other.variable.clone(),
+                    }
+                }
+                Dropped::Unsplittable => RewriteError::UnsplittableLocation {
+                    line: mapping.line,
+                    variable: mapping.variable.clone(),
+                },
+            };
+            warn!(target: "rewrite::splice", "{}! Skipping..", err);
+            let mapping = &mut outcomes[group.piece_outcomes[index]].2;
+            mapping.status = MappingStatus::Skipped;
+            mapping.reason = Some(err.to_string());
+        }
+        locations.set_all(overrides);
+
+        let unit = dwarf.units.get_mut(dwarf.units.id(vars.unit_index));
+        let new_loc_list_id = unit.locations.add(rebase_location_list(
+            locations.to_locations(),
+            vars.unit_base,
+            compact,
+        ));
         let attr_val = write::AttributeValue::LocationListRef(new_loc_list_id);
-        let var_loc = get_var_loc(unit, &var.unwrap());
+        let var_loc = get_var_loc(unit, &group.var);
         if var_loc == None {
-            //println!("Variable: {} has no location attribute available! Skipping for now..(TODO: Create a new attribute)", var_name);
-            println!("[LOG]: Trying to add a new attribute - DW_AT_location..");
-            let var_die = get_die(unit, &var.unwrap());
+            debug!(target: "rewrite::splice", "Trying to add a new attribute - DW_AT_location..");
+            let var_die = get_die(unit, &group.var);
             var_die.set(DW_AT_location, attr_val);
-            println!(
-                "[LOG]: New Variable: {} location attribute added successfully!",
+            info!(target: "rewrite::splice",
+                "New Variable: {} location attribute added successfully!",
                 var_name
             );
         } else {
             let var_loc = var_loc.unwrap();
             var_loc.set(attr_val);
-            println!(
-                "[LOG]: Variable: {} location attribute changed successfully!",
+            info!(target: "rewrite::splice",
+                "Variable: {} location attribute changed successfully!",
                 var_name
             );
         }
         /* Rust tricks - changing the ordering - Moved the below block after above get_die()
          * block */
         {
-            if const_value_attr {
-                let var_die = get_die(unit, &var.unwrap());
+            if var_location.const_value {
+                let var_die = get_die(unit, &group.var);
                 var_die.delete(DW_AT_const_value);
             }
-            if let Some(true) = var_empty_scope.get(&var_name.to_string()) {
-                let parent_die = get_die(unit, &parent.unwrap());
-                if parent_die.tag().static_string() == Some("DW_TAG_lexical_block") {
-                    parent_die.delete(gimli::DW_AT_ranges);
+            if var_location.empty_scope {
+                if let Some(parent) = unit.get(group.var).parent() {
+                    let parent_die = get_die(unit, &parent);
+                    if parent_die.tag().static_string() == Some("DW_TAG_lexical_block") {
+                        parent_die.delete(gimli::DW_AT_ranges);
+                    }
                 }
             }
         }
     }
 
-    // TODO: only add relocations for relocatable files
+    let new_ids = match options.dwarf_version {
+        Some(version) => upgrade_units(&mut dwarf.units, version, &pinned_units),
+        None => HashMap::new(),
+    };
+    let (sections, offsets) = write_sections(&mut dwarf)?;
+
+    if let Some(report) = report {
+        let new_locations =
+            read_new_locations(&sections, &offsets, &new_ids, &dwarf, &outcomes, &functions)?;
+        fill_report(report, outcomes, &functions, &new_locations);
+    }
+    Ok(sections)
+}
+
+/* Writes the sections as dwarf.write() does, keeping the offsets of the entries to read the new
+ * locations back. The base addresses of the location lists of version 5 are indexed into
+ * .debug_addr (see add_addr_bases). */
+fn write_sections(
+    dwarf: &mut write::Dwarf,
+) -> Result<(DwarfSections, write::DebugInfoOffsets), RewriteError> {
+    let addr_base_units = add_addr_bases(&mut dwarf.units);
     let mut sections = write::Sections::new(WriterRelocate::new(EndianVec::new(LittleEndian)));
-    dwarf.write(&mut sections).unwrap();
-    let mut section_symbols = HashMap::new();
+    let line_strings = dwarf.line_strings.write(&mut sections.debug_line_str)?;
+    let strings = dwarf.strings.write(&mut sections.debug_str)?;
+    let offsets = dwarf.units.write(&mut sections, &line_strings, &strings)?;
+    for line_program in &dwarf.line_programs {
+        line_program.write(
+            &mut sections.debug_line,
+            line_program.encoding(),
+            &line_strings,
+            &strings,
+        )?;
+    }
+    let root_offsets: Vec<_> = addr_base_units
+        .iter()
+        .map(|&unit_id| offsets.entry(unit_id, dwarf.units.get(unit_id).root()).0 as u64)
+        .collect();
+    let mut loclists = sections.debug_loclists.0.writer.take();
+    let (debug_addr, debug_addr_relocations) = index_base_addresses(
+        &mut loclists,
+        &mut sections.debug_loclists.0.relocations,
+        &mut sections.debug_info.0.relocations,
+        &root_offsets,
+    )?;
+    write::Writer::write(&mut sections.debug_loclists.0.writer, &loclists)?;
+    let mut sections = DwarfSections {
+        debug_addr: WriterRelocate {
+            relocations: debug_addr_relocations,
+            writer: EndianVec::new(LittleEndian),
+        },
+        sections,
+    };
+    write::Writer::write(&mut sections.debug_addr.writer, &debug_addr)?;
+    Ok((sections, offsets))
+}
 
-    let _: Result<(), gimli::Error> = sections.for_each_mut(|id, w| {
-        define(
-            id,
-            out_object,
-            &mut section_symbols,
-            symbols,
-            w.writer.take(),
-            &w.relocations,
-        );
+/* Reads back the locations written for the variables which mappings were applied to, keyed by
+ * the index of their unit and their entry. new_ids are the ids of the entries of the upgraded
+ * units. */
+fn read_new_locations<'a>(
+    sections: &DwarfSections,
+    offsets: &write::DebugInfoOffsets,
+    new_ids: &HashMap<(UnitId, UnitEntryId), UnitEntryId>,
+    dwarf: &write::Dwarf,
+    outcomes: &[Outcome<'a>],
+    functions: &HashMap<FunctionKey<'a>, FunctionVars<UnitEntryId>>,
+) -> Result<HashMap<(usize, UnitEntryId), Vec<LocationReport>>, RewriteError> {
+    let mut data = HashMap::new();
+    sections.for_each(|id, w| -> Result<(), RewriteError> {
+        data.insert(id, resolve_relocations(w));
         Ok(())
-    });
+    })?;
+    let no_section: &[u8] = &[];
+    let get_section = |id| -> Result<_, RewriteError> {
+        let data = data.get(&id).map_or(no_section, Vec::as_slice);
+        Ok(EndianSlice::new(data, LittleEndian))
+    };
+    let read_dwarf = read::Dwarf::load(get_section, |_| {
+        Ok(EndianSlice::new(no_section, LittleEndian))
+    })?;
+
+    let mut locations = HashMap::new();
+    for (function, var, mapping) in outcomes {
+        let var = match var {
+            Some(var) if mapping.status == MappingStatus::Applied => *var,
+            _ => continue,
+        };
+        let unit_index = functions[function].unit_index;
+        if locations.contains_key(&(unit_index, var)) {
+            continue;
+        }
+        let unit_id = dwarf.units.id(unit_index);
+        let unit_offset = offsets.unit(unit_id);
+        let new_id = new_ids.get(&(unit_id, var)).copied().unwrap_or(var);
+        let entry_offset = offsets.entry(unit_id, new_id);
+        let unit = read_dwarf.unit(read_dwarf.debug_info.header_from_offset(unit_offset)?)?;
+        let entry = unit.entry(read::UnitOffset(entry_offset.0 - unit_offset.0))?;
+        let mut entries = Vec::new();
+        if let Some(read::AttributeValue::LocationListsRef(offset)) =
+            entry.attr_value(DW_AT_location)?
+        {
+            let mut location_list = read_dwarf.locations(&unit, offset)?;
+            while let Some(location) = location_list.next()? {
+                entries.push(LocationReport {
+                    begin: location.range.begin,
+                    end: location.range.end,
+                    expression: format_expression(location.data, unit.encoding(), &|address| {
+                        address
+                    }),
+                });
+            }
+        }
+        locations.insert((unit_index, var), entries);
+    }
+    Ok(locations)
+}
 
-    /*
-    let frame = write::FrameTable::from(&eh_frame, &convert_address).unwrap();
-    let mut out_eh_frame = write::EhFrame(WriterRelocate::new(EndianVec::new(LittleEndian)));
-    frame.write_eh_frame(&mut out_eh_frame).unwrap();
-    define(
-        gimli::SectionId::EhFrame,
-        out_object,
-        &mut section_symbols,
-        symbols,
-        out_eh_frame.0.writer.take(),
-        &out_eh_frame.0.relocations,
-    );
-    */
+/* The data written to a section with its relocations resolved to their addends, so that its
+ * addresses read as get_addr() gives those of the input */
+fn resolve_relocations(w: &WriterRelocate<EndianVec<LittleEndian>>) -> Vec<u8> {
+    let mut data = w.writer.slice().to_vec();
+    for relocation in &w.relocations {
+        let (offset, addend, size) = match *relocation {
+            Relocation::Section {
+                offset,
+                addend,
+                size,
+                ..
+            }
+            | Relocation::Symbol {
+                offset,
+                addend,
+                size,
+                ..
+            } => (offset as usize, addend, size as usize),
+        };
+        let value = (addend as u64).to_le_bytes();
+        data[offset..offset + size].copy_from_slice(&value[..size]);
+    }
+    data
+}
+
+/* Records each mapping under its function and variable, with the locations of the variable
+ * before and after the rewrite, their PCs as offsets from the start of the function */
+fn fill_report<'a>(
+    report: &mut RewriteReport,
+    outcomes: Vec<Outcome<'a>>,
+    functions: &HashMap<FunctionKey<'a>, FunctionVars<UnitEntryId>>,
+    new_locations: &HashMap<(usize, UnitEntryId), Vec<LocationReport>>,
+) {
+    for (function, var, mapping) in outcomes {
+        let vars = &functions[&function];
+        let function_report = report.function(function.0);
+        let var = match var {
+            Some(var) => var,
+            None => {
+                function_report.unresolved.push(mapping);
+                continue;
+            }
+        };
+        let var_location = &vars.vars[&var];
+        let index = match function_report
+            .variables
+            .iter()
+            .position(|other| other.offset == var_location.offset)
+        {
+            Some(index) => index,
+            None => {
+                let relative = |locations: &[LocationReport]| -> Vec<LocationReport> {
+                    locations
+                        .iter()
+                        .map(|location| LocationReport {
+                            begin: location.begin.wrapping_sub(vars.start),
+                            end: location.end.wrapping_sub(vars.start),
+                            expression: location.expression.clone(),
+                        })
+                        .collect()
+                };
+                let before = relative(&var_location.before);
+                let (changed, after) = match new_locations.get(&(vars.unit_index, var)) {
+                    Some(after) => (true, relative(after)),
+                    None => (false, before.clone()),
+                };
+                function_report.variables.push(VariableReport {
+                    name: var_location.name.clone(),
+                    offset: var_location.offset,
+                    changed,
+                    before,
+                    after,
+                    mappings: Vec::new(),
+                });
+                function_report.variables.len() - 1
+            }
+        };
+        function_report.variables[index].mappings.push(mapping);
+    }
+    report.count();
 }
 
 fn define(
@@ -1786,9 +2003,9 @@ fn define(
     symbols: &HashMap<SymbolIndex, object_write::SymbolId>,
     data: Vec<u8>,
     relocations: &[Relocation],
-) {
+) -> Result<(), RewriteError> {
     if data.is_empty() {
-        return;
+        return Ok(());
     }
 
     let section_id = out_object.add_section(
@@ -1800,16 +2017,18 @@ fn define(
     section.set_data(data, 1);
     let symbol_id = out_object.section_symbol(section_id);
     section_symbols.insert(id, symbol_id);
-    for relocation in link(section_symbols, symbols, relocations) {
-        out_object.add_relocation(section_id, relocation).unwrap();
+    for relocation in link(out_object, section_symbols, symbols, relocations)? {
+        out_object.add_relocation(section_id, relocation)?;
     }
+    Ok(())
 }
 
 fn link(
+    out_object: &object_write::Object,
     section_symbols: &HashMap<gimli::SectionId, object_write::SymbolId>,
     symbols: &HashMap<SymbolIndex, object_write::SymbolId>,
     relocations: &[Relocation],
-) -> Vec<object_write::Relocation> {
+) -> Result<Vec<object_write::Relocation>, RewriteError> {
     let mut out_relocations = Vec::new();
     for reloc in relocations {
         match *reloc {
@@ -1822,7 +2041,7 @@ fn link(
                 let symbol = match section_symbols.get(&section) {
                     Some(s) => *s,
                     None => {
-                        eprintln!("Missing section {}", section.name());
+                        warn!(target: "rewrite::reloc", "Missing section {}", section.name());
                         continue;
                     }
                 };
@@ -1842,55 +2061,86 @@ fn link(
                 kind,
                 size,
             } => {
-                let symbol = *symbols.get(&symbol).unwrap();
+                let symbol = match symbols.get(&symbol) {
+                    Some(symbol) => *symbol,
+                    None => {
+                        return Err(RewriteError::UnsupportedObject(format!(
+                            "relocation against symbol {} which is not copied",
+                            symbol.0
+                        )))
+                    }
+                };
+                /* The addend read back holds the symbol value too (see get_section), which is
+                 * only zero for section symbols. RISC-V relocates against local labels. */
+                let mut addend = addend as i64;
+                let out_symbol = out_object.symbol(symbol);
+                if out_symbol.kind != object::SymbolKind::Section {
+                    addend -= out_symbol.value as i64;
+                }
                 out_relocations.push(object_write::Relocation {
                     offset,
                     size: size * 8,
                     kind,
                     encoding: object::RelocationEncoding::Generic,
                     symbol,
-                    addend: addend as i64,
+                    addend,
                 });
             }
         }
     }
-    out_relocations
+    Ok(out_relocations)
 }
 
 pub fn is_rewrite_dwarf_section(section: &object::Section<'_, '_>) -> bool {
     if let Ok(name) = section.name() {
-        if name.starts_with(".debug_") {
-            match name {
-                ".debug_aranges" | ".debug_abbrev" | ".debug_addr" | ".debug_info"
-                | ".debug_line" | ".debug_line_str" | ".debug_loc" | ".debug_loclists"
-                | ".debug_pubnames" | ".debug_pubtypes" | ".debug_ranges" | ".debug_rnglists"
-                | ".debug_str" | ".debug_str_offsets" => {
-                    return true;
-                }
-                _ => return false,
+        return is_rewrite_dwarf_section_name(name);
+    }
+    false
+}
+
+pub fn is_rewrite_dwarf_section_name(name: &str) -> bool {
+    if name.starts_with(".debug_") {
+        match name {
+            ".debug_aranges" | ".debug_abbrev" | ".debug_addr" | ".debug_info" | ".debug_line"
+            | ".debug_line_str" | ".debug_loc" | ".debug_loclists" | ".debug_pubnames"
+            | ".debug_pubtypes" | ".debug_ranges" | ".debug_rnglists" | ".debug_str"
+            | ".debug_str_offsets" => {
+                return true;
             }
+            _ => return false,
         }
-        /*
-        if name == ".eh_frame" {
-            return true;
-        }
-        */
     }
+    /*
+    if name == ".eh_frame" {
+        return true;
+    }
+    */
     false
 }
 
-type ReadRelocationMap = HashMap<usize, object::Relocation>;
+/* A relocation of a DWARF section being read, against symbol. The addend holds the value of the
+ * symbol too. Only absolute relocations relocate offsets, addresses may also be PC-relative. */
+#[derive(Debug, Clone, Copy)]
+struct ReadRelocation {
+    symbol: usize,
+    addend: i64,
+    implicit_addend: bool,
+    absolute: bool,
+}
+
+type ReadRelocationMap = HashMap<usize, ReadRelocation>;
 
 fn get_section<'data>(
     file: &object::File<'data>,
     name: &str,
-) -> (Cow<'data, [u8]>, ReadRelocationMap) {
+) -> Result<(Cow<'data, [u8]>, ReadRelocationMap), RewriteError> {
     let mut relocations = ReadRelocationMap::default();
     let section = match file.section_by_name(name) {
         Some(section) => section,
-        None => return (Cow::Borrowed(&[][..]), relocations),
+        None => return Ok((Cow::Borrowed(&[][..]), relocations)),
     };
-    for (offset64, mut relocation) in section.relocations() {
+    let mut data = section.uncompressed_data()?;
+    for (offset64, relocation) in section.relocations() {
         let offset = offset64 as usize;
         if offset as u64 != offset64 {
             continue;
@@ -1899,20 +2149,32 @@ fn get_section<'data>(
         match relocation.kind() {
             object::RelocationKind::Absolute | object::RelocationKind::Relative => {
                 match relocation.target() {
-                    object::RelocationTarget::Symbol(symbol) => {
-                        if let Ok(symbol) = file.symbol_by_index(symbol) {
+                    object::RelocationTarget::Symbol(index) => {
+                        if let Ok(symbol) = file.symbol_by_index(index) {
                             let addend = symbol.address().wrapping_add(relocation.addend() as u64);
-                            relocation.set_addend(addend as i64);
-                            println!("Adding reloc {} {:?}", offset, relocation);
+                            let relocation = ReadRelocation {
+                                symbol: index.0,
+                                addend: addend as i64,
+                                implicit_addend: relocation.has_implicit_addend(),
+                                absolute: relocation.kind() == object::RelocationKind::Absolute,
+                            };
+                            trace!(
+                                target: "rewrite::reloc",
+                                "Adding reloc {} {:?}",
+                                offset,
+                                relocation
+                            );
                             if relocations.insert(offset, relocation).is_some() {
-                                println!(
+                                warn!(
+                                    target: "rewrite::reloc",
                                     "Multiple relocations for section {} at offset 0x{:08x}",
                                     section.name().unwrap(),
                                     offset
                                 );
                             }
                         } else {
-                            println!(
+                            warn!(
+                                target: "rewrite::reloc",
                                 "Relocation with invalid symbol for section {} at offset 0x{:08x}",
                                 section.name().unwrap(),
                                 offset
@@ -1920,7 +2182,8 @@ fn get_section<'data>(
                         }
                     }
                     _ => {
-                        println!(
+                        warn!(
+                            target: "rewrite::reloc",
                             "Unsupported relocation target for section {} at offset 0x{:08x}",
                             section.name().unwrap(),
                             offset
@@ -1928,8 +2191,19 @@ fn get_section<'data>(
                     }
                 }
             }
+            object::RelocationKind::Elf(r_type) if is_riscv_add_sub(file, r_type) => {
+                if !apply_riscv_add_sub(file, data.to_mut(), offset, &relocation) {
+                    warn!(
+                        target: "rewrite::reloc",
+                        "Unsupported relocation for section {} at offset 0x{:08x}",
+                        section.name().unwrap(),
+                        offset
+                    );
+                }
+            }
             _ => {
-                println!(
+                warn!(
+                    target: "rewrite::reloc",
                     "Unsupported relocation kind for section {} at offset 0x{:08x}",
                     section.name().unwrap(),
                     offset
@@ -1938,8 +2212,60 @@ fn get_section<'data>(
         }
     }
 
-    let data = section.uncompressed_data().unwrap();
-    (data, relocations)
+    Ok((data, relocations))
+}
+
+fn is_riscv_add_sub(file: &object::File, r_type: u32) -> bool {
+    match file.architecture() {
+        object::Architecture::Riscv32 | object::Architecture::Riscv64 => {
+            (object::elf::R_RISCV_ADD8..=object::elf::R_RISCV_SUB64).contains(&r_type)
+        }
+        _ => false,
+    }
+}
+
+/* RISC-V objects describe label differences (e.g. DW_AT_high_pc and location list bounds) as a
+ * pair of ADD/SUB relocations against a zero field, since linker relaxation may still move the
+ * labels. Fold each of them into the section data so that the field reads as the difference. */
+fn apply_riscv_add_sub(
+    file: &object::File,
+    data: &mut [u8],
+    offset: usize,
+    relocation: &object::Relocation,
+) -> bool {
+    let symbol = match relocation.target() {
+        object::RelocationTarget::Symbol(symbol) => match file.symbol_by_index(symbol) {
+            Ok(symbol) => symbol,
+            Err(_) => return false,
+        },
+        _ => return false,
+    };
+    let r_type = match relocation.kind() {
+        object::RelocationKind::Elf(r_type) => r_type,
+        _ => return false,
+    };
+    /* object leaves the size of these relocations unset; the type encodes it instead */
+    let size = match r_type {
+        object::elf::R_RISCV_ADD8 | object::elf::R_RISCV_SUB8 => 1,
+        object::elf::R_RISCV_ADD16 | object::elf::R_RISCV_SUB16 => 2,
+        object::elf::R_RISCV_ADD32 | object::elf::R_RISCV_SUB32 => 4,
+        _ => 8,
+    };
+    let field = match data.get_mut(offset..offset + size) {
+        Some(field) => field,
+        None => return false,
+    };
+    let value = symbol.address().wrapping_add(relocation.addend() as u64);
+    let mut bytes = [0; 8];
+    bytes[..size].copy_from_slice(field);
+    let old = u64::from_le_bytes(bytes);
+    let new = if r_type >= object::elf::R_RISCV_SUB8 {
+        old.wrapping_sub(value)
+    } else {
+        old.wrapping_add(value)
+    };
+    field.copy_from_slice(&new.to_le_bytes()[..size]);
+    true
 }
 
 // gimli::read::Reader::read_address() returns u64, but gimli::write data structures wants
@@ -1985,43 +2311,28 @@ struct ReaderRelocate<'a, R: read::Reader<Offset = usize>> {
 
 impl<'a, R: read::Reader<Offset = usize>> ReaderRelocate<'a, R> {
     fn relocate(&self, offset: usize, value: u64) -> u64 {
-        if let Some(relocation) = self.relocations.get(&offset) {
-            match relocation.kind() {
-                object::RelocationKind::Absolute => {
-                    if relocation.has_implicit_addend() {
-                        // Use the explicit addend too, because it may have the symbol value.
-                        return value.wrapping_add(relocation.addend() as u64);
-                    } else {
-                        return relocation.addend() as u64;
-                    }
-                }
-                _ => {}
-            }
+        match self.relocations.get(&offset) {
+            Some(relocation) if relocation.absolute => get_relocated(relocation, value),
+            _ => value,
         }
-        value
     }
 
     fn relocate_address(&self, offset: usize, value: u64) -> Option<Address> {
-        if let Some(relocation) = self.relocations.get(&offset) {
-            let symbol = match relocation.target() {
-                object::RelocationTarget::Symbol(symbol) => symbol.0,
-                _ => unimplemented!(),
-            };
-            let addend = match relocation.kind() {
-                object::RelocationKind::Absolute | object::RelocationKind::Relative => {
-                    if relocation.has_implicit_addend() {
-                        // Use the explicit addend too, because it may have the symbol value.
-                        value.wrapping_add(relocation.addend() as u64) as i64
-                    } else {
-                        relocation.addend()
-                    }
-                }
-                _ => unimplemented!(),
-            };
-            Some(Address::Symbol { symbol, addend })
-        } else {
-            None
-        }
+        let relocation = self.relocations.get(&offset)?;
+        Some(Address::Symbol {
+            symbol: relocation.symbol,
+            addend: get_relocated(relocation, value) as i64,
+        })
+    }
+}
+
+/* The value a relocated field reads as */
+fn get_relocated(relocation: &ReadRelocation, value: u64) -> u64 {
+    if relocation.implicit_addend {
+        // Use the explicit addend too, because it may have the symbol value.
+        value.wrapping_add(relocation.addend as u64)
+    } else {
+        relocation.addend as u64
     }
 }
 
@@ -2032,33 +2343,29 @@ impl<'a, R: read::Reader<Offset = usize>> read::Reader for ReaderRelocate<'a, R>
     fn read_address(&mut self, address_size: u8) -> read::Result<u64> {
         let offset = self.reader.offset_from(&self.section);
         let value = self.reader.read_address(address_size)?;
-        //println!("read_address {} {:x}", offset, value);
         let address = ReaderRelocate::relocate_address(self, offset, value)
             .unwrap_or(Address::Constant(value));
-        let addend = get_addr(address);
-        if value != addend {
-            eprintln!("value: {}, addend: {}", value, addend);
-        }
-        //println!("relocate_address {} {:?}", offset, address);
-        let ret = self.addresses.add(address) as u64;
-        if value == 0x4b6 || ret == 64 {
-            eprintln!("value: {:x}, index: {}", value, ret);
-        }
-        //println!("index: {}", ret);
-        Ok(ret)
+        let index = self.addresses.add(address) as u64;
+        trace!(
+            target: "rewrite::reloc",
+            "Address 0x{:x} at offset {} read as {:?}, index {}",
+            value,
+            offset,
+            address,
+            index
+        );
+        Ok(index)
     }
 
     fn read_offset(&mut self, format: gimli::Format) -> read::Result<usize> {
         let offset = self.reader.offset_from(&self.section);
         let value = self.reader.read_offset(format)?;
-        //println!("read_offset {} {}", offset, value);
         <usize as read::ReaderOffset>::from_u64(self.relocate(offset, value as u64))
     }
 
     fn read_sized_offset(&mut self, size: u8) -> read::Result<usize> {
         let offset = self.reader.offset_from(&self.section);
         let value = self.reader.read_sized_offset(size)?;
-        //println!("read_sized_offset {} {}", offset, value);
         <usize as read::ReaderOffset>::from_u64(self.relocate(offset, value as u64))
     }
 
@@ -2197,7 +2504,7 @@ impl<W: write::Writer> write::Writer for WriterRelocate<W> {
                 self.relocations.push(Relocation::Symbol {
                     offset,
                     symbol: SymbolIndex(symbol),
-                    addend: addend as i32,
+                    addend: i32::try_from(addend).map_err(|_| write::Error::ValueTooLarge)?,
                     kind: object::RelocationKind::Absolute,
                     size,
                 });
@@ -2212,7 +2519,7 @@ impl<W: write::Writer> write::Writer for WriterRelocate<W> {
         eh_pe: gimli::DwEhPe,
         _size: u8,
     ) -> write::Result<()> {
-        println!("write_eh_pointer {} {:?}", self.len(), address);
+        trace!(target: "rewrite::reloc", "write_eh_pointer {} {:?}", self.len(), address);
         match (address, eh_pe.application(), eh_pe.format()) {
             (Address::Constant(value), gimli::DW_EH_PE_absptr, gimli::DW_EH_PE_sdata4) => {
                 self.write_u32(value as u32)
@@ -2222,13 +2529,13 @@ impl<W: write::Writer> write::Writer for WriterRelocate<W> {
                 self.relocations.push(Relocation::Symbol {
                     offset,
                     symbol: SymbolIndex(symbol),
-                    addend: addend as i32,
+                    addend: i32::try_from(addend).map_err(|_| write::Error::ValueTooLarge)?,
                     kind: object::RelocationKind::Relative,
                     size: 4,
                 });
                 self.write_u32(0)
             }
-            _ => unimplemented!("{:?} {:?}", address, eh_pe),
+            _ => Err(write::Error::UnsupportedPointerEncoding(eh_pe)),
         }
     }
 
@@ -2264,3 +2571,245 @@ impl<W: write::Writer> write::Writer for WriterRelocate<W> {
         self.write_udata_at(offset, 0, size)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gimli::write::{LineProgram, Location, LocationList};
+    use gimli::{Encoding, Format};
+
+    /* The base address and range of the location list of each variable */
+    const LISTS: [(u64, u64, u64); 3] = [(0x1000, 0, 4), (0x1000, 4, 8), (0x2000, 2, 6)];
+
+    /* A unit of version 5 with a variable per location list of LISTS, whose base addresses are
+     * given by address */
+    fn new_dwarf(address: impl Fn(u64) -> Address) -> write::Dwarf {
+        let mut dwarf = write::Dwarf::new();
+        let encoding = Encoding {
+            format: Format::Dwarf32,
+            version: 5,
+            address_size: 8,
+        };
+        let unit_id = dwarf.units.add(Unit::new(encoding, LineProgram::none()));
+        let unit = dwarf.units.get_mut(unit_id);
+        let root = unit.root();
+        for &(base, begin, end) in &LISTS {
+            let list = unit.locations.add(LocationList(vec![
+                Location::BaseAddress {
+                    address: address(base),
+                },
+                Location::OffsetPair {
+                    begin,
+                    end,
+                    data: write::Expression::raw(vec![gimli::DW_OP_reg0.0]),
+                },
+            ]));
+            let var = unit.add(root, gimli::DW_TAG_variable);
+            unit.get_mut(var)
+                .set(DW_AT_location, AttributeValue::LocationListRef(list));
+        }
+        dwarf
+    }
+
+    /* The .debug_addr indices of the base addresses of a location list, and its ranges */
+    type ReadList = (Vec<u64>, Vec<(u64, u64)>);
+
+    /* Reads back the DW_AT_addr_base of the unit, with the .debug_addr index of the base address
+     * and the ranges of the location list of each variable */
+    fn read_locations(
+        data: &HashMap<gimli::SectionId, Vec<u8>>,
+    ) -> (gimli::DebugAddrBase, Vec<ReadList>) {
+        let no_section: &[u8] = &[];
+        let read_dwarf = read::Dwarf::load(
+            |id| -> Result<_, read::Error> {
+                let data = data.get(&id).map_or(no_section, Vec::as_slice);
+                Ok(EndianSlice::new(data, LittleEndian))
+            },
+            |_| Ok(EndianSlice::new(no_section, LittleEndian)),
+        )
+        .unwrap();
+        let header = read_dwarf.units().next().unwrap().unwrap();
+        let unit = read_dwarf.unit(header).unwrap();
+        let mut lists = Vec::new();
+        let mut entries = unit.entries();
+        while let Some((_, entry)) = entries.next_dfs().unwrap() {
+            let offset = match entry.attr_value(DW_AT_location).unwrap() {
+                Some(read::AttributeValue::LocationListsRef(offset)) => offset,
+                _ => continue,
+            };
+            let mut indices = Vec::new();
+            let mut raw_locations = read_dwarf
+                .locations
+                .raw_locations(offset, unit.encoding())
+                .unwrap();
+            while let Some(location) = raw_locations.next().unwrap() {
+                match location {
+                    read::RawLocListEntry::BaseAddressx { addr } => indices.push(addr.0 as u64),
+                    read::RawLocListEntry::BaseAddress { .. } => panic!("base address left"),
+                    _ => {}
+                }
+            }
+            let mut ranges = Vec::new();
+            let mut locations = read_dwarf.locations(&unit, offset).unwrap();
+            while let Some(location) = locations.next().unwrap() {
+                ranges.push((location.range.begin, location.range.end));
+            }
+            lists.push((indices, ranges));
+        }
+        (unit.addr_base, lists)
+    }
+
+    fn check_locations(data: &HashMap<gimli::SectionId, Vec<u8>>) {
+        let (addr_base, lists) = read_locations(data);
+        /* Past the header of .debug_addr */
+        assert_eq!(addr_base, gimli::DebugAddrBase(8));
+        let expected: Vec<_> = LISTS
+            .iter()
+            .map(|&(base, begin, end)| {
+                let index = if base == 0x1000 { 0 } else { 1 };
+                (vec![index], vec![(base + begin, base + end)])
+            })
+            .collect();
+        assert_eq!(lists, expected);
+        assert_eq!(
+            data[&gimli::SectionId::DebugAddr],
+            [
+                &20u32.to_le_bytes()[..],
+                &[5, 0, 8, 0],
+                &0x1000u64.to_le_bytes(),
+                &0x2000u64.to_le_bytes(),
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn base_addresses_are_indexed_when_relocatable() {
+        let mut dwarf = new_dwarf(|base| Address::Symbol {
+            symbol: 1,
+            addend: base as i64,
+        });
+        let (sections, _) = write_sections(&mut dwarf).unwrap();
+
+        /* The addresses are relocated in .debug_addr only, .debug_info refers to it */
+        assert!(sections.sections.debug_loclists.0.relocations.is_empty());
+        let addr_relocations: Vec<_> = sections
+            .debug_addr
+            .relocations
+            .iter()
+            .map(|relocation| match *relocation {
+                Relocation::Symbol {
+                    offset,
+                    symbol,
+                    addend,
+                    ..
+                } => (offset, symbol.0, addend),
+                Relocation::Section { .. } => panic!("section relocation in .debug_addr"),
+            })
+            .collect();
+        assert_eq!(addr_relocations, [(8, 1, 0x1000), (16, 1, 0x2000)]);
+        let addr_sections: Vec<_> = sections
+            .sections
+            .debug_info
+            .0
+            .relocations
+            .iter()
+            .filter_map(|relocation| match *relocation {
+                Relocation::Section {
+                    section: gimli::SectionId::DebugAddr,
+                    addend,
+                    ..
+                } => Some(addend),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(addr_sections, [8]);
+
+        let mut data = HashMap::new();
+        sections
+            .for_each(|id, w| -> Result<(), RewriteError> {
+                data.insert(id, resolve_relocations(w));
+                Ok(())
+            })
+            .unwrap();
+        check_locations(&data);
+    }
+
+    fn func_entry(
+        addresses: &ReadAddressMap,
+        unit_index: usize,
+        start: Option<u64>,
+        end_offset: Option<u64>,
+    ) -> FuncEntry<usize> {
+        FuncEntry {
+            unit_index,
+            unit_offset: gimli::DebugInfoOffset(0),
+            entry_offset: read::UnitOffset(0),
+            start_addr: start.map(|start| addresses.add(Address::Constant(start)) as u64),
+            end_offset,
+        }
+    }
+
+    #[test]
+    fn functions_of_several_units_are_told_apart_by_zero_address() {
+        let addresses = ReadAddressMap::default();
+        let mut func_entries = HashMap::new();
+        func_entries.insert(
+            "f".to_string(),
+            vec![
+                func_entry(&addresses, 0, None, None),
+                func_entry(&addresses, 1, Some(0x1000), Some(0x20)),
+                func_entry(&addresses, 2, Some(0x2000), Some(0x40)),
+            ],
+        );
+        func_entries.insert("g".to_string(), vec![func_entry(&addresses, 3, None, None)]);
+        func_entries.insert(
+            "h".to_string(),
+            vec![func_entry(&addresses, 4, Some(0x3000), None)],
+        );
+
+        let mapping = |function: &str, zero_address| Mapping {
+            function: function.to_string(),
+            variable: "x".to_string(),
+            expression: "%rax".to_string(),
+            pc_range: (0, 4),
+            zero_address,
+            line: None,
+        };
+        let unit_of = |function, zero_address| {
+            find_func_entry(&func_entries, &mapping(function, zero_address), &addresses)
+                .map(|entry| entry.unit_index)
+        };
+        assert_eq!(unit_of("f", Some(0x2000)).unwrap(), 2);
+        assert_eq!(unit_of("f", Some(0x1010)).unwrap(), 1);
+        /* Only a definition in several units needs the zero address */
+        assert_eq!(unit_of("g", None).unwrap(), 3);
+        assert_eq!(unit_of("h", Some(0)).unwrap(), 4);
+        assert!(matches!(
+            unit_of("f", Some(0x1020)),
+            Err(RewriteError::AmbiguousFunction { units: 2, .. })
+        ));
+        assert!(matches!(
+            unit_of("f", None),
+            Err(RewriteError::AmbiguousFunction { .. })
+        ));
+        assert!(matches!(
+            unit_of("i", None),
+            Err(RewriteError::UnknownFunction(_))
+        ));
+    }
+
+    #[test]
+    fn base_addresses_are_indexed_in_place() {
+        let mut dwarf = new_dwarf(Address::Constant);
+        let (mut sections, _) = write_sections(&mut dwarf).unwrap();
+        let mut data = HashMap::new();
+        sections
+            .for_each_mut(|id, w| -> Result<(), RewriteError> {
+                data.insert(id, resolve_in_place(id, w)?);
+                Ok(())
+            })
+            .unwrap();
+        check_locations(&data);
+    }
+}
@@ -0,0 +1,585 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::mem;
+
+use gimli::read::{self, EndianSlice, Reader, ReaderOffset};
+use gimli::write::{self, AttributeValue, Reference, Unit, UnitEntryId, UnitId, UnitTable};
+use gimli::{leb128, DebugInfoOffset, Encoding, LittleEndian};
+use log::warn;
+
+use crate::dwarf::Relocation;
+use crate::error::RewriteError;
+
+/* The units are upgraded by copying their entries into new units with the target encoding, as the
+ * encoding of a write::Unit is fixed. The location and range lists are moved over as they are and
+ * written out to .debug_loclists/.debug_rnglists, DW_OP_GNU_entry_value becoming
+ * DW_OP_entry_value. gimli cannot write .debug_addr, so the base addresses of the location lists
+ * are moved there once written (see index_base_addresses), the other addresses stay inline. The
+ * line programs keep their version. */
+
+pub fn check_dwarf_version(version: u16) -> Result<(), RewriteError> {
+    if version != 5 {
+        return Err(RewriteError::UnsupportedDwarfVersion(version));
+    }
+    Ok(())
+}
+
+/* Indices of the units which must keep their version. Expressions refer to entries (base types,
+ * implicit pointers, ...) through ids that write::Expression does not let us remap, so neither
+ * the units holding such expressions nor the units they point into can be copied. */
+pub fn find_pinned_units<R: Reader<Offset = usize>>(
+    dwarf: &read::Dwarf<R>,
+) -> Result<HashSet<usize>, RewriteError> {
+    let mut headers = Vec::new();
+    let mut units = dwarf.units();
+    while let Some(header) = units.next()? {
+        headers.push(header);
+    }
+    let unit_of = |offset: DebugInfoOffset| {
+        headers.iter().position(|header| {
+            offset.0 >= header.offset().0
+                && offset.0 < header.offset().0 + header.length_including_self()
+        })
+    };
+
+    let mut pinned = HashSet::new();
+    for (index, header) in headers.iter().enumerate() {
+        let unit = dwarf.unit(header.clone())?;
+        let mut references = References::default();
+        let mut entries = unit.entries();
+        while let Some((_, entry)) = entries.next_dfs()? {
+            let mut attrs = entry.attrs();
+            while let Some(attr) = attrs.next()? {
+                match attr.value() {
+                    read::AttributeValue::Exprloc(expression) => {
+                        references.add(expression, unit.encoding())?
+                    }
+                    read::AttributeValue::LocationListsRef(offset) => {
+                        let mut locations =
+                            dwarf.locations.raw_locations(offset, unit.encoding())?;
+                        while let Some(location) = locations.next()? {
+                            if let Some(expression) = raw_location_expression(location) {
+                                references.add(expression, unit.encoding())?;
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if references.unit {
+            pinned.insert(index);
+        }
+        for offset in references.debug_info {
+            pinned.insert(index);
+            if let Some(target) = unit_of(offset) {
+                pinned.insert(target);
+            }
+        }
+    }
+    Ok(pinned)
+}
+
+#[derive(Default)]
+struct References {
+    /* Whether there are references within the unit */
+    unit: bool,
+    debug_info: Vec<DebugInfoOffset>,
+}
+
+impl References {
+    fn add<R: Reader<Offset = usize>>(
+        &mut self,
+        expression: read::Expression<R>,
+        encoding: Encoding,
+    ) -> Result<(), RewriteError> {
+        let mut operations = expression.operations(encoding);
+        while let Some(operation) = operations.next()? {
+            match operation {
+                read::Operation::Call {
+                    offset: read::DieReference::DebugInfoRef(offset),
+                }
+                | read::Operation::ImplicitPointer { value: offset, .. } => {
+                    self.debug_info.push(offset)
+                }
+                read::Operation::Call { .. }
+                | read::Operation::ParameterRef { .. }
+                | read::Operation::TypedLiteral { .. } => self.unit = true,
+                read::Operation::Deref { base_type, .. }
+                | read::Operation::RegisterOffset { base_type, .. }
+                | read::Operation::Convert { base_type }
+                | read::Operation::Reinterpret { base_type }
+                    if base_type.0 != 0 =>
+                {
+                    self.unit = true
+                }
+                read::Operation::EntryValue { expression } => {
+                    self.add(read::Expression(expression), encoding)?
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+fn raw_location_expression<R: Reader>(
+    location: read::RawLocListEntry<R>,
+) -> Option<read::Expression<R>> {
+    match location {
+        read::RawLocListEntry::AddressOrOffsetPair { data, .. }
+        | read::RawLocListEntry::StartxEndx { data, .. }
+        | read::RawLocListEntry::StartxLength { data, .. }
+        | read::RawLocListEntry::OffsetPair { data, .. }
+        | read::RawLocListEntry::DefaultLocation { data }
+        | read::RawLocListEntry::StartEnd { data, .. }
+        | read::RawLocListEntry::StartLength { data, .. } => Some(data),
+        read::RawLocListEntry::BaseAddress { .. } | read::RawLocListEntry::BaseAddressx { .. } => {
+            None
+        }
+    }
+}
+
+/* Upgrades the units older than version, except the pinned ones (see find_pinned_units) and
+ * those whose root is not a compilation unit, which cannot be recreated. The entries of the
+ * upgraded units are recreated too, the new id of each is returned. */
+pub fn upgrade_units(
+    units: &mut UnitTable,
+    version: u16,
+    pinned: &HashSet<usize>,
+) -> HashMap<(UnitId, UnitEntryId), UnitEntryId> {
+    let mut entry_ids = HashMap::new();
+    let mut new_units = Vec::new();
+    for index in 0..units.count() {
+        let unit_id = units.id(index);
+        let unit = units.get_mut(unit_id);
+        if unit.version() >= version {
+            continue;
+        }
+        if pinned.contains(&index) || unit.get(unit.root()).tag() != gimli::DW_TAG_compile_unit {
+            warn!(
+                "unit {} cannot be upgraded, keeping DWARF {}",
+                index,
+                unit.version()
+            );
+            continue;
+        }
+
+        let encoding = Encoding {
+            version,
+            ..unit.encoding()
+        };
+        let line_program = mem::replace(&mut unit.line_program, write::LineProgram::none());
+        let mut new_unit = Unit::new(encoding, line_program);
+        new_unit.ranges = mem::take(&mut unit.ranges);
+        new_unit.locations = mem::take(&mut unit.locations);
+
+        let (root, new_root) = (unit.root(), new_unit.root());
+        let mut copied = vec![(root, new_root)];
+        copy_children(unit, root, &mut new_unit, new_root, &mut copied);
+        for &(id, new_id) in &copied {
+            entry_ids.insert((unit_id, id), new_id);
+        }
+        new_units.push((unit_id, new_unit, copied));
+    }
+
+    /* Attributes are copied once all the entries exist, for the references between units */
+    for (unit_id, new_unit, copied) in &mut new_units {
+        let unit = units.get(*unit_id);
+        for &(id, new_id) in copied.iter() {
+            let new_entry = new_unit.get_mut(new_id);
+            new_entry.set_sibling(unit.get(id).sibling());
+            for attr in unit.get(id).attrs() {
+                new_entry.set(attr.name(), remap(attr.get(), *unit_id, &entry_ids));
+            }
+        }
+    }
+    for index in 0..units.count() {
+        let unit_id = units.id(index);
+        if new_units.iter().any(|(id, _, _)| *id == unit_id) {
+            continue;
+        }
+        let unit = units.get_mut(unit_id);
+        let mut stack = vec![unit.root()];
+        while let Some(id) = stack.pop() {
+            let entry = unit.get_mut(id);
+            for attr in entry.attrs_mut() {
+                let value = remap(attr.get(), unit_id, &entry_ids);
+                attr.set(value);
+            }
+            stack.extend(entry.children());
+        }
+    }
+
+    for (unit_id, new_unit, _) in new_units {
+        *units.get_mut(unit_id) = new_unit;
+    }
+    entry_ids
+}
+
+/* Entries are added in the same order as in the original unit */
+fn copy_children(
+    unit: &Unit,
+    parent: UnitEntryId,
+    new_unit: &mut Unit,
+    new_parent: UnitEntryId,
+    copied: &mut Vec<(UnitEntryId, UnitEntryId)>,
+) {
+    for &child in unit.get(parent).children() {
+        let new_child = new_unit.add(new_parent, unit.get(child).tag());
+        copied.push((child, new_child));
+        copy_children(unit, child, new_unit, new_child, copied);
+    }
+}
+
+fn remap(
+    value: &AttributeValue,
+    unit_id: UnitId,
+    entry_ids: &HashMap<(UnitId, UnitEntryId), UnitEntryId>,
+) -> AttributeValue {
+    match *value {
+        AttributeValue::UnitRef(id) => match entry_ids.get(&(unit_id, id)) {
+            Some(&new_id) => AttributeValue::UnitRef(new_id),
+            None => value.clone(),
+        },
+        AttributeValue::DebugInfoRef(Reference::Entry(target_unit, id)) => {
+            match entry_ids.get(&(target_unit, id)) {
+                Some(&new_id) => {
+                    AttributeValue::DebugInfoRef(Reference::Entry(target_unit, new_id))
+                }
+                None => value.clone(),
+            }
+        }
+        _ => value.clone(),
+    }
+}
+
+/* Gives the root of each unit of version 5 with location lists a DW_AT_addr_base, for
+ * index_base_addresses to redirect to .debug_addr. gimli cannot write a reference to .debug_addr,
+ * so it is written as a reference to an empty location list, the first reference to
+ * .debug_loclists of the unit. The units given one are returned. */
+pub fn add_addr_bases(units: &mut UnitTable) -> Vec<UnitId> {
+    let mut addr_base_units = Vec::new();
+    for index in 0..units.count() {
+        let unit_id = units.id(index);
+        let unit = units.get_mut(unit_id);
+        if unit.version() < 5 || !has_location_lists(unit) {
+            continue;
+        }
+        let addr_base = unit.locations.add(write::LocationList(Vec::new()));
+        let root = unit.root();
+        unit.get_mut(root).set(
+            gimli::DW_AT_addr_base,
+            AttributeValue::LocationListRef(addr_base),
+        );
+        addr_base_units.push(unit_id);
+    }
+    addr_base_units
+}
+
+fn has_location_lists(unit: &Unit) -> bool {
+    let mut stack = vec![unit.root()];
+    while let Some(id) = stack.pop() {
+        let entry = unit.get(id);
+        if entry
+            .attrs()
+            .any(|attr| matches!(attr.get(), AttributeValue::LocationListRef(_)))
+        {
+            return true;
+        }
+        stack.extend(entry.children());
+    }
+    false
+}
+
+/* An address by its value and the symbol, addend and kind of its relocation */
+type AddressKey = (u64, Option<(usize, i32, object::RelocationKind)>);
+
+/* The addresses of .debug_addr for one address size, each once */
+struct AddressTable {
+    address_size: u8,
+    /* Each address with its relocation, if it has one */
+    addresses: Vec<(u64, Option<Relocation>)>,
+    indices: HashMap<AddressKey, u64>,
+    /* The offset of the first address in .debug_addr */
+    base: u64,
+}
+
+impl AddressTable {
+    fn add(&mut self, value: u64, relocation: Option<Relocation>) -> u64 {
+        let key = match relocation {
+            Some(Relocation::Symbol {
+                symbol,
+                addend,
+                kind,
+                ..
+            }) => (value, Some((symbol.0, addend, kind))),
+            _ => (value, None),
+        };
+        let addresses = &mut self.addresses;
+        *self.indices.entry(key).or_insert_with(|| {
+            addresses.push((value, relocation));
+            addresses.len() as u64 - 1
+        })
+    }
+}
+
+/* Rewrites the DW_LLE_base_address entries of .debug_loclists (the only base address entries
+ * gimli writes) as DW_LLE_base_addressx entries, returning the .debug_addr they index into and
+ * its relocations. The location lists are moved accordingly, with the relocations of
+ * .debug_loclists and the references to it from .debug_info, except for the DW_AT_addr_base of
+ * the units with the given roots (see add_addr_bases), which now refer to .debug_addr. */
+pub fn index_base_addresses(
+    loclists: &mut Vec<u8>,
+    loclists_relocations: &mut Vec<Relocation>,
+    info_relocations: &mut [Relocation],
+    root_offsets: &[u64],
+) -> Result<(Vec<u8>, Vec<Relocation>), RewriteError> {
+    if root_offsets.is_empty() {
+        return Ok((Vec::new(), Vec::new()));
+    }
+    let mut address_relocations: HashMap<usize, Relocation> = loclists_relocations
+        .iter()
+        .filter_map(|relocation| match *relocation {
+            Relocation::Symbol { offset, .. } => Some((offset as usize, relocation.clone())),
+            Relocation::Section { .. } => None,
+        })
+        .collect();
+
+    let mut tables: BTreeMap<u8, AddressTable> = BTreeMap::new();
+    let mut output = Vec::new();
+    /* The spans of the input copied to the output, as (start, end, start in the output) */
+    let mut spans: Vec<(usize, usize, usize)> = Vec::new();
+    let mut copy = |output: &mut Vec<u8>, start: usize, end: usize| {
+        match spans.last_mut() {
+            Some(span) if span.1 == start && span.2 + (span.1 - span.0) == output.len() => {
+                span.1 = end
+            }
+            _ => spans.push((start, end, output.len())),
+        }
+        output.extend_from_slice(&loclists[start..end]);
+    };
+    let mut list_offsets = HashMap::new();
+    /* The contributions, as (start, end, address size) */
+    let mut contributions = Vec::new();
+
+    let data = EndianSlice::new(loclists, LittleEndian);
+    let mut input = data;
+    let position = |input: &EndianSlice<LittleEndian>| input.offset_from(data);
+    while !input.is_empty() {
+        let start = position(&input);
+        let new_start = output.len();
+        let (length, format) = input.read_initial_length()?;
+        let length_size = position(&input) - start;
+        let end = position(&input) + length;
+        let _version = input.read_u16()?;
+        let address_size = input.read_u8()?;
+        let _segment_selector_size = input.read_u8()?;
+        if input.read_u32()? != 0 {
+            return Err(RewriteError::UnsupportedObject(
+                "offset entries in .debug_loclists".to_string(),
+            ));
+        }
+        copy(&mut output, start, position(&input));
+        contributions.push((start, end, address_size));
+        let table = tables.entry(address_size).or_insert_with(|| AddressTable {
+            address_size,
+            addresses: Vec::new(),
+            indices: HashMap::new(),
+            base: 0,
+        });
+
+        let mut list_start = true;
+        while position(&input) < end {
+            let entry_start = position(&input);
+            if list_start {
+                list_offsets.insert(entry_start, output.len());
+            }
+            list_start = false;
+            let kind = gimli::DwLle(input.read_u8()?);
+            match kind {
+                gimli::DW_LLE_base_address => {
+                    let value = input.read_address(address_size)?;
+                    let relocation = address_relocations.remove(&(entry_start + 1));
+                    let index = table.add(value, relocation);
+                    output.push(gimli::DW_LLE_base_addressx.0);
+                    leb128::write::unsigned(&mut output, index)?;
+                    continue;
+                }
+                gimli::DW_LLE_end_of_list => list_start = true,
+                gimli::DW_LLE_base_addressx => {
+                    input.read_uleb128()?;
+                }
+                gimli::DW_LLE_startx_endx
+                | gimli::DW_LLE_startx_length
+                | gimli::DW_LLE_offset_pair => {
+                    input.read_uleb128()?;
+                    input.read_uleb128()?;
+                    skip_expression(&mut input)?;
+                }
+                gimli::DW_LLE_default_location => skip_expression(&mut input)?,
+                gimli::DW_LLE_start_end => {
+                    input.read_address(address_size)?;
+                    input.read_address(address_size)?;
+                    skip_expression(&mut input)?;
+                }
+                gimli::DW_LLE_start_length => {
+                    input.read_address(address_size)?;
+                    input.read_uleb128()?;
+                    skip_expression(&mut input)?;
+                }
+                _ => return Err(read::Error::InvalidLocationAddressRange.into()),
+            }
+            copy(&mut output, entry_start, position(&input));
+        }
+
+        let new_length = (output.len() - new_start - length_size) as u64;
+        match format {
+            gimli::Format::Dwarf32 => {
+                output[new_start..new_start + 4].copy_from_slice(&(new_length as u32).to_le_bytes())
+            }
+            gimli::Format::Dwarf64 => {
+                output[new_start + 4..new_start + 12].copy_from_slice(&new_length.to_le_bytes())
+            }
+        }
+    }
+
+    let mut debug_addr = Vec::new();
+    let mut debug_addr_relocations = Vec::new();
+    for table in tables.values_mut() {
+        let size = table.address_size as usize;
+        debug_addr.extend_from_slice(&((4 + table.addresses.len() * size) as u32).to_le_bytes());
+        debug_addr.extend_from_slice(&5u16.to_le_bytes());
+        debug_addr.extend_from_slice(&[table.address_size, 0]);
+        table.base = debug_addr.len() as u64;
+        for (value, relocation) in &table.addresses {
+            if let Some(Relocation::Symbol {
+                symbol,
+                addend,
+                kind,
+                size,
+                ..
+            }) = *relocation
+            {
+                debug_addr_relocations.push(Relocation::Symbol {
+                    offset: debug_addr.len() as u64,
+                    symbol,
+                    addend,
+                    kind,
+                    size,
+                });
+            }
+            debug_addr.extend_from_slice(&value.to_le_bytes()[..size]);
+        }
+    }
+
+    let moved = |offset: u64| -> Result<u64, RewriteError> {
+        let offset = offset as usize;
+        let index = spans
+            .binary_search_by(|&(start, end, _)| {
+                if end <= offset {
+                    Ordering::Less
+                } else if start > offset {
+                    Ordering::Greater
+                } else {
+                    Ordering::Equal
+                }
+            })
+            .map_err(|_| read::Error::OffsetOutOfBounds)?;
+        let (start, _, new_start) = spans[index];
+        Ok((new_start + offset - start) as u64)
+    };
+    let mut relocations = Vec::new();
+    for relocation in loclists_relocations.drain(..) {
+        let relocation = match relocation {
+            Relocation::Symbol {
+                offset,
+                symbol,
+                addend,
+                kind,
+                size,
+            } => {
+                if !address_relocations.contains_key(&(offset as usize)) {
+                    /* Moved to .debug_addr */
+                    continue;
+                }
+                Relocation::Symbol {
+                    offset: moved(offset)?,
+                    symbol,
+                    addend,
+                    kind,
+                    size,
+                }
+            }
+            Relocation::Section {
+                offset,
+                section,
+                addend,
+                size,
+            } => Relocation::Section {
+                offset: moved(offset)?,
+                section,
+                addend,
+                size,
+            },
+        };
+        relocations.push(relocation);
+    }
+    *loclists_relocations = relocations;
+    *loclists = output;
+
+    /* The DW_AT_addr_base of a unit is the first reference to .debug_loclists from its root */
+    let mut addr_bases = HashSet::new();
+    for &root_offset in root_offsets {
+        let addr_base = info_relocations
+            .iter()
+            .filter_map(|relocation| match *relocation {
+                Relocation::Section {
+                    offset,
+                    section: gimli::SectionId::DebugLocLists,
+                    ..
+                } if offset >= root_offset => Some(offset),
+                _ => None,
+            })
+            .min();
+        addr_bases.extend(addr_base);
+    }
+    for relocation in info_relocations.iter_mut() {
+        if let Relocation::Section {
+            offset,
+            ref mut section,
+            ref mut addend,
+            ..
+        } = *relocation
+        {
+            if *section != gimli::SectionId::DebugLocLists {
+                continue;
+            }
+            let list_offset = *addend as usize;
+            if addr_bases.contains(&offset) {
+                let address_size = contributions
+                    .iter()
+                    .find(|&&(start, end, _)| list_offset >= start && list_offset < end)
+                    .map(|&(_, _, address_size)| address_size);
+                let table = address_size
+                    .and_then(|address_size| tables.get(&address_size))
+                    .ok_or(read::Error::OffsetOutOfBounds)?;
+                *section = gimli::SectionId::DebugAddr;
+                *addend = i32::try_from(table.base).map_err(|_| write::Error::ValueTooLarge)?;
+            } else {
+                let new_offset = list_offsets
+                    .get(&list_offset)
+                    .ok_or(read::Error::OffsetOutOfBounds)?;
+                *addend = i32::try_from(*new_offset).map_err(|_| write::Error::ValueTooLarge)?;
+            }
+        }
+    }
+    Ok((debug_addr, debug_addr_relocations))
+}
+
+fn skip_expression<R: Reader>(input: &mut R) -> Result<(), RewriteError> {
+    let length = input.read_uleb128()?;
+    input.skip(R::Offset::from_u64(length)?)?;
+    Ok(())
+}
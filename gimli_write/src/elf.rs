@@ -0,0 +1,399 @@
+use std::collections::HashMap;
+
+use object::elf;
+use object::read::elf::{FileHeader, SectionHeader};
+use object::{Endian, Endianness, FileKind};
+
+use crate::error::RewriteError;
+
+/* A section header of either ELF class */
+struct Section {
+    name: u32,
+    sh_type: u32,
+    flags: u64,
+    addr: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    addralign: u64,
+    entsize: u64,
+}
+
+/* Replaces sections of a linked ELF file. The file is kept byte for byte and the new contents
+ * of the sections are appended to it, followed by a new section header table, so the program
+ * headers and the loadable segments are left untouched. Sections missing from the file are
+ * added, and the sections matched by is_dropped which are not replaced are emptied. */
+pub fn replace_sections(
+    data: &[u8],
+    new_sections: &[(&str, Vec<u8>)],
+    is_dropped: impl Fn(&str) -> bool,
+) -> Result<Vec<u8>, RewriteError> {
+    match FileKind::parse(data)? {
+        FileKind::Elf32 => {
+            replace_elf_sections::<elf::FileHeader32<Endianness>, _>(data, new_sections, is_dropped)
+        }
+        FileKind::Elf64 => {
+            replace_elf_sections::<elf::FileHeader64<Endianness>, _>(data, new_sections, is_dropped)
+        }
+        _ => Err(RewriteError::UnsupportedObject(
+            "only ELF files can be rewritten in place".to_string(),
+        )),
+    }
+}
+
+fn replace_elf_sections<Elf: FileHeader<Endian = Endianness>, F: Fn(&str) -> bool>(
+    data: &[u8],
+    new_sections: &[(&str, Vec<u8>)],
+    is_dropped: F,
+) -> Result<Vec<u8>, RewriteError> {
+    let unsupported = |reason: &str| RewriteError::UnsupportedObject(reason.to_string());
+    let header = Elf::parse(data)?;
+    let endian = header.endian()?;
+    let section_table = header.sections(endian, data)?;
+    if section_table.is_empty() {
+        return Err(unsupported("no section headers"));
+    }
+    let shstrndx = header.shstrndx(endian, data)? as usize;
+
+    let mut sections = Vec::new();
+    let mut names = Vec::new();
+    /* The index of the first section of each name */
+    let mut indices = HashMap::new();
+    for (index, section) in section_table.iter().enumerate() {
+        let name = section_table.section_name(endian, section)?;
+        let name = String::from_utf8_lossy(name).into_owned();
+        indices.entry(name.clone()).or_insert(index);
+        names.push(name);
+        sections.push(Section {
+            name: section.sh_name(endian),
+            sh_type: section.sh_type(endian),
+            flags: section.sh_flags(endian).into(),
+            addr: section.sh_addr(endian).into(),
+            offset: section.sh_offset(endian).into(),
+            size: section.sh_size(endian).into(),
+            link: section.sh_link(endian),
+            info: section.sh_info(endian),
+            addralign: section.sh_addralign(endian).into(),
+            entsize: section.sh_entsize(endian).into(),
+        });
+    }
+    let mut shstrtab = section_table
+        .section(shstrndx)?
+        .data(endian, data)
+        .map_err(|()| unsupported("invalid section name table"))?
+        .to_vec();
+    let shstrtab_size = shstrtab.len();
+
+    let mut out = data.to_vec();
+    let mut replaced = vec![false; sections.len()];
+    for (name, contents) in new_sections {
+        let index = match indices.get(*name) {
+            Some(&index) => index,
+            None => {
+                let name_offset = shstrtab.len() as u32;
+                shstrtab.extend_from_slice(name.as_bytes());
+                shstrtab.push(0);
+                names.push(name.to_string());
+                indices.insert(name.to_string(), names.len() - 1);
+                replaced.push(false);
+                sections.push(Section {
+                    name: name_offset,
+                    sh_type: elf::SHT_PROGBITS,
+                    flags: 0,
+                    addr: 0,
+                    offset: 0,
+                    size: 0,
+                    link: 0,
+                    info: 0,
+                    addralign: 1,
+                    entsize: 0,
+                });
+                sections.len() - 1
+            }
+        };
+        let section = &mut sections[index];
+        if section.flags & u64::from(elf::SHF_ALLOC) != 0 {
+            return Err(unsupported(&format!("section {} is loaded", name)));
+        }
+        section.sh_type = elf::SHT_PROGBITS;
+        section.flags &= !u64::from(elf::SHF_COMPRESSED);
+        section.offset = append(&mut out, contents, section.addralign);
+        section.size = contents.len() as u64;
+        replaced[index] = true;
+    }
+    for (index, section) in sections.iter_mut().enumerate() {
+        if !replaced[index] && is_dropped(&names[index]) {
+            section.size = 0;
+            replaced[index] = true;
+        }
+    }
+    /* Relocations would apply to the old contents */
+    for section in &sections {
+        if (section.sh_type == elf::SHT_REL || section.sh_type == elf::SHT_RELA)
+            && replaced.get(section.info as usize) == Some(&true)
+        {
+            return Err(unsupported("relocations against a rewritten section"));
+        }
+    }
+    if shstrtab.len() != shstrtab_size {
+        sections[shstrndx].offset = append(&mut out, &shstrtab, 1);
+        sections[shstrndx].size = shstrtab.len() as u64;
+    }
+
+    /* With extended section numbering, from SHN_LORESERVE sections on, e_shnum is 0 and the
+     * number of sections is the size of the first one. e_shstrndx (or the sh_link of the first
+     * section it defers to) is kept, the index of the section name table does not change. */
+    let shnum = if sections.len() >= usize::from(elf::SHN_LORESERVE) {
+        sections[0].size = sections.len() as u64;
+        0
+    } else {
+        sections[0].size = 0;
+        sections.len() as u16
+    };
+    let is_64 = header.is_type_64();
+    let shoff = append(&mut out, &[], if is_64 { 8 } else { 4 });
+    for section in &sections {
+        write_section_header(&mut out, endian, is_64, section);
+    }
+
+    /* e_shoff and e_shnum, see the layout of Elf32_Ehdr and Elf64_Ehdr */
+    let shnum = endian.write_u16_bytes(shnum);
+    if is_64 {
+        out[0x28..0x30].copy_from_slice(&endian.write_u64_bytes(shoff));
+        out[0x3c..0x3e].copy_from_slice(&shnum);
+    } else {
+        out[0x20..0x24].copy_from_slice(&endian.write_u32_bytes(shoff as u32));
+        out[0x30..0x32].copy_from_slice(&shnum);
+    }
+    Ok(out)
+}
+
+/* Appends contents at the next offset aligned to align, returning that offset */
+fn append(out: &mut Vec<u8>, contents: &[u8], align: u64) -> u64 {
+    let align = align.max(1) as usize;
+    let offset = out.len() + (align - out.len() % align) % align;
+    out.resize(offset, 0);
+    out.extend_from_slice(contents);
+    offset as u64
+}
+
+fn write_section_header(out: &mut Vec<u8>, endian: Endianness, is_64: bool, section: &Section) {
+    let word = |out: &mut Vec<u8>, value: u64| {
+        if is_64 {
+            out.extend_from_slice(&endian.write_u64_bytes(value));
+        } else {
+            out.extend_from_slice(&endian.write_u32_bytes(value as u32));
+        }
+    };
+    out.extend_from_slice(&endian.write_u32_bytes(section.name));
+    out.extend_from_slice(&endian.write_u32_bytes(section.sh_type));
+    word(out, section.flags);
+    word(out, section.addr);
+    word(out, section.offset);
+    word(out, section.size);
+    out.extend_from_slice(&endian.write_u32_bytes(section.link));
+    out.extend_from_slice(&endian.write_u32_bytes(section.info));
+    word(out, section.addralign);
+    word(out, section.entsize);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object::{Object, ObjectSection, ObjectSegment};
+    use std::convert::TryInto;
+
+    const TEXT: [u8; 16] = [0x90; 16];
+
+    /* A linked little endian ELF file of the given type, with a loadable segment for .text and
+     * .debug_info and .debug_abbrev sections */
+    fn new_elf(is_64: bool, e_type: u16) -> Vec<u8> {
+        let endian = Endianness::Little;
+        let (ehsize, phentsize, shentsize) = if is_64 { (64, 56, 64) } else { (52, 32, 40) };
+        let shstrtab = b"\0.text\0.debug_info\0.debug_abbrev\0.shstrtab\0";
+        let text_offset = ehsize + phentsize;
+        let mut data = vec![0; text_offset];
+        data.extend_from_slice(&TEXT);
+        let debug_info_offset = append(&mut data, b"old info", 1);
+        let debug_abbrev_offset = append(&mut data, b"old abbrev", 1);
+        let shstrtab_offset = append(&mut data, shstrtab, 1);
+        let shoff = append(&mut data, &[], 8);
+        let section = |name, sh_type, flags, addr, offset, size| Section {
+            name,
+            sh_type,
+            flags,
+            addr,
+            offset,
+            size,
+            link: 0,
+            info: 0,
+            addralign: 1,
+            entsize: 0,
+        };
+        let sections = [
+            section(0, elf::SHT_NULL, 0, 0, 0, 0),
+            section(
+                1,
+                elf::SHT_PROGBITS,
+                u64::from(elf::SHF_ALLOC | elf::SHF_EXECINSTR),
+                0x1000 + text_offset as u64,
+                text_offset as u64,
+                TEXT.len() as u64,
+            ),
+            section(7, elf::SHT_PROGBITS, 0, 0, debug_info_offset, 8),
+            section(19, elf::SHT_PROGBITS, 0, 0, debug_abbrev_offset, 10),
+            section(
+                33,
+                elf::SHT_STRTAB,
+                0,
+                0,
+                shstrtab_offset,
+                shstrtab.len() as u64,
+            ),
+        ];
+        for section in &sections {
+            write_section_header(&mut data, endian, is_64, section);
+        }
+
+        let mut header = vec![0x7f, b'E', b'L', b'F'];
+        header.extend_from_slice(&[if is_64 { 2 } else { 1 }, 1, 1]);
+        header.resize(16, 0);
+        let u16 = |header: &mut Vec<u8>, value: u16| {
+            header.extend_from_slice(&endian.write_u16_bytes(value))
+        };
+        let word = |header: &mut Vec<u8>, value: u64| {
+            if is_64 {
+                header.extend_from_slice(&endian.write_u64_bytes(value));
+            } else {
+                header.extend_from_slice(&endian.write_u32_bytes(value as u32));
+            }
+        };
+        u16(&mut header, e_type);
+        u16(
+            &mut header,
+            if is_64 { elf::EM_X86_64 } else { elf::EM_386 },
+        );
+        header.extend_from_slice(&endian.write_u32_bytes(1));
+        word(&mut header, 0x1000 + text_offset as u64);
+        word(&mut header, ehsize as u64);
+        word(&mut header, shoff);
+        header.extend_from_slice(&endian.write_u32_bytes(0));
+        for &value in &[ehsize, phentsize, 1, shentsize, sections.len(), 4] {
+            u16(&mut header, value as u16);
+        }
+
+        /* The segment of .text, whose flags come before p_offset in Elf64_Phdr only */
+        let address = 0x1000 + text_offset as u64;
+        let size = TEXT.len() as u64;
+        header.extend_from_slice(&endian.write_u32_bytes(elf::PT_LOAD));
+        if is_64 {
+            header.extend_from_slice(&endian.write_u32_bytes(elf::PF_R | elf::PF_X));
+        }
+        for &value in &[text_offset as u64, address, address, size, size] {
+            word(&mut header, value);
+        }
+        if !is_64 {
+            header.extend_from_slice(&endian.write_u32_bytes(elf::PF_R | elf::PF_X));
+        }
+        word(&mut header, 0x1000);
+        data[..text_offset].copy_from_slice(&header);
+        data
+    }
+
+    /* Replaces .debug_info, adds .debug_line and drops the other DWARF sections, checking the
+     * segments are kept */
+    fn check_rewrite(data: &[u8]) {
+        let new_sections = [
+            (".debug_info", b"new info".to_vec()),
+            (".debug_line", b"new line".to_vec()),
+        ];
+        let out =
+            replace_sections(data, &new_sections, |name| name.starts_with(".debug_")).unwrap();
+
+        let before = object::File::parse(data).unwrap();
+        let after = object::File::parse(&*out).unwrap();
+        /* The header up to e_shoff (e_type included) and the program headers, right after it */
+        let is_64 = after.is_64();
+        let (shoff_offset, ehsize, phentsize) = if is_64 {
+            (0x28, 64, 56)
+        } else {
+            (0x20, 52, 32)
+        };
+        assert_eq!(out[..shoff_offset], data[..shoff_offset]);
+        assert_eq!(
+            out[ehsize..ehsize + phentsize],
+            data[ehsize..ehsize + phentsize]
+        );
+        let phdrs = |file: &object::File, data: &[u8]| {
+            let segments: Vec<_> = file
+                .segments()
+                .map(|segment| {
+                    let (offset, size) = segment.file_range();
+                    (segment.address(), segment.size(), offset, size)
+                })
+                .collect();
+            let contents: Vec<_> = segments
+                .iter()
+                .map(|&(_, _, offset, size)| {
+                    data[offset as usize..(offset + size) as usize].to_vec()
+                })
+                .collect();
+            (segments, contents)
+        };
+        assert_eq!(phdrs(&after, &out), phdrs(&before, data));
+        assert_eq!(after.segments().count(), 1);
+
+        let contents = |name| {
+            after
+                .section_by_name(name)
+                .unwrap()
+                .data()
+                .unwrap()
+                .to_vec()
+        };
+        assert_eq!(contents(".text"), TEXT);
+        assert_eq!(contents(".debug_info"), b"new info");
+        assert_eq!(contents(".debug_line"), b"new line");
+        assert!(contents(".debug_abbrev").is_empty());
+    }
+
+    #[test]
+    fn executables_keep_their_segments() {
+        check_rewrite(&new_elf(true, elf::ET_EXEC));
+    }
+
+    #[test]
+    fn shared_objects_keep_their_segments() {
+        check_rewrite(&new_elf(false, elf::ET_DYN));
+    }
+
+    #[test]
+    fn extended_section_numbering_is_read() {
+        let mut data = new_elf(true, elf::ET_EXEC);
+        let endian = Endianness::Little;
+        /* e_shnum of 0, the number of sections going in the sh_size of the first one */
+        let shoff = u64::from_le_bytes(data[0x28..0x30].try_into().unwrap()) as usize;
+        data[0x3c..0x3e].copy_from_slice(&endian.write_u16_bytes(0));
+        data[shoff + 0x20..shoff + 0x28].copy_from_slice(&endian.write_u64_bytes(5));
+        check_rewrite(&data);
+    }
+
+    #[test]
+    fn extended_section_numbering_is_written() {
+        let data = new_elf(true, elf::ET_EXEC);
+        let names: Vec<_> = (0..elf::SHN_LORESERVE)
+            .map(|index| format!(".debug_{}", index))
+            .collect();
+        let new_sections: Vec<_> = names
+            .iter()
+            .map(|name| (name.as_str(), Vec::new()))
+            .collect();
+        let out = replace_sections(&data, &new_sections, |_| false).unwrap();
+        assert_eq!(out[0x3c..0x3e], [0, 0]);
+        let file = object::File::parse(&*out).unwrap();
+        let last = format!(".debug_{}", elf::SHN_LORESERVE - 1);
+        assert!(file.section_by_name(&last).is_some());
+        assert_eq!(file.sections().count(), 5 + usize::from(elf::SHN_LORESERVE));
+    }
+}
@@ -0,0 +1,267 @@
+use std::{error, fmt, io};
+
+use gimli::{read, write};
+use object::write as object_write;
+
+/* Everything that can go wrong while rewriting the debug info of an object. Errors tied to an
+ * entry of the expression file carry its (1-based) line number, if the mapping came from one. */
+#[derive(Debug)]
+pub enum RewriteError {
+    MalformedExpressionFile {
+        line: usize,
+        column: usize,
+        reason: String,
+    },
+    /* A mapping which cannot be written out as an expression file entry */
+    InvalidMapping {
+        variable: Option<String>,
+        reason: String,
+    },
+    UnknownFunction(String),
+    /* A function defined in several units, none of which can be told to be the one of a mapping
+     * by its zero address */
+    AmbiguousFunction {
+        line: Option<usize>,
+        function: String,
+        units: usize,
+    },
+    /* A variable reference which is malformed or cannot refer to a variable of the function */
+    InvalidVariable {
+        line: Option<usize>,
+        variable: String,
+        reason: String,
+    },
+    UnknownVariable {
+        line: Option<usize>,
+        name: String,
+    },
+    /* A member of a variable (e.g. "a.x" or "a[3]") which cannot be located in its type */
+    UnknownMember {
+        line: Option<usize>,
+        variable: String,
+        reason: String,
+    },
+    InvalidExpression {
+        line: Option<usize>,
+        expression: String,
+        reason: String,
+    },
+    UnsupportedAttributeForm {
+        attribute: gimli::DwAt,
+        value: String,
+    },
+    RangeInversion {
+        line: Option<usize>,
+        begin: u64,
+        end: u64,
+    },
+    /* Two mappings of a variable over the same range with different expressions */
+    ConflictingMappings {
+        line: Option<usize>,
+        other_line: Option<usize>,
+        variable: String,
+        begin: u64,
+        end: u64,
+    },
+    /* A member set over a range where another member it overlaps is set too */
+    OverlappingMembers {
+        line: Option<usize>,
+        other_line: Option<usize>,
+        variable: String,
+        other: String,
+    },
+    /* A member of a variable whose existing location cannot be split into pieces */
+    UnsplittableLocation {
+        line: Option<usize>,
+        variable: String,
+    },
+    UnsupportedObject(String),
+    UnsupportedDwarfVersion(u16),
+    Io(io::Error),
+    ObjectRead(object::Error),
+    ObjectWrite(object_write::Error),
+    DwarfRead(read::Error),
+    DwarfConvert(write::ConvertError),
+    DwarfWrite(write::Error),
+}
+
+impl RewriteError {
+    pub fn malformed(line: usize, column: usize, reason: &str) -> Self {
+        RewriteError::MalformedExpressionFile {
+            line,
+            column,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for RewriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RewriteError::MalformedExpressionFile {
+                line,
+                column,
+                reason,
+            } => write!(
+                f,
+                "expression file line {}, column {}: {}",
+                line, column, reason
+            ),
+            RewriteError::InvalidMapping { variable, reason } => match variable {
+                Some(variable) => write!(f, "invalid mapping for {}: {}", variable, reason),
+                None => write!(f, "invalid expression file: {}", reason),
+            },
+            RewriteError::UnknownFunction(function) => {
+                write!(f, "function {} not present in .debug_info", function)
+            }
+            RewriteError::AmbiguousFunction {
+                line,
+                function,
+                units,
+            } => {
+                write_line(f, *line)?;
+                write!(
+                    f,
+                    "function {} is defined in {} units, none starting at the zero address alone",
+                    function, units
+                )
+            }
+            RewriteError::InvalidVariable {
+                line,
+                variable,
+                reason,
+            } => {
+                write_line(f, *line)?;
+                write!(f, "invalid variable {}: {}", variable, reason)
+            }
+            RewriteError::UnknownVariable { line, name } => {
+                write_line(f, *line)?;
+                write!(f, "variable {} not present in the function", name)
+            }
+            RewriteError::UnknownMember {
+                line,
+                variable,
+                reason,
+            } => {
+                write_line(f, *line)?;
+                write!(f, "cannot locate {}: {}", variable, reason)
+            }
+            RewriteError::InvalidExpression {
+                line,
+                expression,
+                reason,
+            } => {
+                write_line(f, *line)?;
+                write!(f, "invalid expression '{}': {}", expression, reason)
+            }
+            RewriteError::UnsupportedAttributeForm { attribute, value } => {
+                write!(f, "unsupported {} value: {}", attribute, value)
+            }
+            RewriteError::RangeInversion { line, begin, end } => {
+                write_line(f, *line)?;
+                write!(f, "inverted range 0x{:x}->0x{:x}", begin, end)
+            }
+            RewriteError::ConflictingMappings {
+                line,
+                other_line,
+                variable,
+                begin,
+                end,
+            } => {
+                write_line(f, *line)?;
+                write!(
+                    f,
+                    "conflicting mappings of {} over 0x{:x}->0x{:x}",
+                    variable, begin, end
+                )?;
+                match other_line {
+                    Some(other_line) => write!(f, " (see line {})", other_line),
+                    None => Ok(()),
+                }
+            }
+            RewriteError::OverlappingMembers {
+                line,
+                other_line,
+                variable,
+                other,
+            } => {
+                write_line(f, *line)?;
+                write!(f, "{} overlaps {}", variable, other)?;
+                match other_line {
+                    Some(other_line) => write!(f, " (see line {})", other_line),
+                    None => Ok(()),
+                }
+            }
+            RewriteError::UnsplittableLocation { line, variable } => {
+                write_line(f, *line)?;
+                write!(
+                    f,
+                    "the existing location of {} cannot be split into pieces",
+                    variable
+                )
+            }
+            RewriteError::UnsupportedObject(reason) => write!(f, "unsupported object: {}", reason),
+            RewriteError::UnsupportedDwarfVersion(version) => {
+                write!(
+                    f,
+                    "cannot write DWARF version {}, only 5 is supported",
+                    version
+                )
+            }
+            RewriteError::Io(err) => write!(f, "I/O error: {}", err),
+            RewriteError::ObjectRead(err) => write!(f, "failed to read object: {}", err),
+            RewriteError::ObjectWrite(err) => write!(f, "failed to write object: {}", err),
+            RewriteError::DwarfRead(err) => write!(f, "failed to read DWARF: {}", err),
+            RewriteError::DwarfConvert(err) => write!(f, "failed to convert DWARF: {}", err),
+            RewriteError::DwarfWrite(err) => write!(f, "failed to write DWARF: {}", err),
+        }
+    }
+}
+
+fn write_line(f: &mut fmt::Formatter<'_>, line: Option<usize>) -> fmt::Result {
+    match line {
+        Some(line) => write!(f, "expression file line {}: ", line),
+        None => Ok(()),
+    }
+}
+
+impl error::Error for RewriteError {}
+
+impl From<io::Error> for RewriteError {
+    fn from(err: io::Error) -> Self {
+        RewriteError::Io(err)
+    }
+}
+
+impl From<object::Error> for RewriteError {
+    fn from(err: object::Error) -> Self {
+        RewriteError::ObjectRead(err)
+    }
+}
+
+impl From<object_write::Error> for RewriteError {
+    fn from(err: object_write::Error) -> Self {
+        RewriteError::ObjectWrite(err)
+    }
+}
+
+impl From<read::Error> for RewriteError {
+    fn from(err: read::Error) -> Self {
+        RewriteError::DwarfRead(err)
+    }
+}
+
+impl From<write::ConvertError> for RewriteError {
+    fn from(err: write::ConvertError) -> Self {
+        match err {
+            write::ConvertError::Read(err) => RewriteError::DwarfRead(err),
+            err => RewriteError::DwarfConvert(err),
+        }
+    }
+}
+
+impl From<write::Error> for RewriteError {
+    fn from(err: write::Error) -> Self {
+        RewriteError::DwarfWrite(err)
+    }
+}
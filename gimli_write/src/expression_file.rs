@@ -0,0 +1,395 @@
+use std::fmt;
+use std::io::{Read, Write};
+use std::iter::Peekable;
+
+use crate::error::RewriteError;
+use crate::Mapping;
+
+const ZERO_ADDRESS_HEADER: &str = "=ZeroAddress";
+const TOTAL_PCS_HEADER: &str = "=TotalPCs";
+const FUNCTION_HEADER: &str = "=Function";
+const EXPRESSIONS_HEADER: &str = "=Expressions";
+
+/* An expression file: one or more function sections, each made of the =ZeroAddress, =TotalPCs
+ * and =Function headers followed by their value on the next line, then =Expressions and one
+ * "var=postfix-expr\t0xBEG->0xEND" per line until the next =ZeroAddress.
+ *
+ * The variable name ends at the first '=' and the PC range starts after the last tab, so the
+ * expression itself may contain both. The variable may also be given by its entry, see
+ * VariableRef. It is kept as written and only resolved when rewriting, so that an invalid one
+ * costs its entry rather than the whole file. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpressionFile {
+    pub functions: Vec<FunctionExpressions>,
+}
+
+/* The PC ranges of the entries are rebased on the zero address of their section, i.e. they are
+ * offsets from the start of the function like in any other Mapping. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionExpressions {
+    pub zero_address: u64,
+    pub total_pcs: u32,
+    pub function: String,
+    pub entries: Vec<Mapping>,
+}
+
+impl ExpressionFile {
+    pub fn read(input: &mut impl Read) -> Result<Self, RewriteError> {
+        let mut buffer = String::new();
+        input.read_to_string(&mut buffer)?;
+        ExpressionFile::parse(&buffer)
+    }
+
+    pub fn parse(input: &str) -> Result<Self, RewriteError> {
+        let mut parser = Parser {
+            lines: input
+                .lines()
+                .enumerate()
+                .map(|(i, line)| (i + 1, line))
+                .filter(|(_, line)| !line.trim().is_empty())
+                .peekable(),
+            eof_line: input.lines().count() + 1,
+        };
+        let mut functions = vec![parser.function()?];
+        while parser.lines.peek().is_some() {
+            functions.push(parser.function()?);
+        }
+        Ok(ExpressionFile { functions })
+    }
+
+    /* All the entries of the file, in order */
+    pub fn mappings(&self) -> impl Iterator<Item = &Mapping> {
+        self.functions.iter().flat_map(|function| &function.entries)
+    }
+
+    /* Writes the file back in the format read by parse(). Entries which could not be read back
+     * as they are (e.g. a variable name containing '=') are rejected rather than mangled. */
+    pub fn write(&self, out: &mut impl Write) -> Result<(), RewriteError> {
+        for function in &self.functions {
+            function.write(out)?;
+        }
+        Ok(())
+    }
+}
+
+impl FunctionExpressions {
+    fn write(&self, out: &mut impl Write) -> Result<(), RewriteError> {
+        if self.function.is_empty() || self.function.contains(is_line_break) {
+            return Err(RewriteError::InvalidMapping {
+                variable: None,
+                reason: format!("invalid function name {:?}", self.function),
+            });
+        }
+        writeln!(out, "{}\n0x{:x}", ZERO_ADDRESS_HEADER, self.zero_address)?;
+        writeln!(out, "{}\n{}", TOTAL_PCS_HEADER, self.total_pcs)?;
+        writeln!(out, "{}\n{}", FUNCTION_HEADER, self.function)?;
+        writeln!(out, "{}", EXPRESSIONS_HEADER)?;
+        for entry in &self.entries {
+            let invalid = |reason: String| RewriteError::InvalidMapping {
+                variable: Some(entry.variable.clone()),
+                reason,
+            };
+            if entry.function != self.function {
+                return Err(invalid(format!(
+                    "belongs to function {} instead of {}",
+                    entry.function, self.function
+                )));
+            }
+            if entry
+                .variable
+                .contains(|c| c == '=' || c == '\t' || is_line_break(c))
+            {
+                return Err(invalid("invalid variable name".to_string()));
+            }
+            VariableRef::parse(&entry.variable).map_err(invalid)?;
+            if entry.expression.contains(is_line_break) {
+                return Err(invalid("line break in expression".to_string()));
+            }
+            let (begin, end) = entry.pc_range;
+            if begin >= end {
+                return Err(RewriteError::RangeInversion {
+                    line: entry.line,
+                    begin,
+                    end,
+                });
+            }
+            writeln!(
+                out,
+                "{}={}\t0x{:x}->0x{:x}",
+                entry.variable,
+                entry.expression,
+                begin.wrapping_add(self.zero_address),
+                end.wrapping_add(self.zero_address)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/* The variable of a mapping. A plain name is looked up in the scopes of the function around the
+ * PC range, which cannot tell shadowed variables apart. "name@0xOFFSET" (the name being optional)
+ * gives the offset of the entry of the variable in .debug_info, and "function/lexical_block#N/name"
+ * its scope path, lexical_block#N being the N-th (from 1) lexical block of the scope before it.
+ * Any of the names may go on with the members of the variable the mapping sets ("a.x", "a[3]"). */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VariableRef {
+    Name(String),
+    Offset {
+        name: Option<String>,
+        offset: u64,
+    },
+    Path {
+        function: String,
+        blocks: Vec<usize>,
+        name: String,
+    },
+}
+
+impl VariableRef {
+    pub fn parse(variable: &str) -> Result<Self, String> {
+        if variable.is_empty() {
+            return Err("missing variable name".to_string());
+        }
+        if variable.contains('/') {
+            let components: Vec<&str> = variable.split('/').collect();
+            let (function, name) = (components[0], components[components.len() - 1]);
+            if function.is_empty() || name.is_empty() || name.contains('@') {
+                return Err(format!("invalid scope path {}", variable));
+            }
+            let blocks = components[1..components.len() - 1]
+                .iter()
+                .map(|block| parse_block(block).ok_or_else(|| format!("invalid scope {}", block)))
+                .collect::<Result<_, _>>()?;
+            return Ok(VariableRef::Path {
+                function: function.to_string(),
+                blocks,
+                name: name.to_string(),
+            });
+        }
+        match variable.find('@') {
+            Some(at) => {
+                let offset = parse_address(&variable[at + 1..])
+                    .ok_or_else(|| format!("invalid entry offset {}", &variable[at + 1..]))?;
+                let name = Some(&variable[..at])
+                    .filter(|name| !name.is_empty())
+                    .map(str::to_string);
+                Ok(VariableRef::Offset { name, offset })
+            }
+            None => Ok(VariableRef::Name(variable.to_string())),
+        }
+    }
+
+    /* The name of the variable, if given */
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            VariableRef::Name(name) | VariableRef::Path { name, .. } => Some(name),
+            VariableRef::Offset { name, .. } => name.as_deref(),
+        }
+    }
+}
+
+impl fmt::Display for VariableRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VariableRef::Name(name) => write!(f, "{}", name),
+            VariableRef::Offset { name, offset } => {
+                write!(f, "{}@0x{:x}", name.as_deref().unwrap_or(""), offset)
+            }
+            VariableRef::Path {
+                function,
+                blocks,
+                name,
+            } => {
+                write!(f, "{}", function)?;
+                for block in blocks {
+                    write!(f, "/lexical_block#{}", block)?;
+                }
+                write!(f, "/{}", name)
+            }
+        }
+    }
+}
+
+fn parse_block(block: &str) -> Option<usize> {
+    let index = match block.strip_prefix("lexical_block")? {
+        "" => 1,
+        index => index.strip_prefix('#')?.parse().ok()?,
+    };
+    Some(index).filter(|&index| index > 0)
+}
+
+/* The lines are numbered from 1, eof_line being the one past the last. Blank lines are skipped. */
+struct Parser<I: Iterator> {
+    lines: Peekable<I>,
+    eof_line: usize,
+}
+
+impl<'a, I: Iterator<Item = (usize, &'a str)>> Parser<I> {
+    fn next_line(&mut self) -> Result<(usize, &'a str), RewriteError> {
+        let eof_line = self.eof_line;
+        self.lines
+            .next()
+            .ok_or_else(|| RewriteError::malformed(eof_line, 1, "unexpected end of file"))
+    }
+
+    fn header(&mut self, header: &str) -> Result<(), RewriteError> {
+        let (line_no, line) = self.next_line()?;
+        if line != header {
+            return Err(RewriteError::malformed(
+                line_no,
+                1,
+                &format!("expected {}", header),
+            ));
+        }
+        Ok(())
+    }
+
+    fn header_value(&mut self, header: &str) -> Result<(usize, &'a str), RewriteError> {
+        self.header(header)?;
+        self.next_line()
+    }
+
+    fn function(&mut self) -> Result<FunctionExpressions, RewriteError> {
+        let (line_no, line) = self.header_value(ZERO_ADDRESS_HEADER)?;
+        let zero_address = parse_address(line)
+            .ok_or_else(|| RewriteError::malformed(line_no, 1, "invalid zero address"))?;
+        let (line_no, line) = self.header_value(TOTAL_PCS_HEADER)?;
+        let total_pcs = line
+            .parse::<u32>()
+            .map_err(|_| RewriteError::malformed(line_no, 1, "invalid number of PCs"))?;
+        let (line_no, function) = self.header_value(FUNCTION_HEADER)?;
+        if function.starts_with('=') {
+            return Err(RewriteError::malformed(line_no, 1, "missing function name"));
+        }
+        self.header(EXPRESSIONS_HEADER)?;
+
+        let mut entries = Vec::new();
+        while let Some(&(line_no, line)) = self.lines.peek() {
+            if line == ZERO_ADDRESS_HEADER {
+                break;
+            }
+            self.lines.next();
+            let mut mapping = parse_entry(line_no, line, zero_address)?;
+            mapping.function = function.to_string();
+            entries.push(mapping);
+        }
+
+        Ok(FunctionExpressions {
+            zero_address,
+            total_pcs,
+            function: function.to_string(),
+            entries,
+        })
+    }
+}
+
+fn parse_entry(line_no: usize, line: &str, zero_address: u64) -> Result<Mapping, RewriteError> {
+    /* Columns are 1-based and counted in characters */
+    let column = |index: usize| line[..index].chars().count() + 1;
+
+    let tab = line
+        .rfind('\t')
+        .ok_or_else(|| RewriteError::malformed(line_no, column(line.len()), "missing PC range"))?;
+    let (var_and_expr, pc_range) = (&line[..tab], &line[tab + 1..]);
+    let equals = var_and_expr.find('=').ok_or_else(|| {
+        RewriteError::malformed(line_no, column(tab), "missing '=' after variable name")
+    })?;
+    let range_column = column(tab + 1);
+    let arrow = pc_range.find("->").ok_or_else(|| {
+        RewriteError::malformed(
+            line_no,
+            range_column,
+            "PC range is not of the form BEG->END",
+        )
+    })?;
+    let begin = parse_address(&pc_range[..arrow])
+        .ok_or_else(|| RewriteError::malformed(line_no, range_column, "invalid begin address"))?;
+    let end = parse_address(&pc_range[arrow + 2..]).ok_or_else(|| {
+        RewriteError::malformed(line_no, column(tab + 1 + arrow + 2), "invalid end address")
+    })?;
+    Ok(Mapping {
+        function: String::new(),
+        variable: var_and_expr[..equals].to_string(),
+        expression: var_and_expr[equals + 1..].to_string(),
+        /* Wrapping, as the function start is added back the same way */
+        pc_range: (
+            begin.wrapping_sub(zero_address),
+            end.wrapping_sub(zero_address),
+        ),
+        zero_address: Some(zero_address),
+        line: Some(line_no),
+    })
+}
+
+fn parse_address(text: &str) -> Option<u64> {
+    let text = text.strip_prefix("0x").unwrap_or(text);
+    u64::from_str_radix(text, 16).ok()
+}
+
+fn is_line_break(c: char) -> bool {
+    c == '\n' || c == '\r'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* The line and column of the error parsing text */
+    fn error_position(text: &str) -> (usize, usize) {
+        match ExpressionFile::parse(text) {
+            Err(RewriteError::MalformedExpressionFile { line, column, .. }) => (line, column),
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn written_files_read_back_the_same() {
+        let text = "=ZeroAddress\n0x40\n=TotalPCs\n20\n=Function\nf\n=Expressions\n\
+                    s=%rax 2 *\t0x44->0x50\nt=%rdx\t3 +\t0x40->0x48\n\
+                    =ZeroAddress\n0x0\n=TotalPCs\n4\n=Function\ng\n=Expressions\n";
+        let file = ExpressionFile::parse(text).unwrap();
+        assert_eq!(file.functions.len(), 2);
+        let t = &file.functions[0].entries[1];
+        assert_eq!(
+            (t.variable.as_str(), t.expression.as_str()),
+            ("t", "%rdx\t3 +")
+        );
+        assert_eq!(t.pc_range, (0, 8));
+
+        let mut out = Vec::new();
+        file.write(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), text);
+    }
+
+    #[test]
+    fn errors_give_their_line_and_column() {
+        let header = "=ZeroAddress\n0x0\n=TotalPCs\n4\n=Function\nf\n=Expressions\n";
+        assert_eq!(error_position(&format!("{}s=%rax", header)), (8, 7));
+        assert_eq!(
+            error_position(&format!("{}s%rax\t0x0->0x4", header)),
+            (8, 6)
+        );
+        assert_eq!(
+            error_position(&format!("{}s=%rax\t0x0-0x4", header)),
+            (8, 8)
+        );
+        assert_eq!(
+            error_position(&format!("{}s=%rax\t0x0->zz", header)),
+            (8, 13)
+        );
+        assert_eq!(error_position("=ZeroAddress\n\n0x0\n=TotalPCs\n"), (5, 1));
+        assert_eq!(error_position("=ZeroAddress\n0x0\n=Function\n"), (3, 1));
+    }
+
+    #[test]
+    fn invalid_variables_are_left_to_the_rewriter() {
+        let text = "=ZeroAddress\n0x10\n=TotalPCs\n4\n=Function\nf\n=Expressions\n\
+                    a/b=%rax\t0x10->0x14\nf//x=%rax\t0x10->0x14\n";
+        let file = ExpressionFile::parse(text).unwrap();
+        let mappings: Vec<_> = file.mappings().collect();
+        assert_eq!(mappings.len(), 2);
+        assert_eq!(mappings[0].variable, "a/b");
+        assert_eq!(mappings[0].pc_range, (0, 4));
+        assert!(mappings[1].variable_ref().is_err());
+    }
+}
@@ -0,0 +1,369 @@
+use std::collections::HashMap;
+
+use object::write;
+use object::{
+    self, Architecture, BinaryFormat, Endianness, Object, ObjectSection, ObjectSymbol,
+    RelocationTarget, SectionKind, SymbolFlags, SymbolIndex, SymbolKind, SymbolSection,
+};
+
+mod dwarf;
+use dwarf::*;
+mod dwarf_version;
+mod elf;
+mod error;
+pub use error::RewriteError;
+mod expression_file;
+pub use expression_file::{ExpressionFile, FunctionExpressions, VariableRef};
+mod location_map;
+mod pieces;
+mod postfix;
+mod registers;
+mod report;
+pub use report::{
+    Counts, FunctionReport, LocationReport, MappingReport, MappingStatus, RewriteReport,
+    VariableReport,
+};
+mod simplify;
+
+/* A location expression (in postfix notation, registers prefixed with '%', ending with '@' if it
+ * is the address of the variable rather than its value) for a variable of a function over a range
+ * of PCs. The range is given as offsets from the start of the function, the variable as a
+ * VariableRef. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mapping {
+    pub function: String,
+    pub variable: String,
+    pub expression: String,
+    pub pc_range: (u64, u64),
+    /* The address the PC range is relative to (the =ZeroAddress of the expression file), where
+     * the function starts in the input. It tells apart functions of the same name defined in
+     * several units. */
+    pub zero_address: Option<u64>,
+    /* Line of the expression file the mapping was read from, used in diagnostics */
+    pub line: Option<usize>,
+}
+
+impl Mapping {
+    /* The variable of the mapping, parsed */
+    pub fn variable_ref(&self) -> Result<VariableRef, RewriteError> {
+        VariableRef::parse(&self.variable).map_err(|reason| RewriteError::InvalidVariable {
+            line: self.line,
+            variable: self.variable.clone(),
+            reason,
+        })
+    }
+}
+
+/* Rewrites the debug info of an object with the given mappings. The object is copied as is,
+ * except for the DWARF sections which are regenerated. */
+pub struct DebugInfoRewriter<'a, 'data> {
+    in_object: &'a object::File<'data>,
+    mappings: Vec<Mapping>,
+    dwarf_version: Option<u16>,
+    architecture: Option<Architecture>,
+    simplify: bool,
+}
+
+impl<'a, 'data> DebugInfoRewriter<'a, 'data> {
+    pub fn new(in_object: &'a object::File<'data>) -> Self {
+        DebugInfoRewriter {
+            in_object,
+            mappings: Vec::new(),
+            dwarf_version: None,
+            architecture: None,
+            simplify: false,
+        }
+    }
+
+    pub fn add_mapping(
+        &mut self,
+        function: &str,
+        variable: &str,
+        expression: &str,
+        pc_range: (u64, u64),
+    ) {
+        self.add(Mapping {
+            function: function.to_string(),
+            variable: variable.to_string(),
+            expression: expression.to_string(),
+            pc_range,
+            zero_address: None,
+            line: None,
+        });
+    }
+
+    /* The order of the mappings does not matter: where the ranges of mappings of a variable
+     * overlap, the narrowest one wins. Mappings over the same range must agree. */
+    pub fn add(&mut self, mapping: Mapping) {
+        self.mappings.push(mapping);
+    }
+
+    /* Upgrades the units to the given DWARF version instead of keeping the version of the input.
+     * Only 5 is supported. */
+    pub fn set_dwarf_version(&mut self, version: u16) {
+        self.dwarf_version = Some(version);
+    }
+
+    /* Reads the register names of the expressions as those of the given architecture instead of
+     * the architecture of the object, e.g. for objects whose architecture is unknown */
+    pub fn set_architecture(&mut self, architecture: Architecture) {
+        self.architecture = Some(architecture);
+    }
+
+    /* Folds the operations of the expressions: constants are computed, registers and the frame
+     * base plus or minus a constant become DW_OP_breg and DW_OP_fbreg, and operations which do
+     * nothing are dropped. The locations are the same, but not the bytes written, which is why
+     * expressions are written as given by default. */
+    pub fn set_simplify(&mut self, simplify: bool) {
+        self.simplify = simplify;
+    }
+
+    fn options(&self) -> DwarfOptions {
+        DwarfOptions {
+            dwarf_version: self.dwarf_version,
+            arch: self
+                .architecture
+                .unwrap_or_else(|| self.in_object.architecture()),
+            simplify: self.simplify,
+        }
+    }
+
+    /* Whether the object is a linked ELF file (executable or shared object), which has program
+     * headers unlike relocatable objects */
+    pub fn is_linked(&self) -> bool {
+        self.in_object.format() == BinaryFormat::Elf && self.in_object.segments().next().is_some()
+    }
+
+    /* Writes a new relocatable object. Linked files would lose their program headers this way,
+     * they must be rewritten with finish_in_place() instead. */
+    pub fn finish(self) -> Result<Vec<u8>, RewriteError> {
+        self.write(None)
+    }
+
+    /* Like finish(), also reporting what became of each mapping */
+    pub fn finish_with_report(self) -> Result<(Vec<u8>, RewriteReport), RewriteError> {
+        let mut report = RewriteReport::default();
+        let data = self.write(Some(&mut report))?;
+        Ok((data, report))
+    }
+
+    /* Rewrites a linked ELF file (executable or shared object) in place: only the DWARF sections
+     * are replaced, the program headers and the loadable segments are kept as they are. in_data
+     * must be the data the object was parsed from. */
+    pub fn finish_in_place(self, in_data: &[u8]) -> Result<Vec<u8>, RewriteError> {
+        self.write_in_place(in_data, None)
+    }
+
+    /* Like finish_in_place(), also reporting what became of each mapping */
+    pub fn finish_in_place_with_report(
+        self,
+        in_data: &[u8],
+    ) -> Result<(Vec<u8>, RewriteReport), RewriteError> {
+        let mut report = RewriteReport::default();
+        let data = self.write_in_place(in_data, Some(&mut report))?;
+        Ok((data, report))
+    }
+
+    /* Rewrites the object whatever it is: in place if it is linked, as a new relocatable object
+     * otherwise, reporting what became of each mapping if with_report is set. in_data must be the
+     * data the object was parsed from. */
+    pub fn finish_any(
+        self,
+        in_data: &[u8],
+        with_report: bool,
+    ) -> Result<(Vec<u8>, Option<RewriteReport>), RewriteError> {
+        let mut report = RewriteReport::default();
+        let report_ref = if with_report { Some(&mut report) } else { None };
+        let data = if self.is_linked() {
+            self.write_in_place(in_data, report_ref)?
+        } else {
+            self.write(report_ref)?
+        };
+        Ok((data, if with_report { Some(report) } else { None }))
+    }
+
+    fn write(self, report: Option<&mut RewriteReport>) -> Result<Vec<u8>, RewriteError> {
+        if self.is_linked() {
+            return Err(RewriteError::UnsupportedObject(
+                "linked files must be rewritten in place".to_string(),
+            ));
+        }
+        let (mut out_object, out_symbols) = copy_object(self.in_object)?;
+        rewrite_dwarf(
+            self.in_object,
+            &mut out_object,
+            &out_symbols,
+            &self.mappings,
+            &self.options(),
+            report,
+        )?;
+        Ok(out_object.write()?)
+    }
+
+    fn write_in_place(
+        self,
+        in_data: &[u8],
+        report: Option<&mut RewriteReport>,
+    ) -> Result<Vec<u8>, RewriteError> {
+        if !self.is_linked() {
+            return Err(RewriteError::UnsupportedObject(
+                "only linked ELF files can be rewritten in place".to_string(),
+            ));
+        }
+        if self.in_object.endianness() != Endianness::Little {
+            return Err(RewriteError::UnsupportedObject(
+                "big-endian files cannot be rewritten in place".to_string(),
+            ));
+        }
+        let sections =
+            rewrite_dwarf_in_place(self.in_object, &self.mappings, &self.options(), report)?;
+        elf::replace_sections(in_data, &sections, is_rewrite_dwarf_section_name)
+    }
+}
+
+/* Copies everything but the DWARF sections being rewritten, returning the mapping of the
+ * symbols of in_object to those of the copy. */
+fn copy_object<'data>(
+    in_object: &object::File<'data>,
+) -> Result<(write::Object, HashMap<SymbolIndex, write::SymbolId>), RewriteError> {
+    let mut out_object = write::Object::new(
+        in_object.format(),
+        in_object.architecture(),
+        in_object.endianness(),
+    );
+    out_object.mangling = write::Mangling::None;
+    out_object.flags = in_object.flags();
+
+    let mut out_sections = HashMap::new();
+    for in_section in in_object.sections() {
+        if in_section.kind() == SectionKind::Metadata || is_rewrite_dwarf_section(&in_section) {
+            continue;
+        }
+        let segment_name = in_section.segment_name()?.unwrap_or("").as_bytes().to_vec();
+        let name = in_section.name().unwrap_or("").as_bytes().to_vec();
+        let section_id = out_object.add_section(segment_name, name, in_section.kind());
+        let out_section = out_object.section_mut(section_id);
+        if out_section.is_bss() {
+            out_section.append_bss(in_section.size(), in_section.align());
+        } else {
+            out_section.set_data(in_section.data()?.into(), in_section.align());
+        }
+        out_section.flags = in_section.flags();
+        out_sections.insert(in_section.index(), section_id);
+    }
+
+    let mut out_symbols = HashMap::new();
+    for in_symbol in in_object.symbols() {
+        let symbol_index = in_symbol.index();
+        if in_symbol.kind() == SymbolKind::Null {
+            continue;
+        }
+        let (section, value) = match in_symbol.section() {
+            SymbolSection::None => (write::SymbolSection::None, in_symbol.address()),
+            SymbolSection::Undefined => (write::SymbolSection::Undefined, in_symbol.address()),
+            SymbolSection::Absolute => (write::SymbolSection::Absolute, in_symbol.address()),
+            SymbolSection::Common => (write::SymbolSection::Common, in_symbol.address()),
+            SymbolSection::Section(index) => {
+                let out_section = if let Some(s) = out_sections.get(&index).cloned() {
+                    s
+                } else {
+                    // Must be a section that we are rewriting.
+                    continue;
+                };
+                (
+                    write::SymbolSection::Section(out_section),
+                    in_symbol.address() - in_object.section_by_index(index)?.address(),
+                )
+            }
+            section => {
+                return Err(RewriteError::UnsupportedObject(format!(
+                    "symbol {} in section {:?}",
+                    in_symbol.name().unwrap_or(""),
+                    section
+                )))
+            }
+        };
+        let flags = match in_symbol.flags() {
+            SymbolFlags::None => SymbolFlags::None,
+            SymbolFlags::Elf { st_info, st_other } => SymbolFlags::Elf { st_info, st_other },
+            SymbolFlags::MachO { n_desc } => SymbolFlags::MachO { n_desc },
+            SymbolFlags::CoffSection {
+                selection,
+                associative_section,
+            } => {
+                let associative_section = match associative_section {
+                    Some(index) => Some(*out_sections.get(&index).ok_or_else(|| {
+                        RewriteError::UnsupportedObject(format!(
+                            "symbol {} associated with a rewritten section",
+                            in_symbol.name().unwrap_or("")
+                        ))
+                    })?),
+                    None => None,
+                };
+                SymbolFlags::CoffSection {
+                    selection,
+                    associative_section,
+                }
+            }
+            flags => {
+                return Err(RewriteError::UnsupportedObject(format!(
+                    "symbol {} with flags {:?}",
+                    in_symbol.name().unwrap_or(""),
+                    flags
+                )))
+            }
+        };
+        let out_symbol = write::Symbol {
+            name: in_symbol.name().unwrap_or("").as_bytes().to_vec(),
+            value,
+            size: in_symbol.size(),
+            kind: in_symbol.kind(),
+            scope: in_symbol.scope(),
+            weak: in_symbol.is_weak(),
+            section,
+            flags,
+        };
+        let symbol_id = out_object.add_symbol(out_symbol);
+        out_symbols.insert(symbol_index, symbol_id);
+    }
+
+    for in_section in in_object.sections() {
+        /* Only the sections copied above */
+        let out_section = match out_sections.get(&in_section.index()) {
+            Some(&out_section) => out_section,
+            None => continue,
+        };
+        for (offset, in_relocation) in in_section.relocations() {
+            /* Relocations against the sections being rewritten, or their symbols, cannot be
+             * carried over */
+            let unsupported = || {
+                RewriteError::UnsupportedObject(format!(
+                    "relocation at 0x{:x} in {} against {:?}",
+                    offset,
+                    in_section.name().unwrap_or(""),
+                    in_relocation.target()
+                ))
+            };
+            let symbol = match in_relocation.target() {
+                RelocationTarget::Symbol(symbol) => {
+                    *out_symbols.get(&symbol).ok_or_else(unsupported)?
+                }
+                RelocationTarget::Section(section) => {
+                    out_object.section_symbol(*out_sections.get(&section).ok_or_else(unsupported)?)
+                }
+                _ => return Err(unsupported()),
+            };
+            let out_relocation = write::Relocation {
+                offset,
+                size: in_relocation.size(),
+                kind: in_relocation.kind(),
+                encoding: in_relocation.encoding(),
+                symbol,
+                addend: in_relocation.addend(),
+            };
+            out_object.add_relocation(out_section, out_relocation)?;
+        }
+    }
+
+    Ok((out_object, out_symbols))
+}
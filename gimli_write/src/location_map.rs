@@ -0,0 +1,284 @@
+use gimli::write::{Address, Expression, Location};
+
+/* A bound of a location range: its value, which ranges are compared with, and the address it is
+ * written with. Bounds read from the input keep their address, so that they keep their
+ * relocation, while the bounds of the overrides are constants. */
+#[derive(Debug, Clone, Copy)]
+pub struct Bound {
+    pub value: u64,
+    pub address: Address,
+}
+
+impl Bound {
+    pub fn new(value: u64, address: Address) -> Self {
+        Bound { value, address }
+    }
+
+    pub fn constant(value: u64) -> Self {
+        Bound::new(value, Address::Constant(value))
+    }
+}
+
+/* A new location of a variable over [begin, end) */
+#[derive(Debug, Clone)]
+pub struct Override {
+    pub begin: u64,
+    pub end: u64,
+    pub expression: Expression,
+}
+
+#[derive(Debug, Clone)]
+struct Interval {
+    begin: Bound,
+    end: Bound,
+    expression: Expression,
+}
+
+/* The location of a variable as a list of PC ranges [begin, end), each with its expression, kept
+ * sorted by begin. Overrides replace whatever was there over their range, so the ranges they
+ * cover never overlap. The ranges read from the input are kept as they are, overlapping or not. */
+#[derive(Debug, Clone, Default)]
+pub struct LocationMap {
+    intervals: Vec<Interval>,
+}
+
+impl LocationMap {
+    pub fn new() -> Self {
+        LocationMap::default()
+    }
+
+    /* Adds an existing location. Empty ranges describe nothing and are dropped. */
+    pub fn insert(&mut self, begin: Bound, end: Bound, expression: Expression) {
+        if begin.value >= end.value {
+            return;
+        }
+        self.push(Interval {
+            begin,
+            end,
+            expression,
+        });
+    }
+
+    /* Sets the locations of all the overrides at once, splitting or dropping the ranges they
+     * overlap. Where overrides overlap each other, the narrowest one wins, and between equally
+     * wide ones the one starting first, so that the result does not depend on their order. */
+    pub fn set_all(&mut self, mut overrides: Vec<Override>) {
+        overrides.retain(|o| o.begin < o.end);
+        if overrides.is_empty() {
+            return;
+        }
+        overrides.sort_by_key(|o| (o.end - o.begin, o.begin));
+
+        /* Each segment between two consecutive bounds goes to the first override covering it */
+        let mut bounds: Vec<u64> = overrides
+            .iter()
+            .flat_map(|o| vec![o.begin, o.end])
+            .collect();
+        bounds.sort_unstable();
+        bounds.dedup();
+        let mut owners = vec![None; bounds.len() - 1];
+        for (index, o) in overrides.iter().enumerate() {
+            let first = bounds.binary_search(&o.begin).unwrap();
+            let last = bounds.binary_search(&o.end).unwrap();
+            for owner in owners[first..last]
+                .iter_mut()
+                .filter(|owner| owner.is_none())
+            {
+                *owner = Some(index);
+            }
+        }
+        let mut runs: Vec<(u64, u64, usize)> = Vec::new();
+        for (segment, owner) in owners.into_iter().enumerate() {
+            let owner = match owner {
+                Some(owner) => owner,
+                None => continue,
+            };
+            let (begin, end) = (bounds[segment], bounds[segment + 1]);
+            match runs.last_mut() {
+                Some(run) if run.1 == begin && run.2 == owner => run.1 = end,
+                _ => runs.push((begin, end, owner)),
+            }
+        }
+
+        /* The existing ranges keep what the runs do not cover */
+        let mut intervals = Vec::with_capacity(self.intervals.len() + runs.len());
+        for interval in self.intervals.drain(..) {
+            let mut begin = interval.begin;
+            let first = runs.partition_point(|run| run.1 <= begin.value);
+            for run in runs[first..]
+                .iter()
+                .take_while(|run| run.0 < interval.end.value)
+            {
+                if begin.value < run.0 {
+                    intervals.push(Interval {
+                        begin,
+                        end: Bound::constant(run.0),
+                        expression: interval.expression.clone(),
+                    });
+                }
+                begin = Bound::constant(run.1);
+            }
+            if begin.value < interval.end.value {
+                intervals.push(Interval {
+                    begin,
+                    end: interval.end,
+                    expression: interval.expression,
+                });
+            }
+        }
+        for (begin, end, owner) in runs {
+            intervals.push(Interval {
+                begin: Bound::constant(begin),
+                end: Bound::constant(end),
+                expression: overrides[owner].expression.clone(),
+            });
+        }
+        intervals.sort_by_key(|interval| interval.begin.value);
+        self.intervals = intervals;
+    }
+
+    /* The locations in order, adjacent ranges with the same expression being merged */
+    pub fn to_locations(&self) -> Vec<Location> {
+        let mut locations: Vec<Location> = Vec::with_capacity(self.intervals.len());
+        let mut last_end = None;
+        for interval in &self.intervals {
+            if let Some(Location::StartEnd { end, data, .. }) = locations.last_mut() {
+                if last_end == Some(interval.begin.value) && *data == interval.expression {
+                    *end = interval.end.address;
+                    last_end = Some(interval.end.value);
+                    continue;
+                }
+            }
+            locations.push(Location::StartEnd {
+                begin: interval.begin.address,
+                end: interval.end.address,
+                data: interval.expression.clone(),
+            });
+            last_end = Some(interval.end.value);
+        }
+        locations
+    }
+
+    /* Intervals with the same begin stay in the order they were added */
+    fn push(&mut self, interval: Interval) {
+        let index = self
+            .intervals
+            .iter()
+            .rposition(|other| other.begin.value <= interval.begin.value)
+            .map_or(0, |index| index + 1);
+        self.intervals.insert(index, interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expression(opcode: gimli::DwOp) -> Expression {
+        let mut expression = Expression::new();
+        expression.op(opcode);
+        expression
+    }
+
+    fn start_end(begin: Address, end: Address, data: &Expression) -> Location {
+        Location::StartEnd {
+            begin,
+            end,
+            data: data.clone(),
+        }
+    }
+
+    fn overrides(ranges: &[(u64, u64, &Expression)]) -> Vec<Override> {
+        ranges
+            .iter()
+            .map(|&(begin, end, expression)| Override {
+                begin,
+                end,
+                expression: expression.clone(),
+            })
+            .collect()
+    }
+
+    fn constant(begin: u64, end: u64, data: &Expression) -> Location {
+        start_end(Address::Constant(begin), Address::Constant(end), data)
+    }
+
+    #[test]
+    fn adjacent_ranges_with_the_same_expression_are_merged() {
+        let (lit0, lit1) = (expression(gimli::DW_OP_lit0), expression(gimli::DW_OP_lit1));
+        let mut map = LocationMap::new();
+        map.insert(Bound::constant(4), Bound::constant(8), lit0.clone());
+        map.insert(Bound::constant(0), Bound::constant(4), lit0.clone());
+        map.insert(Bound::constant(8), Bound::constant(12), lit1.clone());
+        map.insert(Bound::constant(14), Bound::constant(16), lit1.clone());
+        map.insert(Bound::constant(16), Bound::constant(16), lit0.clone());
+        assert_eq!(
+            map.to_locations(),
+            vec![
+                constant(0, 8, &lit0),
+                constant(8, 12, &lit1),
+                constant(14, 16, &lit1),
+            ]
+        );
+    }
+
+    #[test]
+    fn narrowest_override_wins_whatever_the_order() {
+        let (lit0, lit1) = (expression(gimli::DW_OP_lit0), expression(gimli::DW_OP_lit1));
+        let wide = (0, 16, &lit0);
+        let narrow = (4, 8, &lit1);
+        for order in &[[wide, narrow], [narrow, wide]] {
+            let mut map = LocationMap::new();
+            map.set_all(overrides(order));
+            assert_eq!(
+                map.to_locations(),
+                vec![
+                    constant(0, 4, &lit0),
+                    constant(4, 8, &lit1),
+                    constant(8, 16, &lit0)
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn equally_wide_overrides_go_to_the_first() {
+        let (lit0, lit1) = (expression(gimli::DW_OP_lit0), expression(gimli::DW_OP_lit1));
+        let first = (0, 8, &lit0);
+        let second = (4, 12, &lit1);
+        for order in &[[first, second], [second, first]] {
+            let mut map = LocationMap::new();
+            map.insert(Bound::constant(0), Bound::constant(20), lit1.clone());
+            map.set_all(overrides(order));
+            assert_eq!(
+                map.to_locations(),
+                vec![constant(0, 8, &lit0), constant(8, 20, &lit1)]
+            );
+        }
+    }
+
+    #[test]
+    fn split_ranges_keep_their_relocated_bounds() {
+        let (lit0, lit1) = (expression(gimli::DW_OP_lit0), expression(gimli::DW_OP_lit1));
+        let symbol = |addend| Address::Symbol { symbol: 1, addend };
+        let mut map = LocationMap::new();
+        map.insert(
+            Bound::new(0x10, symbol(0x10)),
+            Bound::new(0x30, symbol(0x30)),
+            lit0.clone(),
+        );
+        map.set_all(vec![Override {
+            begin: 0x18,
+            end: 0x20,
+            expression: lit1.clone(),
+        }]);
+        assert_eq!(
+            map.to_locations(),
+            vec![
+                start_end(symbol(0x10), Address::Constant(0x18), &lit0),
+                constant(0x18, 0x20, &lit1),
+                start_end(Address::Constant(0x20), symbol(0x30), &lit0),
+            ]
+        );
+    }
+}
@@ -1,29 +1,49 @@
-use std::collections::HashMap;
+use std::io;
 use std::{env, fs, process};
 
 use env_logger;
 use memmap;
-use object::write;
-use object::{
-    self, Object, ObjectSection, RelocationTarget, SectionKind, SymbolFlags, SymbolKind,
-    SymbolSection,
-};
-
-mod dwarf;
-use dwarf::*;
+use rewrite::{DebugInfoRewriter, ExpressionFile};
 
 fn main() {
     env_logger::init();
 
     let mut args = env::args();
-    if args.len() < 3 {
-        eprintln!("Usage: {} <infile> <outfile>", args.next().unwrap());
+    let program = args.next().unwrap();
+    let usage = || {
+        eprintln!(
+            "Usage: {} [--dwarf-version <version>] [--report <file>] [--simplify] <infile> <outfile>",
+            program
+        );
         process::exit(1);
-    }
+    };
 
-    args.next();
-    let in_file_path = args.next().unwrap();
-    let out_file_path = args.next().unwrap();
+    let mut dwarf_version = None;
+    let mut report_path = None;
+    let mut simplify = false;
+    let mut paths = Vec::new();
+    while let Some(arg) = args.next() {
+        if arg == "--dwarf-version" {
+            match args.next().and_then(|version| version.parse::<u16>().ok()) {
+                Some(version) => dwarf_version = Some(version),
+                None => usage(),
+            }
+        } else if arg == "--report" {
+            match args.next() {
+                Some(path) => report_path = Some(path),
+                None => usage(),
+            }
+        } else if arg == "--simplify" {
+            simplify = true;
+        } else {
+            paths.push(arg);
+        }
+    }
+    if paths.len() != 2 {
+        usage();
+    }
+    let out_file_path = paths.pop().unwrap();
+    let in_file_path = paths.pop().unwrap();
 
     let in_file = match fs::File::open(&in_file_path) {
         Ok(file) => file,
@@ -47,117 +67,40 @@ fn main() {
         }
     };
 
-    let mut out_object = write::Object::new(in_object.format(), in_object.architecture());
-    out_object.mangling = write::Mangling::None;
-    out_object.flags = in_object.flags();
-
-    let mut out_sections = HashMap::new();
-    for in_section in in_object.sections() {
-        if in_section.kind() == SectionKind::Metadata || is_rewrite_dwarf_section(&in_section) {
-            continue;
-        }
-        let segment_name = in_section
-            .segment_name()
-            .unwrap()
-            .unwrap_or("")
-            .as_bytes()
-            .to_vec();
-        let name = in_section.name().unwrap_or("").as_bytes().to_vec();
-        let section_id = out_object.add_section(segment_name, name, in_section.kind());
-        let out_section = out_object.section_mut(section_id);
-        if out_section.is_bss() {
-            out_section.append_bss(in_section.size(), in_section.align());
-        } else {
-            out_section.set_data(in_section.data().unwrap().into(), in_section.align());
+    let expression_file = match ExpressionFile::read(&mut io::stdin()) {
+        Ok(expression_file) => expression_file,
+        Err(err) => {
+            eprintln!("Failed to read the expression file: {}", err);
+            process::exit(1);
         }
-        out_section.flags = in_section.flags();
-        out_sections.insert(in_section.index(), section_id);
-    }
+    };
 
-    let mut out_symbols = HashMap::new();
-    for (symbol_index, in_symbol) in in_object.symbols() {
-        if in_symbol.kind() == SymbolKind::Null {
-            continue;
-        }
-        let (section, value) = match in_symbol.section() {
-            SymbolSection::Unknown => panic!("unknown symbol section for {:?}", in_symbol),
-            SymbolSection::None => (write::SymbolSection::None, in_symbol.address()),
-            SymbolSection::Undefined => (write::SymbolSection::Undefined, in_symbol.address()),
-            SymbolSection::Absolute => (write::SymbolSection::Absolute, in_symbol.address()),
-            SymbolSection::Common => (write::SymbolSection::Common, in_symbol.address()),
-            SymbolSection::Section(index) => {
-                let out_section = if let Some(s) = out_sections.get(&index).cloned() {
-                    s
-                } else {
-                    // Must be a section that we are rewriting.
-                    continue;
-                };
-                (
-                    write::SymbolSection::Section(out_section),
-                    in_symbol.address() - in_object.section_by_index(index).unwrap().address(),
-                )
-            }
-        };
-        let flags = match in_symbol.flags() {
-            SymbolFlags::None => SymbolFlags::None,
-            SymbolFlags::Elf { st_info, st_other } => SymbolFlags::Elf { st_info, st_other },
-            SymbolFlags::MachO { n_desc } => SymbolFlags::MachO { n_desc },
-            SymbolFlags::CoffSection {
-                selection,
-                associative_section,
-            } => {
-                let associative_section = *out_sections.get(&associative_section).unwrap();
-                SymbolFlags::CoffSection {
-                    selection,
-                    associative_section,
-                }
-            }
-        };
-        let out_symbol = write::Symbol {
-            name: in_symbol.name().unwrap_or("").as_bytes().to_vec(),
-            value,
-            size: in_symbol.size(),
-            kind: in_symbol.kind(),
-            scope: in_symbol.scope(),
-            weak: in_symbol.is_weak(),
-            section,
-            flags,
-        };
-        let symbol_id = out_object.add_symbol(out_symbol);
-        out_symbols.insert(symbol_index, symbol_id);
+    let mut rewriter = DebugInfoRewriter::new(&in_object);
+    if let Some(version) = dwarf_version {
+        rewriter.set_dwarf_version(version);
     }
-
-    for in_section in in_object.sections() {
-        if in_section.kind() == SectionKind::Metadata || is_rewrite_dwarf_section(&in_section) {
-            continue;
-        }
-        let out_section = *out_sections.get(&in_section.index()).unwrap();
-        for (offset, in_relocation) in in_section.relocations() {
-            let symbol = match in_relocation.target() {
-                RelocationTarget::Symbol(symbol) => *out_symbols.get(&symbol).unwrap(),
-                RelocationTarget::Section(section) => {
-                    out_object.section_symbol(*out_sections.get(&section).unwrap())
-                }
-            };
-            let out_relocation = write::Relocation {
-                offset,
-                size: in_relocation.size(),
-                kind: in_relocation.kind(),
-                encoding: in_relocation.encoding(),
-                symbol,
-                addend: in_relocation.addend(),
-            };
-            out_object
-                .add_relocation(out_section, out_relocation)
-                .unwrap();
+    rewriter.set_simplify(simplify);
+    for function in expression_file.functions {
+        for mapping in function.entries {
+            rewriter.add(mapping);
         }
     }
-
-    rewrite_dwarf(&in_object, &mut out_object, &out_symbols);
-
-    let out_data = out_object.write().unwrap();
+    /* Linked files keep their layout, only their debug sections are replaced */
+    let (out_data, report) = match rewriter.finish_any(&in_file, report_path.is_some()) {
+        Ok(result) => result,
+        Err(err) => {
+            eprintln!("Failed to rewrite file '{}': {}", in_file_path, err);
+            process::exit(1);
+        }
+    };
     if let Err(err) = fs::write(&out_file_path, out_data) {
         eprintln!("Failed to write file '{}': {}", out_file_path, err);
         process::exit(1);
     }
+    if let (Some(report_path), Some(report)) = (report_path, report) {
+        if let Err(err) = fs::write(&report_path, report.to_json()) {
+            eprintln!("Failed to write file '{}': {}", report_path, err);
+            process::exit(1);
+        }
+    }
 }
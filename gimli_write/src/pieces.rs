@@ -0,0 +1,714 @@
+use gimli::write::{
+    AttributeValue, DebuggingInformationEntry, Expression, StringTable, Unit, UnitEntryId,
+};
+
+use crate::dwarf::get_name;
+use crate::location_map::Override;
+use crate::postfix::PostfixExpression;
+
+/* A member of a variable: a field of a structure or union ("a.x") or an element of an array
+ * ("a[3]"). Members chain, e.g. "a[1].x". */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Member {
+    Field(String),
+    Element(u64),
+}
+
+/* A part of a variable: bit_size bits at bit_offset from its start */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Piece {
+    pub bit_offset: u64,
+    pub bit_size: u64,
+}
+
+/* A new location of a piece of a variable over [begin, end). The expression is kept unwritten, as
+ * the composite locations are built from the expressions of several pieces. */
+#[derive(Debug, Clone)]
+pub struct PieceOverride {
+    pub begin: u64,
+    pub end: u64,
+    pub piece: Piece,
+    pub expression: PostfixExpression,
+}
+
+/* An existing location of a whole variable over [begin, end), which the parts of the variable no
+ * mapping sets are taken from. The expression is None if the location cannot be taken apart,
+ * e.g. if it is a composite location already. */
+#[derive(Debug, Clone)]
+pub struct ExistingLocation {
+    pub begin: u64,
+    pub end: u64,
+    pub expression: Option<PostfixExpression>,
+}
+
+/* Splits a variable name into the name of the variable and its members */
+pub fn split_members(name: &str) -> Result<(&str, Vec<Member>), String> {
+    let base_end = name.find(|c| c == '.' || c == '[').unwrap_or(name.len());
+    let (base, mut rest) = name.split_at(base_end);
+    if base.is_empty() {
+        return Err("missing variable name".to_string());
+    }
+    let mut members = Vec::new();
+    while !rest.is_empty() {
+        if let Some(field) = rest.strip_prefix('.') {
+            let end = field.find(|c| c == '.' || c == '[').unwrap_or(field.len());
+            if end == 0 {
+                return Err("missing field name".to_string());
+            }
+            members.push(Member::Field(field[..end].to_string()));
+            rest = &field[end..];
+        } else {
+            let index = &rest[1..];
+            let end = index
+                .find(']')
+                .ok_or_else(|| "missing ']' after index".to_string())?;
+            let element = index[..end]
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| format!("invalid index {}", &index[..end]))?;
+            members.push(Member::Element(element));
+            rest = &index[end + 1..];
+        }
+    }
+    Ok((base, members))
+}
+
+/* The piece of a variable its members stand for and the size of the whole variable in bits, or
+ * None for the whole variable. Frontend SSA names such as "i.0" or "sum.1" read like a field
+ * access, but only structures, unions and arrays have members: for any other variable, the
+ * numeric suffixes are part of its name and the variable itself is meant. */
+pub fn locate_members(
+    unit: &Unit,
+    strings: &StringTable,
+    var: UnitEntryId,
+    members: &[Member],
+) -> Result<Option<(Piece, u64)>, String> {
+    let has_members = get_type(unit.get(var))
+        .and_then(|ty| strip_type(unit, ty))
+        .map(|ty| {
+            let entry = unit.get(ty);
+            is_aggregate(entry) || entry.tag() == gimli::DW_TAG_array_type
+        })
+        .unwrap_or(false);
+    let members = if has_members {
+        members
+    } else {
+        let suffixes = members
+            .iter()
+            .take_while(|member| match member {
+                Member::Field(name) => name.bytes().all(|byte| byte.is_ascii_digit()),
+                Member::Element(_) => false,
+            })
+            .count();
+        &members[suffixes..]
+    };
+    if members.is_empty() {
+        return Ok(None);
+    }
+    locate_member(unit, strings, var, members).map(Some)
+}
+
+/* The size of a variable in bits */
+pub fn get_var_bit_size(unit: &Unit, var: UnitEntryId) -> Result<u64, String> {
+    get_bit_size(unit, get_type(unit.get(var))?)
+}
+
+/* The piece of a variable its members stand for, from the layout of its DW_AT_type, and the size
+ * of the whole variable in bits */
+fn locate_member(
+    unit: &Unit,
+    strings: &StringTable,
+    var: UnitEntryId,
+    members: &[Member],
+) -> Result<(Piece, u64), String> {
+    let mut ty = get_type(unit.get(var))?;
+    let var_size = get_var_bit_size(unit, var)?;
+    let mut piece = Piece {
+        bit_offset: 0,
+        bit_size: var_size,
+    };
+    /* The dimensions of the array being indexed left to index, its elements being of type ty */
+    let mut dims: Vec<u64> = Vec::new();
+    for member in members {
+        match member {
+            Member::Field(name) => {
+                let entry = unit.get(strip_type(unit, ty)?);
+                if !dims.is_empty() || !is_aggregate(entry) {
+                    return Err(format!("no field {}, not a structure or union", name));
+                }
+                let (field, field_ty) = find_field(unit, strings, entry, name)?
+                    .ok_or_else(|| format!("no field {}", name))?;
+                piece = Piece {
+                    bit_offset: piece.bit_offset + field.bit_offset,
+                    bit_size: field.bit_size,
+                };
+                ty = field_ty;
+            }
+            Member::Element(index) => {
+                if dims.is_empty() {
+                    let array = strip_type(unit, ty)?;
+                    if unit.get(array).tag() != gimli::DW_TAG_array_type {
+                        return Err(format!("no element {}, not an array", index));
+                    }
+                    dims = get_dims(unit, array)?;
+                    ty = get_type(unit.get(array))?;
+                }
+                let count = dims.remove(0);
+                if *index >= count {
+                    return Err(format!("element {} out of bounds (of {})", index, count));
+                }
+                let size = get_bit_size(unit, ty)? * dims.iter().product::<u64>();
+                piece = Piece {
+                    bit_offset: piece.bit_offset + index * size,
+                    bit_size: size,
+                };
+            }
+        }
+    }
+    Ok((piece, var_size))
+}
+
+/* Why compose() left out the override of a piece */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dropped {
+    /* Its piece overlaps that of another override over part of its range, by its index */
+    Overlap(usize),
+    /* The existing location of the variable cannot be taken apart anywhere over its range */
+    Unsplittable,
+}
+
+/* Turns the overrides of the pieces of a variable into overrides of the whole variable: over each
+ * range where some of its pieces are set, a composite location made of them, the rest of the
+ * variable being taken from its existing location there, or undefined if it has none. Ranges
+ * where the existing location cannot be taken apart are left as they are. Where the overrides of
+ * a piece overlap, the narrowest one wins like in LocationMap::set_all. The overrides left out
+ * are returned with their index in pieces: those whose piece overlaps another one set first (the
+ * one at the lowest offset, then the narrowest) and those set nowhere. */
+pub fn compose(
+    pieces: &[PieceOverride],
+    var_size: u64,
+    existing: &[ExistingLocation],
+) -> (Vec<Override>, Vec<(usize, Dropped)>) {
+    let mut order: Vec<usize> = (0..pieces.len())
+        .filter(|&index| pieces[index].begin < pieces[index].end)
+        .collect();
+    order.sort_by_key(|&index| {
+        let p = &pieces[index];
+        (p.piece.bit_offset, p.end - p.begin, p.begin)
+    });
+    let mut dropped = Vec::new();
+    let mut kept: Vec<usize> = Vec::new();
+    for index in order {
+        let p = &pieces[index];
+        let overlap = kept.iter().copied().find(|&other| {
+            let q = &pieces[other];
+            q.piece != p.piece
+                && q.begin < p.end
+                && p.begin < q.end
+                && q.piece.bit_offset < p.piece.bit_offset + p.piece.bit_size
+                && p.piece.bit_offset < q.piece.bit_offset + q.piece.bit_size
+        });
+        match overlap {
+            Some(other) => dropped.push((index, Dropped::Overlap(other))),
+            None => kept.push(index),
+        }
+    }
+    kept.sort_by_key(|&index| (pieces[index].end - pieces[index].begin, pieces[index].begin));
+
+    let mut bounds: Vec<u64> = kept
+        .iter()
+        .flat_map(|&index| vec![pieces[index].begin, pieces[index].end])
+        .collect();
+    /* The existing location may change within the range of a piece */
+    bounds.extend(existing.iter().flat_map(|l| vec![l.begin, l.end]));
+    bounds.sort_unstable();
+    bounds.dedup();
+
+    let mut overrides = Vec::new();
+    let mut applied = vec![false; pieces.len()];
+    let mut blocked = vec![false; pieces.len()];
+    for segment in bounds.windows(2) {
+        let (begin, end) = (segment[0], segment[1]);
+        let mut set: Vec<usize> = Vec::new();
+        for &index in &kept {
+            let p = &pieces[index];
+            if p.begin <= begin
+                && end <= p.end
+                && set.iter().all(|&other| pieces[other].piece != p.piece)
+            {
+                set.push(index);
+            }
+        }
+        if set.is_empty() {
+            continue;
+        }
+        set.sort_by_key(|&index| pieces[index].piece.bit_offset);
+        let whole = match existing
+            .iter()
+            .find(|l| l.begin < l.end && l.begin <= begin && end <= l.end)
+        {
+            Some(ExistingLocation {
+                expression: None, ..
+            }) => {
+                for index in set {
+                    blocked[index] = true;
+                }
+                continue;
+            }
+            Some(l) => l.expression.as_ref(),
+            None => None,
+        };
+        let fill = |expression: &mut Expression, bit_offset: u64, bit_size: u64| match whole {
+            Some(whole) => whole.append_part(expression, bit_offset, bit_size),
+            None => add_piece(expression, bit_size),
+        };
+
+        let mut expression = Expression::new();
+        let mut position = 0;
+        for index in set {
+            let p = &pieces[index];
+            if p.piece.bit_offset > position {
+                fill(&mut expression, position, p.piece.bit_offset - position);
+            }
+            p.expression.append_piece(&mut expression, p.piece.bit_size);
+            position = p.piece.bit_offset + p.piece.bit_size;
+            applied[index] = true;
+        }
+        if position < var_size {
+            fill(&mut expression, position, var_size - position);
+        }
+        overrides.push(Override {
+            begin,
+            end,
+            expression,
+        });
+    }
+    dropped.extend(
+        (0..pieces.len())
+            .filter(|&index| blocked[index] && !applied[index])
+            .map(|index| (index, Dropped::Unsplittable)),
+    );
+    dropped.sort_by_key(|&(index, _)| index);
+    (overrides, dropped)
+}
+
+pub fn add_piece(expression: &mut Expression, bit_size: u64) {
+    if bit_size % 8 == 0 {
+        expression.op_piece(bit_size / 8);
+    } else {
+        expression.op_bit_piece(bit_size, 0);
+    }
+}
+
+/* The piece of a structure or union a field is, and its type, looking into the anonymous members
+ * of the structure too */
+fn find_field(
+    unit: &Unit,
+    strings: &StringTable,
+    entry: &DebuggingInformationEntry,
+    name: &str,
+) -> Result<Option<(Piece, UnitEntryId)>, String> {
+    for child in entry.children() {
+        let child = unit.get(*child);
+        if child.tag() != gimli::DW_TAG_member {
+            continue;
+        }
+        match get_name(child, strings) {
+            Some(child_name) if child_name == name.as_bytes() => {
+                let ty = get_type(child)?;
+                let size = match get_constant(child, gimli::DW_AT_bit_size) {
+                    Some(size) => size,
+                    None => get_bit_size(unit, ty)?,
+                };
+                let piece = Piece {
+                    bit_offset: get_member_offset(unit, child, size)?,
+                    bit_size: size,
+                };
+                return Ok(Some((piece, ty)));
+            }
+            Some(_) => {}
+            None => {
+                let inner = unit.get(strip_type(unit, get_type(child)?)?);
+                if is_aggregate(inner) {
+                    if let Some((mut piece, ty)) = find_field(unit, strings, inner, name)? {
+                        piece.bit_offset += get_member_offset(unit, child, 0)?;
+                        return Ok(Some((piece, ty)));
+                    }
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+/* The offset of a member in bits. Bit fields are given either by DW_AT_data_bit_offset, or (before
+ * DWARF 4) by DW_AT_bit_offset, counted from the most significant bit of the storage unit. */
+fn get_member_offset(
+    unit: &Unit,
+    member: &DebuggingInformationEntry,
+    bit_size: u64,
+) -> Result<u64, String> {
+    if let Some(offset) = get_constant(member, gimli::DW_AT_data_bit_offset) {
+        return Ok(offset);
+    }
+    let offset = match member.get(gimli::DW_AT_data_member_location) {
+        Some(value) => get_udata(value)
+            .and_then(|offset| offset.checked_mul(8))
+            .ok_or_else(|| "unsupported member location".to_string())?,
+        None => 0,
+    };
+    match get_constant(member, gimli::DW_AT_bit_offset) {
+        Some(bit_offset) => {
+            let storage_size = match get_constant(member, gimli::DW_AT_byte_size) {
+                Some(byte_size) => byte_size.checked_mul(8),
+                None => Some(get_bit_size(unit, get_type(member)?)?),
+            };
+            storage_size
+                .and_then(|storage_size| storage_size.checked_sub(bit_offset))
+                .and_then(|end| end.checked_sub(bit_size))
+                .and_then(|start| offset.checked_add(start))
+                .ok_or_else(|| "invalid bit offset".to_string())
+        }
+        None => Ok(offset),
+    }
+}
+
+/* The size of a type in bits */
+fn get_bit_size(unit: &Unit, ty: UnitEntryId) -> Result<u64, String> {
+    let ty = strip_type(unit, ty)?;
+    let entry = unit.get(ty);
+    if let Some(byte_size) = get_constant(entry, gimli::DW_AT_byte_size) {
+        return Ok(byte_size * 8);
+    }
+    if entry.tag() == gimli::DW_TAG_array_type {
+        let count: u64 = get_dims(unit, ty)?.iter().product();
+        return Ok(count * get_bit_size(unit, get_type(entry)?)?);
+    }
+    Err("type without a size".to_string())
+}
+
+/* The number of elements along each dimension of an array */
+fn get_dims(unit: &Unit, array: UnitEntryId) -> Result<Vec<u64>, String> {
+    let mut dims = Vec::new();
+    for child in unit.get(array).children() {
+        let subrange = unit.get(*child);
+        if subrange.tag() != gimli::DW_TAG_subrange_type {
+            continue;
+        }
+        let count = match get_constant(subrange, gimli::DW_AT_count) {
+            Some(count) => count,
+            None => {
+                let lower_bound = get_constant(subrange, gimli::DW_AT_lower_bound).unwrap_or(0);
+                get_constant(subrange, gimli::DW_AT_upper_bound)
+                    .ok_or_else(|| "array without bounds".to_string())?
+                    .wrapping_sub(lower_bound)
+                    .wrapping_add(1)
+            }
+        };
+        dims.push(count);
+    }
+    if dims.is_empty() {
+        return Err("array without bounds".to_string());
+    }
+    Ok(dims)
+}
+
+/* Skips typedefs and qualifiers */
+fn strip_type(unit: &Unit, mut ty: UnitEntryId) -> Result<UnitEntryId, String> {
+    loop {
+        match unit.get(ty).tag() {
+            gimli::DW_TAG_typedef
+            | gimli::DW_TAG_const_type
+            | gimli::DW_TAG_volatile_type
+            | gimli::DW_TAG_restrict_type
+            | gimli::DW_TAG_atomic_type => ty = get_type(unit.get(ty))?,
+            _ => return Ok(ty),
+        }
+    }
+}
+
+fn get_type(entry: &DebuggingInformationEntry) -> Result<UnitEntryId, String> {
+    match entry.get(gimli::DW_AT_type) {
+        Some(AttributeValue::UnitRef(ty)) => Ok(*ty),
+        Some(_) => Err("type in another unit".to_string()),
+        None => Err("entry without a type".to_string()),
+    }
+}
+
+fn is_aggregate(entry: &DebuggingInformationEntry) -> bool {
+    matches!(
+        entry.tag(),
+        gimli::DW_TAG_structure_type | gimli::DW_TAG_union_type | gimli::DW_TAG_class_type
+    )
+}
+
+fn get_constant(entry: &DebuggingInformationEntry, name: gimli::DwAt) -> Option<u64> {
+    get_udata(entry.get(name)?)
+}
+
+fn get_udata(value: &AttributeValue) -> Option<u64> {
+    match *value {
+        AttributeValue::Data1(data) => Some(data.into()),
+        AttributeValue::Data2(data) => Some(data.into()),
+        AttributeValue::Data4(data) => Some(data.into()),
+        AttributeValue::Data8(data) | AttributeValue::Udata(data) => Some(data),
+        AttributeValue::Sdata(data) if data >= 0 => Some(data as u64),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gimli::write::LineProgram;
+    use gimli::{read, Encoding, EndianSlice, Format, LittleEndian};
+    use object::Architecture;
+
+    fn new_unit() -> Unit {
+        let encoding = Encoding {
+            format: Format::Dwarf32,
+            version: 4,
+            address_size: 8,
+        };
+        Unit::new(encoding, LineProgram::none())
+    }
+
+    /* A variable of a type of byte_size bytes with the given tag */
+    fn add_var(unit: &mut Unit, tag: gimli::DwTag, byte_size: u8) -> UnitEntryId {
+        let root = unit.root();
+        let ty = unit.add(root, tag);
+        unit.get_mut(ty)
+            .set(gimli::DW_AT_byte_size, AttributeValue::Data1(byte_size));
+        let var = unit.add(root, gimli::DW_TAG_variable);
+        unit.get_mut(var)
+            .set(gimli::DW_AT_type, AttributeValue::UnitRef(ty));
+        var
+    }
+
+    #[test]
+    fn ssa_suffix_names_the_variable() {
+        let mut unit = new_unit();
+        let var = add_var(&mut unit, gimli::DW_TAG_base_type, 4);
+        let (name, members) = split_members("i.0").unwrap();
+        assert_eq!(name, "i");
+        assert_eq!(members, vec![Member::Field("0".to_string())]);
+        let strings = StringTable::default();
+        assert_eq!(locate_members(&unit, &strings, var, &members), Ok(None));
+    }
+
+    #[test]
+    fn field_of_a_scalar_is_rejected() {
+        let mut unit = new_unit();
+        let var = add_var(&mut unit, gimli::DW_TAG_base_type, 4);
+        let (_, members) = split_members("i.x").unwrap();
+        let strings = StringTable::default();
+        assert!(locate_members(&unit, &strings, var, &members).is_err());
+    }
+
+    #[test]
+    fn bit_offset_past_the_storage_unit_is_rejected() {
+        let mut unit = new_unit();
+        let var = add_var(&mut unit, gimli::DW_TAG_structure_type, 4);
+        let ty = match unit.get(var).get(gimli::DW_AT_type) {
+            Some(&AttributeValue::UnitRef(ty)) => ty,
+            _ => unreachable!(),
+        };
+        let field = unit.add(ty, gimli::DW_TAG_member);
+        let entry = unit.get_mut(field);
+        entry.set(gimli::DW_AT_name, AttributeValue::String(b"f".to_vec()));
+        entry.set(gimli::DW_AT_type, AttributeValue::UnitRef(ty));
+        entry.set(gimli::DW_AT_byte_size, AttributeValue::Data1(4));
+        entry.set(gimli::DW_AT_bit_size, AttributeValue::Data1(3));
+        /* The 3 bits under the 2 most significant ones */
+        entry.set(gimli::DW_AT_bit_offset, AttributeValue::Data1(2));
+        let strings = StringTable::default();
+        let (_, members) = split_members("s.f").unwrap();
+        let piece = Piece {
+            bit_offset: 27,
+            bit_size: 3,
+        };
+        assert_eq!(
+            locate_members(&unit, &strings, var, &members),
+            Ok(Some((piece, 32)))
+        );
+        unit.get_mut(field)
+            .set(gimli::DW_AT_bit_offset, AttributeValue::Data1(30));
+        assert_eq!(
+            locate_members(&unit, &strings, var, &members),
+            Err("invalid bit offset".to_string())
+        );
+    }
+
+    #[test]
+    fn members_chain() {
+        assert_eq!(split_members("a"), Ok(("a", Vec::new())));
+        assert_eq!(
+            split_members("a[1].x[ 2 ].y"),
+            Ok((
+                "a",
+                vec![
+                    Member::Element(1),
+                    Member::Field("x".to_string()),
+                    Member::Element(2),
+                    Member::Field("y".to_string()),
+                ]
+            ))
+        );
+        for (name, err) in &[
+            ("[1]", "missing variable name"),
+            ("a..x", "missing field name"),
+            ("a.", "missing field name"),
+            ("a[1", "missing ']' after index"),
+            ("a[x]", "invalid index x"),
+        ] {
+            assert_eq!(split_members(name), Err(err.to_string()), "{}", name);
+        }
+    }
+
+    fn piece_override(
+        begin: u64,
+        end: u64,
+        bit_offset: u64,
+        bit_size: u64,
+        expression: &PostfixExpression,
+    ) -> PieceOverride {
+        PieceOverride {
+            begin,
+            end,
+            piece: Piece {
+                bit_offset,
+                bit_size,
+            },
+            expression: expression.clone(),
+        }
+    }
+
+    /* A composite location of the given pieces, None for those left undefined */
+    fn composite(pieces: &[(Option<&PostfixExpression>, u64)]) -> Expression {
+        let mut expression = Expression::new();
+        for &(piece, bit_size) in pieces {
+            match piece {
+                Some(piece) => piece.append_piece(&mut expression, bit_size),
+                None => add_piece(&mut expression, bit_size),
+            }
+        }
+        expression
+    }
+
+    fn ranges(overrides: Vec<Override>) -> Vec<(u64, u64, Expression)> {
+        overrides
+            .into_iter()
+            .map(|o| (o.begin, o.end, o.expression))
+            .collect()
+    }
+
+    #[test]
+    fn pieces_are_composed_over_each_range() {
+        let a = PostfixExpression::parse("%rax", Architecture::X86_64, false).unwrap();
+        let b = PostfixExpression::parse("%rbx", Architecture::X86_64, false).unwrap();
+        let pieces = vec![
+            piece_override(5, 15, 32, 32, &b),
+            piece_override(0, 10, 0, 32, &a),
+        ];
+        assert_eq!(
+            ranges(compose(&pieces, 64, &[]).0),
+            vec![
+                (0, 5, composite(&[(Some(&a), 32), (None, 32)])),
+                (5, 10, composite(&[(Some(&a), 32), (Some(&b), 32)])),
+                (10, 15, composite(&[(None, 32), (Some(&b), 32)])),
+            ]
+        );
+    }
+
+    #[test]
+    fn narrowest_override_of_a_piece_wins() {
+        let a = PostfixExpression::parse("%rax", Architecture::X86_64, false).unwrap();
+        let b = PostfixExpression::parse("%rbx", Architecture::X86_64, false).unwrap();
+        let pieces = vec![
+            piece_override(4, 8, 8, 8, &b),
+            piece_override(0, 16, 8, 8, &a),
+        ];
+        assert_eq!(
+            ranges(compose(&pieces, 32, &[]).0),
+            vec![
+                (0, 4, composite(&[(None, 8), (Some(&a), 8), (None, 16)])),
+                (4, 8, composite(&[(None, 8), (Some(&b), 8), (None, 16)])),
+                (8, 16, composite(&[(None, 8), (Some(&a), 8), (None, 16)])),
+            ]
+        );
+    }
+
+    #[test]
+    fn overlapping_pieces_are_left_out() {
+        let a = PostfixExpression::parse("%rax", Architecture::X86_64, false).unwrap();
+        let b = PostfixExpression::parse("%rbx", Architecture::X86_64, false).unwrap();
+        let pieces = vec![
+            piece_override(0, 8, 16, 32, &b),
+            piece_override(0, 8, 0, 32, &a),
+            piece_override(8, 8, 32, 32, &b),
+        ];
+        let (overrides, dropped) = compose(&pieces, 64, &[]);
+        assert_eq!(
+            ranges(overrides),
+            vec![(0, 8, composite(&[(Some(&a), 32), (None, 32)]))]
+        );
+        assert_eq!(dropped, vec![(0, Dropped::Overlap(1))]);
+    }
+
+    #[test]
+    fn other_members_keep_their_existing_location() {
+        let eax = PostfixExpression::parse("%eax", Architecture::X86_64, false).unwrap();
+        /* DW_OP_fbreg -32 */
+        let bytecode = [gimli::DW_OP_fbreg.0, 0x60];
+        let encoding = new_unit().encoding();
+        let stack = read::Expression(EndianSlice::new(&bytecode, LittleEndian));
+        let stack = PostfixExpression::from_dwarf(stack, encoding).unwrap();
+        let existing = vec![ExistingLocation {
+            begin: 0,
+            end: 8,
+            expression: Some(stack.clone()),
+        }];
+        /* The field at bytes 4 to 8 of a 16 bytes structure */
+        let pieces = vec![piece_override(4, 12, 32, 32, &eax)];
+        let mut expected = Expression::new();
+        expected.op_fbreg(-32);
+        expected.op_piece(4);
+        eax.append_piece(&mut expected, 32);
+        expected.op_fbreg(-32);
+        expected.op_plus_uconst(8);
+        expected.op_piece(8);
+        assert_eq!(
+            ranges(compose(&pieces, 128, &existing).0),
+            vec![
+                (4, 8, expected),
+                (
+                    8,
+                    12,
+                    composite(&[(None, 32), (Some(&eax), 32), (None, 64)])
+                ),
+            ]
+        );
+
+        /* Where the existing location cannot be taken apart, it is kept */
+        let existing = vec![ExistingLocation {
+            begin: 0,
+            end: 8,
+            expression: None,
+        }];
+        let (overrides, dropped) = compose(&pieces, 128, &existing);
+        assert_eq!(
+            ranges(overrides),
+            vec![(
+                8,
+                12,
+                composite(&[(None, 32), (Some(&eax), 32), (None, 64)])
+            )]
+        );
+        assert_eq!(dropped, Vec::new());
+        /* A piece set nowhere is left out */
+        let pieces = vec![piece_override(2, 6, 32, 32, &eax)];
+        let (overrides, dropped) = compose(&pieces, 128, &existing);
+        assert!(overrides.is_empty());
+        assert_eq!(dropped, vec![(0, Dropped::Unsplittable)]);
+    }
+}
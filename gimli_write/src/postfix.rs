@@ -0,0 +1,495 @@
+use std::fmt;
+
+use gimli::constants::{
+    DW_OP_and, DW_OP_deref, DW_OP_div, DW_OP_eq, DW_OP_ge, DW_OP_gt, DW_OP_le, DW_OP_lt,
+    DW_OP_minus, DW_OP_mod, DW_OP_mul, DW_OP_ne, DW_OP_neg, DW_OP_not, DW_OP_or, DW_OP_plus,
+    DW_OP_shl, DW_OP_shr, DW_OP_shra, DW_OP_stack_value, DW_OP_xor, DwOp,
+};
+use gimli::read::{self, Reader, ReaderOffset};
+use gimli::write::Expression;
+use object::Architecture;
+
+use crate::pieces::add_piece;
+use crate::registers::{get_register_mapping, get_vector_register_size};
+use crate::simplify::simplify;
+
+/* A location expression of a mapping, in postfix notation:
+ * - registers ("%eax") and integers (decimal, or hexadecimal with "0x") push a value, as do the
+ *   frame base of the function ("%fb", its DW_AT_frame_base) and the value a register had on
+ *   entry to the function ("entry(%esi)"), which the debugger recovers from the call site, e.g.
+ *   "%esi entry(%esi) - 4 /" for an induction variable eliminated by strength reduction;
+ * - the binary operators are + - * / % << >> (arithmetic) >>> (logical) & | ^ and the signed
+ *   comparisons == != < <= > >=, which give 1 or 0;
+ * - the unary operators are ~ (bitwise not), neg, sextN and zextN (sign and zero extension of the
+ *   low N bits, N being 8, 16 or 32), and deref or derefN, which replace an address with what is
+ *   stored there (N bytes of it, N being 1, 2, 4 or 8).
+ * The expression leaves a single value, the value of the variable, unless it ends with '@': it is
+ * then the address of the variable, which is written as a memory location so that the variable
+ * can also be modified from a debugger, e.g. "%ebp -12 + @".
+ *
+ * Floating point and vector registers ("%xmm0") cannot be read onto the DWARF stack, they are
+ * only used alone, as the register which holds the variable. A lane of a register ("%xmm1[2]")
+ * holds it too, the lanes being as wide as the variable.
+ *
+ * Sign extension is done with shifts on the address-sized values of the DWARF stack, as
+ * DW_OP_convert needs DWARF 5 and base type entries. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PostfixExpression {
+    operations: Vec<Operation>,
+    form: Form,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Form {
+    Value,
+    Memory,
+    /* A register of size bits, or one of its lanes, holding a value of bit_size bits */
+    Register {
+        register: u16,
+        size: u64,
+        lane: Option<u64>,
+        bit_size: Option<u64>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operation {
+    Register { register: u16, offset: i64 },
+    FrameBase { offset: i64 },
+    EntryValue { register: u16 },
+    Signed(i64),
+    Unsigned(u64),
+    Simple(DwOp),
+    DerefSize(u8),
+}
+
+impl PostfixExpression {
+    /* Fails on unknown components and on the expressions simplify rejects, folding the operations
+     * if fold is set */
+    pub fn parse(text: &str, arch: Architecture, fold: bool) -> Result<Self, String> {
+        let mut components: Vec<&str> = text.split_ascii_whitespace().collect();
+        let is_memory = components.last() == Some(&"@");
+        if is_memory {
+            components.pop();
+        }
+        let address_bits = match arch.address_size() {
+            Some(address_size) => u64::from(address_size.bytes()) * 8,
+            None => 64,
+        };
+        if let [component] = components[..] {
+            if let Some(form) = get_register_location(component, arch, address_bits)? {
+                if is_memory {
+                    return Err("a register has no address".to_string());
+                }
+                return Ok(PostfixExpression {
+                    operations: Vec::new(),
+                    form,
+                });
+            }
+        }
+
+        let mut operations = Vec::new();
+        for component in components {
+            /* "%" is the modulo, not a register */
+            if let Some(op) = get_binary_op(component) {
+                operations.push(Operation::Simple(op));
+            } else if component == "%fb" {
+                operations.push(Operation::FrameBase { offset: 0 });
+            } else if let Some(entry) = component.strip_prefix("entry(") {
+                let reg_name = entry
+                    .strip_suffix(')')
+                    .and_then(|entry| entry.strip_prefix('%'))
+                    .filter(|reg_name| get_vector_register_size(arch, reg_name).is_none())
+                    .ok_or_else(|| format!("invalid entry value {}", component))?;
+                operations.push(Operation::EntryValue {
+                    register: get_register(arch, reg_name)?,
+                });
+            } else if let Some(reg_name) = component.strip_prefix('%') {
+                if reg_name.contains('[') || get_vector_register_size(arch, reg_name).is_some() {
+                    return Err(format!("{} can only be used alone", component));
+                }
+                operations.push(Operation::Register {
+                    register: get_register(arch, reg_name)?,
+                    offset: 0,
+                });
+            } else if let Ok(number) = component.parse::<i64>() {
+                operations.push(Operation::Signed(number));
+            } else if let Some(number) = component
+                .strip_prefix("0x")
+                .and_then(|hex| u64::from_str_radix(hex, 16).ok())
+            {
+                operations.push(Operation::Unsigned(number));
+            } else {
+                operations.extend(get_unary_op(component, address_bits)?);
+            }
+        }
+        Ok(PostfixExpression {
+            operations: simplify(operations, address_bits, fold)?,
+            form: if is_memory { Form::Memory } else { Form::Value },
+        })
+    }
+
+    /* The existing location of a variable, if it is simple enough to take pieces of: a memory
+     * location, a value or a register, computed with the operations of postfix expressions */
+    pub fn from_dwarf<R: Reader>(
+        expression: read::Expression<R>,
+        encoding: gimli::Encoding,
+    ) -> Option<Self> {
+        let mut operations = Vec::new();
+        let mut form = Form::Memory;
+        let mut iter = expression.operations(encoding);
+        while let Some(operation) = iter.next().ok()? {
+            /* A register or DW_OP_stack_value ends the expression */
+            if form != Form::Memory {
+                return None;
+            }
+            match operation {
+                read::Operation::Register { register } if operations.is_empty() => {
+                    form = Form::Register {
+                        register: register.0,
+                        size: 0,
+                        lane: None,
+                        bit_size: None,
+                    };
+                }
+                read::Operation::StackValue if !operations.is_empty() => form = Form::Value,
+                read::Operation::RegisterOffset {
+                    register,
+                    offset,
+                    base_type,
+                } if base_type.0.into_u64() == 0 => operations.push(Operation::Register {
+                    register: register.0,
+                    offset,
+                }),
+                read::Operation::FrameOffset { offset } => {
+                    operations.push(Operation::FrameBase { offset })
+                }
+                read::Operation::EntryValue { expression } => {
+                    let mut entry = read::Expression(expression).operations(encoding);
+                    match (entry.next().ok()?, entry.next().ok()?) {
+                        (Some(read::Operation::Register { register }), None) => {
+                            operations.push(Operation::EntryValue {
+                                register: register.0,
+                            })
+                        }
+                        _ => return None,
+                    }
+                }
+                read::Operation::SignedConstant { value } => {
+                    operations.push(Operation::Signed(value))
+                }
+                read::Operation::UnsignedConstant { value } => {
+                    operations.push(Operation::Unsigned(value))
+                }
+                read::Operation::PlusConstant { value } => {
+                    operations.push(Operation::Unsigned(value));
+                    operations.push(Operation::Simple(DW_OP_plus));
+                }
+                read::Operation::Deref {
+                    base_type,
+                    size,
+                    space: false,
+                } if base_type.0.into_u64() == 0 => {
+                    operations.push(if size == encoding.address_size {
+                        Operation::Simple(DW_OP_deref)
+                    } else {
+                        Operation::DerefSize(size)
+                    })
+                }
+                operation => operations.push(Operation::Simple(get_simple_op(&operation)?)),
+            }
+        }
+        if operations.is_empty() && form == Form::Memory {
+            return None;
+        }
+        Some(PostfixExpression { operations, form })
+    }
+
+    /* A variable of constant value */
+    pub fn constant(value: i64) -> Self {
+        PostfixExpression {
+            operations: vec![Operation::Signed(value)],
+            form: Form::Value,
+        }
+    }
+
+    pub fn uses_frame_base(&self) -> bool {
+        self.operations
+            .iter()
+            .any(|operation| matches!(operation, Operation::FrameBase { .. }))
+    }
+
+    pub fn is_register(&self) -> bool {
+        matches!(self.form, Form::Register { .. })
+    }
+
+    /* Sizes a register location by the type of the variable, or of the member, it holds */
+    pub fn set_bit_size(&mut self, value_size: u64) -> Result<(), String> {
+        if let Form::Register {
+            size,
+            lane,
+            bit_size,
+            ..
+        } = &mut self.form
+        {
+            let fits = match lane
+                .unwrap_or(0)
+                .checked_add(1)
+                .and_then(|lanes| lanes.checked_mul(value_size))
+            {
+                Some(end) => value_size > 0 && end <= *size,
+                None => false,
+            };
+            if !fits {
+                return Err(match lane {
+                    Some(lane) => format!(
+                        "no lane {} of {} bits in a register of {} bits",
+                        lane, value_size, size
+                    ),
+                    None => format!(
+                        "a value of {} bits does not fit a register of {} bits",
+                        value_size, size
+                    ),
+                });
+            }
+            *bit_size = Some(value_size);
+        }
+        Ok(())
+    }
+
+    pub fn to_expression(&self) -> Expression {
+        let mut expression = Expression::new();
+        match self.form {
+            /* A lane is the only piece of the variable */
+            Form::Register {
+                lane: Some(_),
+                bit_size: Some(bit_size),
+                ..
+            } => self.append_piece(&mut expression, bit_size),
+            _ => self.append_to(&mut expression),
+        }
+        expression
+    }
+
+    /* Appends the expression as a piece of bit_size bits of a composite location */
+    pub fn append_piece(&self, expression: &mut Expression, bit_size: u64) {
+        match self.form {
+            Form::Register {
+                register,
+                lane: Some(lane),
+                ..
+            } if lane > 0 => {
+                expression.op_reg(gimli::Register(register));
+                expression.op_bit_piece(bit_size, lane * bit_size);
+            }
+            _ => {
+                self.append_to(expression);
+                add_piece(expression, bit_size);
+            }
+        }
+    }
+
+    /* Appends the bit_size bits at bit_offset of the variable whose whole location this is, as a
+     * piece of a composite location */
+    pub fn append_part(&self, expression: &mut Expression, bit_offset: u64, bit_size: u64) {
+        self.append_to(expression);
+        if self.form == Form::Memory && bit_offset % 8 == 0 && bit_size % 8 == 0 {
+            if bit_offset > 0 {
+                expression.op_plus_uconst(bit_offset / 8);
+            }
+            expression.op_piece(bit_size / 8);
+        } else {
+            expression.op_bit_piece(bit_size, bit_offset);
+        }
+    }
+
+    fn append_to(&self, expression: &mut Expression) {
+        if let Form::Register { register, .. } = self.form {
+            expression.op_reg(gimli::Register(register));
+            return;
+        }
+        for operation in &self.operations {
+            match *operation {
+                Operation::Register { register, offset } => {
+                    expression.op_breg(gimli::Register(register), offset)
+                }
+                Operation::FrameBase { offset } => expression.op_fbreg(offset),
+                Operation::EntryValue { register } => {
+                    let mut entry = Expression::new();
+                    entry.op_reg(gimli::Register(register));
+                    expression.op_entry_value(entry);
+                }
+                Operation::Signed(value) => expression.op_consts(value),
+                Operation::Unsigned(value) => expression.op_constu(value),
+                Operation::Simple(op) => expression.op(op),
+                Operation::DerefSize(size) => expression.op_deref_size(size),
+            }
+        }
+        if self.form == Form::Value {
+            expression.op(DW_OP_stack_value);
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operation::Register { register, offset } => {
+                write!(f, "DW_OP_bregx {} {}", register, offset)
+            }
+            Operation::FrameBase { offset } => write!(f, "DW_OP_fbreg {}", offset),
+            Operation::EntryValue { register } => {
+                write!(f, "DW_OP_entry_value(DW_OP_regx {})", register)
+            }
+            Operation::Signed(value) => write!(f, "DW_OP_consts {}", value),
+            Operation::Unsigned(value) => write!(f, "DW_OP_constu {}", value),
+            Operation::Simple(op) => write!(f, "{}", op),
+            Operation::DerefSize(size) => write!(f, "DW_OP_deref_size {}", size),
+        }
+    }
+}
+
+/* A floating point or vector register, or a lane of any register, e.g. "%xmm1[2]" */
+fn get_register_location(
+    component: &str,
+    arch: Architecture,
+    address_bits: u64,
+) -> Result<Option<Form>, String> {
+    let reg_name = match component.strip_prefix('%') {
+        Some(reg_name) => reg_name,
+        None => return Ok(None),
+    };
+    let (reg_name, lane) = match reg_name.find('[') {
+        Some(index) => {
+            let lane = reg_name[index + 1..]
+                .strip_suffix(']')
+                .and_then(|lane| lane.parse::<u64>().ok())
+                .ok_or_else(|| format!("invalid lane in {}", component))?;
+            (&reg_name[..index], Some(lane))
+        }
+        None => (reg_name, None),
+    };
+    let size = match (get_vector_register_size(arch, reg_name), lane) {
+        (Some(size), _) => size,
+        (None, Some(_)) => address_bits,
+        (None, None) => return Ok(None),
+    };
+    Ok(Some(Form::Register {
+        register: get_register(arch, reg_name)?,
+        size,
+        lane,
+        bit_size: None,
+    }))
+}
+
+fn get_register(arch: Architecture, reg_name: &str) -> Result<u16, String> {
+    get_register_mapping(arch, reg_name).ok_or_else(|| format!("unknown register %{}", reg_name))
+}
+
+/* The operations without operands which postfix expressions have too */
+fn get_simple_op<R: Reader>(operation: &read::Operation<R>) -> Option<DwOp> {
+    let op = match operation {
+        read::Operation::Plus => DW_OP_plus,
+        read::Operation::Minus => DW_OP_minus,
+        read::Operation::Mul => DW_OP_mul,
+        read::Operation::Div => DW_OP_div,
+        read::Operation::Mod => DW_OP_mod,
+        read::Operation::Shl => DW_OP_shl,
+        read::Operation::Shra => DW_OP_shra,
+        read::Operation::Shr => DW_OP_shr,
+        read::Operation::And => DW_OP_and,
+        read::Operation::Or => DW_OP_or,
+        read::Operation::Xor => DW_OP_xor,
+        read::Operation::Eq => DW_OP_eq,
+        read::Operation::Ne => DW_OP_ne,
+        read::Operation::Lt => DW_OP_lt,
+        read::Operation::Le => DW_OP_le,
+        read::Operation::Gt => DW_OP_gt,
+        read::Operation::Ge => DW_OP_ge,
+        read::Operation::Not => DW_OP_not,
+        read::Operation::Neg => DW_OP_neg,
+        _ => return None,
+    };
+    Some(op)
+}
+
+fn get_binary_op(s: &str) -> Option<DwOp> {
+    match s {
+        "+" => Some(DW_OP_plus),
+        "-" => Some(DW_OP_minus),
+        "*" => Some(DW_OP_mul),
+        "/" => Some(DW_OP_div),
+        "%" => Some(DW_OP_mod),
+        "<<" => Some(DW_OP_shl),
+        ">>" => Some(DW_OP_shra),
+        ">>>" => Some(DW_OP_shr),
+        "&" => Some(DW_OP_and),
+        "|" => Some(DW_OP_or),
+        "^" => Some(DW_OP_xor),
+        "==" => Some(DW_OP_eq),
+        "!=" => Some(DW_OP_ne),
+        "<" => Some(DW_OP_lt),
+        "<=" => Some(DW_OP_le),
+        ">" => Some(DW_OP_gt),
+        ">=" => Some(DW_OP_ge),
+        _ => None,
+    }
+}
+
+fn get_unary_op(s: &str, address_bits: u64) -> Result<Vec<Operation>, String> {
+    let sized = |prefix: &str, sizes: &[u64]| {
+        s.strip_prefix(prefix)
+            .and_then(|size| size.parse::<u64>().ok())
+            .filter(|size| sizes.contains(size))
+    };
+    let operations = match s {
+        "~" => vec![Operation::Simple(DW_OP_not)],
+        "neg" => vec![Operation::Simple(DW_OP_neg)],
+        "deref" => vec![Operation::Simple(DW_OP_deref)],
+        _ => {
+            if let Some(size) = sized("deref", &[1, 2, 4, 8]) {
+                if size * 8 > address_bits {
+                    return Err(format!("{} is wider than an address", s));
+                }
+                vec![Operation::DerefSize(size as u8)]
+            } else if let Some(bits) = sized("sext", &[8, 16, 32]) {
+                /* Nothing to extend if the values are no wider */
+                match address_bits.checked_sub(bits).filter(|&shift| shift > 0) {
+                    Some(shift) => vec![
+                        Operation::Unsigned(shift),
+                        Operation::Simple(DW_OP_shl),
+                        Operation::Unsigned(shift),
+                        Operation::Simple(DW_OP_shra),
+                    ],
+                    None => Vec::new(),
+                }
+            } else if let Some(bits) = sized("zext", &[8, 16, 32]) {
+                if bits < address_bits {
+                    vec![
+                        Operation::Unsigned((1 << bits) - 1),
+                        Operation::Simple(DW_OP_and),
+                    ]
+                } else {
+                    Vec::new()
+                }
+            } else {
+                return Err(format!("invalid component {}", s));
+            }
+        }
+    };
+    Ok(operations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_registers_are_rejected() {
+        for text in &["%foo", "%rxa 8 +", "entry(%foo)", "%foo[1]"] {
+            let err = PostfixExpression::parse(text, Architecture::X86_64, true).unwrap_err();
+            assert!(err.starts_with("unknown register %"), "{}: {}", text, err);
+        }
+        assert!(PostfixExpression::parse("%r9d 8 +", Architecture::X86_64, true).is_ok());
+    }
+}
@@ -0,0 +1,369 @@
+use object::Architecture;
+
+/* DWARF register numbers as per the psABI of each supported architecture. Register names are
+ * expected without the leading '%'. Sub-registers (e.g. "ax", "r9d") are mapped to the DWARF
+ * number of the full register which contains them. An unknown register name maps to None. */
+
+pub fn get_register_mapping(arch: Architecture, reg_name: &str) -> Option<u16> {
+    match arch {
+        Architecture::X86_64 => get_x86_64_register_mapping(reg_name),
+        Architecture::Aarch64 => get_aarch64_register_mapping(reg_name),
+        Architecture::Riscv32 | Architecture::Riscv64 => get_riscv_register_mapping(reg_name),
+        /* i386 is also the fallback for objects whose architecture could not be identified */
+        _ => get_i386_register_mapping(reg_name),
+    }
+}
+
+/* The size in bits of a floating point or vector register, whose values a DWARF expression can
+ * only refer to as register locations. None for the other registers, and for the scalable vector
+ * registers of SVE and RVV. */
+pub fn get_vector_register_size(arch: Architecture, reg_name: &str) -> Option<u64> {
+    get_register_mapping(arch, reg_name)?;
+    let prefix = reg_name.trim_end_matches(|c: char| c.is_ascii_digit());
+    match (arch, prefix) {
+        (Architecture::Aarch64, "v") | (Architecture::Aarch64, "q") => Some(128),
+        (Architecture::Aarch64, "d") => Some(64),
+        (Architecture::Aarch64, "s") => Some(32),
+        (Architecture::Aarch64, "h") => Some(16),
+        (Architecture::Aarch64, "b") => Some(8),
+        (Architecture::Riscv32, "f")
+        | (Architecture::Riscv32, "ft")
+        | (Architecture::Riscv32, "fs")
+        | (Architecture::Riscv32, "fa")
+        | (Architecture::Riscv64, "f")
+        | (Architecture::Riscv64, "ft")
+        | (Architecture::Riscv64, "fs")
+        | (Architecture::Riscv64, "fa") => Some(64),
+        (Architecture::Aarch64, _) | (Architecture::Riscv32, _) | (Architecture::Riscv64, _) => {
+            None
+        }
+        (_, "xmm") => Some(128),
+        (_, "ymm") => Some(256),
+        (_, "zmm") => Some(512),
+        (_, "st") => Some(80),
+        (_, "mm") => Some(64),
+        _ => None,
+    }
+}
+
+fn get_numbered_register(reg_name: &str, prefix: &str) -> Option<u16> {
+    reg_name.strip_prefix(prefix)?.parse().ok()
+}
+
+/* See Intel386 psABI version 1.1 -- "DWARF Register Number Mapping" */
+fn get_i386_register_mapping(reg_name: &str) -> Option<u16> {
+    match reg_name {
+        "eax" | "ax" | "ah" | "al" => Some(0),
+        "ecx" | "cx" | "ch" | "cl" => Some(1),
+        "edx" | "dx" | "dh" | "dl" => Some(2),
+        "ebx" | "bx" | "bh" | "bl" => Some(3),
+        "esp" | "sp" => Some(4),
+        "ebp" | "bp" => Some(5),
+        "esi" | "si" => Some(6),
+        "edi" | "di" => Some(7),
+        "eip" => Some(8),
+        "eflags" => Some(9),
+        "es" => Some(40),
+        "cs" => Some(41),
+        "ss" => Some(42),
+        "ds" => Some(43),
+        "fs" => Some(44),
+        "gs" => Some(45),
+        _ => {
+            if let Some(num) = get_numbered_register(reg_name, "xmm") {
+                if num < 8 {
+                    return Some(21 + num);
+                }
+            } else if let Some(num) = get_numbered_register(reg_name, "st") {
+                if num < 8 {
+                    return Some(11 + num);
+                }
+            } else if let Some(num) = get_numbered_register(reg_name, "mm") {
+                if num < 8 {
+                    return Some(29 + num);
+                }
+            } else if let Some(num) = get_numbered_register(reg_name, "k") {
+                if num < 8 {
+                    return Some(93 + num);
+                }
+            }
+            None
+        }
+    }
+}
+
+/* See System V AMD64 psABI -- "DWARF Register Number Mapping". The ymm and zmm registers share
+ * the DWARF numbers of the xmm registers they extend. */
+fn get_x86_64_register_mapping(reg_name: &str) -> Option<u16> {
+    match reg_name {
+        "rax" | "eax" | "ax" | "ah" | "al" => Some(0),
+        "rdx" | "edx" | "dx" | "dh" | "dl" => Some(1),
+        "rcx" | "ecx" | "cx" | "ch" | "cl" => Some(2),
+        "rbx" | "ebx" | "bx" | "bh" | "bl" => Some(3),
+        "rsi" | "esi" | "si" | "sil" => Some(4),
+        "rdi" | "edi" | "di" | "dil" => Some(5),
+        "rbp" | "ebp" | "bp" | "bpl" => Some(6),
+        "rsp" | "esp" | "sp" | "spl" => Some(7),
+        "rip" => Some(16),
+        "rflags" | "eflags" => Some(49),
+        "es" => Some(50),
+        "cs" => Some(51),
+        "ss" => Some(52),
+        "ds" => Some(53),
+        "fs" => Some(54),
+        "gs" => Some(55),
+        "fs.base" => Some(58),
+        "gs.base" => Some(59),
+        _ => {
+            if let Some(suffix) = reg_name.strip_prefix('r') {
+                /* r8..r15 along with their d(word), w(ord) and b(yte)/l(ow byte) forms */
+                let num = suffix.trim_end_matches(&['d', 'w', 'b', 'l'][..]);
+                if suffix.len() - num.len() <= 1 {
+                    if let Ok(num) = num.parse::<u16>() {
+                        if (8..16).contains(&num) {
+                            return Some(num);
+                        }
+                    }
+                }
+            } else if let Some(num) = get_numbered_register(reg_name, "xmm")
+                .or_else(|| get_numbered_register(reg_name, "ymm"))
+                .or_else(|| get_numbered_register(reg_name, "zmm"))
+            {
+                if num < 16 {
+                    return Some(17 + num);
+                } else if num < 32 {
+                    return Some(67 + (num - 16));
+                }
+            } else if let Some(num) = get_numbered_register(reg_name, "st") {
+                if num < 8 {
+                    return Some(33 + num);
+                }
+            } else if let Some(num) = get_numbered_register(reg_name, "mm") {
+                if num < 8 {
+                    return Some(41 + num);
+                }
+            } else if let Some(num) = get_numbered_register(reg_name, "k") {
+                if num < 8 {
+                    return Some(118 + num);
+                }
+            }
+            None
+        }
+    }
+}
+
+/* See DWARF for the Arm 64-bit Architecture (AArch64) -- "DWARF register names". The b, h, s,
+ * d and q views of a SIMD register share the DWARF number of the v register. */
+fn get_aarch64_register_mapping(reg_name: &str) -> Option<u16> {
+    match reg_name {
+        "fp" => Some(29),
+        "lr" => Some(30),
+        "sp" | "wsp" => Some(31),
+        "pc" => Some(32),
+        "vg" => Some(46),
+        "ffr" => Some(47),
+        _ => {
+            if let Some(num) = get_numbered_register(reg_name, "x")
+                .or_else(|| get_numbered_register(reg_name, "w"))
+            {
+                if num < 31 {
+                    return Some(num);
+                }
+            } else if let Some(num) = get_numbered_register(reg_name, "v")
+                .or_else(|| get_numbered_register(reg_name, "q"))
+                .or_else(|| get_numbered_register(reg_name, "d"))
+                .or_else(|| get_numbered_register(reg_name, "s"))
+                .or_else(|| get_numbered_register(reg_name, "h"))
+                .or_else(|| get_numbered_register(reg_name, "b"))
+            {
+                if num < 32 {
+                    return Some(64 + num);
+                }
+            } else if let Some(num) = get_numbered_register(reg_name, "p") {
+                if num < 16 {
+                    return Some(48 + num);
+                }
+            } else if let Some(num) = get_numbered_register(reg_name, "z") {
+                if num < 32 {
+                    return Some(96 + num);
+                }
+            }
+            None
+        }
+    }
+}
+
+/* See RISC-V ELF psABI -- "DWARF Register Numbers". Both the numeric (x5, f10) and the ABI
+ * (t0, fa0) register names are accepted. */
+fn get_riscv_register_mapping(reg_name: &str) -> Option<u16> {
+    match reg_name {
+        "zero" => Some(0),
+        "ra" => Some(1),
+        "sp" => Some(2),
+        "gp" => Some(3),
+        "tp" => Some(4),
+        "fp" => Some(8),
+        _ => {
+            let (prefix, num) = match reg_name.find(|c: char| c.is_ascii_digit()) {
+                Some(index) => (&reg_name[..index], reg_name[index..].parse::<u16>().ok()),
+                None => return None,
+            };
+            let num = num?;
+            let gpr = match (prefix, num) {
+                ("x", 0..=31) => Some(num),
+                ("t", 0..=2) => Some(5 + num),
+                ("t", 3..=6) => Some(28 + (num - 3)),
+                ("s", 0..=1) => Some(8 + num),
+                ("s", 2..=11) => Some(18 + (num - 2)),
+                ("a", 0..=7) => Some(10 + num),
+                _ => None,
+            };
+            if let Some(gpr) = gpr {
+                return Some(gpr);
+            }
+            let fpr = match (prefix, num) {
+                ("f", 0..=31) => Some(num),
+                ("ft", 0..=7) => Some(num),
+                ("ft", 8..=11) => Some(28 + (num - 8)),
+                ("fs", 0..=1) => Some(8 + num),
+                ("fs", 2..=11) => Some(18 + (num - 2)),
+                ("fa", 0..=7) => Some(10 + num),
+                _ => None,
+            };
+            if let Some(fpr) = fpr {
+                return Some(32 + fpr);
+            }
+            if let ("v", 0..=31) = (prefix, num) {
+                return Some(96 + num);
+            }
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_mappings(arch: Architecture, mappings: &[(&str, Option<u16>)]) {
+        for &(reg_name, number) in mappings {
+            assert_eq!(
+                get_register_mapping(arch, reg_name),
+                number,
+                "{:?} {}",
+                arch,
+                reg_name
+            );
+        }
+    }
+
+    #[test]
+    fn x86_64_registers_are_mapped() {
+        check_mappings(
+            Architecture::X86_64,
+            &[
+                ("rax", Some(0)),
+                ("ax", Some(0)),
+                ("rdx", Some(1)),
+                ("sil", Some(4)),
+                ("rsp", Some(7)),
+                ("r8", Some(8)),
+                ("r9d", Some(9)),
+                ("r10w", Some(10)),
+                ("r15b", Some(15)),
+                ("r11l", Some(11)),
+                ("rip", Some(16)),
+                ("xmm0", Some(17)),
+                ("xmm3", Some(20)),
+                ("ymm15", Some(32)),
+                ("zmm16", Some(67)),
+                ("zmm17", Some(68)),
+                ("st1", Some(34)),
+                ("mm7", Some(48)),
+                ("k2", Some(120)),
+                ("r16", None),
+                ("r9dw", None),
+                ("xmm32", None),
+                ("eip", None),
+                ("w3", None),
+                ("", None),
+            ],
+        );
+    }
+
+    #[test]
+    fn i386_registers_are_mapped() {
+        check_mappings(
+            Architecture::I386,
+            &[
+                ("eax", Some(0)),
+                ("ecx", Some(1)),
+                ("eip", Some(8)),
+                ("xmm7", Some(28)),
+                ("st0", Some(11)),
+                ("xmm8", None),
+                ("rax", None),
+            ],
+        );
+    }
+
+    #[test]
+    fn aarch64_registers_are_mapped() {
+        check_mappings(
+            Architecture::Aarch64,
+            &[
+                ("x0", Some(0)),
+                ("w3", Some(3)),
+                ("x30", Some(30)),
+                ("fp", Some(29)),
+                ("sp", Some(31)),
+                ("wsp", Some(31)),
+                ("v0", Some(64)),
+                ("q1", Some(65)),
+                ("d31", Some(95)),
+                ("b2", Some(66)),
+                ("p15", Some(63)),
+                ("z0", Some(96)),
+                ("x31", None),
+                ("v32", None),
+                ("p16", None),
+                ("rax", None),
+                ("a0", None),
+            ],
+        );
+    }
+
+    #[test]
+    fn riscv_registers_are_mapped() {
+        for &arch in &[Architecture::Riscv32, Architecture::Riscv64] {
+            check_mappings(
+                arch,
+                &[
+                    ("zero", Some(0)),
+                    ("ra", Some(1)),
+                    ("x5", Some(5)),
+                    ("t0", Some(5)),
+                    ("t3", Some(28)),
+                    ("fp", Some(8)),
+                    ("s0", Some(8)),
+                    ("s1", Some(9)),
+                    ("s2", Some(18)),
+                    ("a0", Some(10)),
+                    ("a7", Some(17)),
+                    ("f0", Some(32)),
+                    ("ft8", Some(60)),
+                    ("fa2", Some(44)),
+                    ("fs11", Some(59)),
+                    ("v31", Some(127)),
+                    ("x32", None),
+                    ("a8", None),
+                    ("t7", None),
+                    ("fa8", None),
+                    ("rax", None),
+                    ("w3", None),
+                    ("pc", None),
+                ],
+            );
+        }
+    }
+}
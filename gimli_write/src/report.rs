@@ -0,0 +1,291 @@
+use gimli::constants::DwOp;
+use gimli::read::{self, Reader, ReaderOffset};
+use serde::Serialize;
+
+use crate::Mapping;
+
+/* What the rewriter did with the mappings of an object: for each function, the variables it found
+ * along with their locations before and after the rewrite, and which mappings were applied or
+ * skipped, and why. PC ranges are offsets from the start of the function, like those of the
+ * mappings. */
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RewriteReport {
+    pub functions: Vec<FunctionReport>,
+    pub counts: Counts,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FunctionReport {
+    pub name: String,
+    pub variables: Vec<VariableReport>,
+    /* The mappings skipped before their variable was found, or whose variable was not found */
+    pub unresolved: Vec<MappingReport>,
+    pub counts: Counts,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct VariableReport {
+    pub name: Option<String>,
+    /* The .debug_info offset of the entry of the variable in the input */
+    pub offset: u64,
+    pub changed: bool,
+    pub before: Vec<LocationReport>,
+    pub after: Vec<LocationReport>,
+    pub mappings: Vec<MappingReport>,
+}
+
+/* An entry of a location list, its expression written as llvm-dwarfdump does */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LocationReport {
+    pub begin: u64,
+    pub end: u64,
+    pub expression: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MappingReport {
+    pub variable: String,
+    pub expression: String,
+    pub begin: u64,
+    pub end: u64,
+    pub line: Option<usize>,
+    pub status: MappingStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/* A duplicate is a mapping over the same range and with the same expression as another one */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MappingStatus {
+    Applied,
+    Duplicate,
+    Skipped,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Counts {
+    pub mappings: usize,
+    pub applied: usize,
+    pub duplicates: usize,
+    pub skipped: usize,
+    pub variables_changed: usize,
+}
+
+impl RewriteReport {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("a report is always serializable")
+    }
+
+    /* The report of a function, added after the others if there is none yet */
+    pub(crate) fn function(&mut self, name: &str) -> &mut FunctionReport {
+        let index = match self.functions.iter().position(|f| f.name == name) {
+            Some(index) => index,
+            None => {
+                self.functions.push(FunctionReport {
+                    name: name.to_string(),
+                    ..FunctionReport::default()
+                });
+                self.functions.len() - 1
+            }
+        };
+        &mut self.functions[index]
+    }
+
+    /* Counts the mappings and the variables of each function, and of the whole object */
+    pub(crate) fn count(&mut self) {
+        self.counts = Counts::default();
+        for function in &mut self.functions {
+            function.counts = Counts::default();
+            let mappings = function
+                .variables
+                .iter()
+                .flat_map(|var| &var.mappings)
+                .chain(&function.unresolved);
+            for mapping in mappings {
+                function.counts.add(mapping.status);
+            }
+            function.counts.variables_changed =
+                function.variables.iter().filter(|var| var.changed).count();
+            self.counts.merge(&function.counts);
+        }
+    }
+}
+
+impl MappingReport {
+    pub(crate) fn new(mapping: &Mapping, status: MappingStatus, reason: Option<String>) -> Self {
+        MappingReport {
+            variable: mapping.variable.clone(),
+            expression: mapping.expression.clone(),
+            begin: mapping.pc_range.0,
+            end: mapping.pc_range.1,
+            line: mapping.line,
+            status,
+            reason,
+        }
+    }
+}
+
+impl Counts {
+    fn add(&mut self, status: MappingStatus) {
+        self.mappings += 1;
+        match status {
+            MappingStatus::Applied => self.applied += 1,
+            MappingStatus::Duplicate => self.duplicates += 1,
+            MappingStatus::Skipped => self.skipped += 1,
+        }
+    }
+
+    fn merge(&mut self, other: &Counts) {
+        self.mappings += other.mappings;
+        self.applied += other.applied;
+        self.duplicates += other.duplicates;
+        self.skipped += other.skipped;
+        self.variables_changed += other.variables_changed;
+    }
+}
+
+/* The operations of an expression separated by commas, e.g. "DW_OP_breg7 -8, DW_OP_deref".
+ * address gives the value of the operand of a DW_OP_addr as read. */
+pub fn format_expression<R: Reader>(
+    expression: read::Expression<R>,
+    encoding: gimli::Encoding,
+    address: &dyn Fn(u64) -> u64,
+) -> String {
+    let mut bytes = expression.0;
+    let mut operations = Vec::new();
+    while !bytes.is_empty() {
+        let opcode = match bytes.clone().read_u8() {
+            Ok(opcode) => DwOp(opcode),
+            Err(_) => break,
+        };
+        match read::Operation::parse(&mut bytes, encoding) {
+            Ok(operation) => {
+                operations.push(format_operation(opcode, operation, encoding, address))
+            }
+            Err(err) => {
+                operations.push(format!("<{}: {}>", opcode, err));
+                break;
+            }
+        }
+    }
+    operations.join(", ")
+}
+
+fn format_operation<R: Reader>(
+    opcode: DwOp,
+    operation: read::Operation<R>,
+    encoding: gimli::Encoding,
+    address: &dyn Fn(u64) -> u64,
+) -> String {
+    let operands = match operation {
+        /* The operands of these are part of their opcode */
+        read::Operation::UnsignedConstant { .. }
+            if (gimli::DW_OP_lit0.0..=gimli::DW_OP_lit31.0).contains(&opcode.0) =>
+        {
+            Vec::new()
+        }
+        read::Operation::Register { .. }
+            if (gimli::DW_OP_reg0.0..=gimli::DW_OP_reg31.0).contains(&opcode.0) =>
+        {
+            Vec::new()
+        }
+        read::Operation::Pick { .. } if opcode != gimli::DW_OP_pick => Vec::new(),
+        read::Operation::Deref { .. }
+            if opcode == gimli::DW_OP_deref || opcode == gimli::DW_OP_xderef =>
+        {
+            Vec::new()
+        }
+        read::Operation::Deref {
+            base_type, size, ..
+        } => {
+            let mut operands = vec![size.to_string()];
+            if opcode == gimli::DW_OP_deref_type || opcode == gimli::DW_OP_xderef_type {
+                operands.push(format!("0x{:x}", base_type.0.into_u64()));
+            }
+            operands
+        }
+        read::Operation::Pick { index } => vec![index.to_string()],
+        read::Operation::PlusConstant { value } | read::Operation::UnsignedConstant { value } => {
+            vec![value.to_string()]
+        }
+        read::Operation::SignedConstant { value } => vec![value.to_string()],
+        read::Operation::Bra { target } | read::Operation::Skip { target } => {
+            vec![target.to_string()]
+        }
+        read::Operation::Register { register } => vec![register.0.to_string()],
+        read::Operation::RegisterOffset {
+            register,
+            offset,
+            base_type,
+        } => {
+            if opcode == gimli::DW_OP_regval_type {
+                vec![
+                    register.0.to_string(),
+                    format!("0x{:x}", base_type.0.into_u64()),
+                ]
+            } else if opcode == gimli::DW_OP_bregx {
+                vec![register.0.to_string(), offset.to_string()]
+            } else {
+                vec![offset.to_string()]
+            }
+        }
+        read::Operation::FrameOffset { offset } => vec![offset.to_string()],
+        read::Operation::Call { offset } => match offset {
+            read::DieReference::UnitRef(offset) => vec![format!("0x{:x}", offset.0.into_u64())],
+            read::DieReference::DebugInfoRef(offset) => {
+                vec![format!("0x{:x}", offset.0.into_u64())]
+            }
+        },
+        read::Operation::Piece {
+            size_in_bits,
+            bit_offset,
+        } => match bit_offset {
+            Some(bit_offset) => vec![size_in_bits.to_string(), bit_offset.to_string()],
+            None => vec![(size_in_bits / 8).to_string()],
+        },
+        read::Operation::ImplicitValue { data } => {
+            vec![data.len().into_u64().to_string(), format_bytes(data)]
+        }
+        read::Operation::ImplicitPointer { value, byte_offset } => vec![
+            format!("0x{:x}", value.0.into_u64()),
+            byte_offset.to_string(),
+        ],
+        read::Operation::EntryValue { expression } => {
+            return format!(
+                "{}({})",
+                opcode,
+                format_expression(read::Expression(expression), encoding, address)
+            )
+        }
+        read::Operation::ParameterRef { offset } => vec![format!("0x{:x}", offset.0.into_u64())],
+        read::Operation::Address { address: value } => vec![format!("0x{:x}", address(value))],
+        read::Operation::AddressIndex { index } | read::Operation::ConstantIndex { index } => {
+            vec![index.0.into_u64().to_string()]
+        }
+        read::Operation::TypedLiteral { base_type, value } => vec![
+            format!("0x{:x}", base_type.0.into_u64()),
+            format_bytes(value),
+        ],
+        read::Operation::Convert { base_type } | read::Operation::Reinterpret { base_type } => {
+            vec![format!("0x{:x}", base_type.0.into_u64())]
+        }
+        _ => Vec::new(),
+    };
+    let mut text = opcode.to_string();
+    for operand in operands {
+        text.push(' ');
+        text.push_str(&operand);
+    }
+    text
+}
+
+fn format_bytes<R: Reader>(data: R) -> String {
+    match data.to_slice() {
+        Ok(bytes) => {
+            let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+            format!("0x{}", hex)
+        }
+        Err(_) => "?".to_string(),
+    }
+}
@@ -0,0 +1,425 @@
+use gimli::constants::DwOp;
+
+use crate::postfix::Operation;
+
+/* A value on the stack of an expression being evaluated: the operations which push it, and the
+ * value itself when it is a constant */
+struct Value {
+    operations: Vec<Operation>,
+    constant: Option<u64>,
+}
+
+/* Evaluates the operations of an expression on values of address_bits bits, checking that each
+ * operation has its operands and that a single value is left, like gdb does before reading a
+ * location. Divisions by a constant zero are rejected, as gdb fails on them.
+ *
+ * If fold is set, constants are folded, a register or the frame base plus or minus a constant
+ * becomes a single DW_OP_breg or DW_OP_fbreg, and operations which leave their operand as it is
+ * (e.g. adding 0) are dropped. Otherwise the operations are kept as they are, but for a register
+ * directly followed by a decimal constant and + or -, which is a single DW_OP_breg. */
+pub fn simplify(
+    operations: Vec<Operation>,
+    address_bits: u64,
+    fold: bool,
+) -> Result<Vec<Operation>, String> {
+    let arith = Arith::new(address_bits, fold);
+    let mut stack: Vec<Value> = Vec::new();
+    for operation in operations {
+        let value = match operation {
+            Operation::Register { .. }
+            | Operation::FrameBase { .. }
+            | Operation::EntryValue { .. } => Value {
+                operations: vec![operation],
+                constant: None,
+            },
+            Operation::Signed(value) if fold => arith.constant(value as u64),
+            Operation::Unsigned(value) if fold => arith.constant(value),
+            Operation::Signed(value) => Value {
+                operations: vec![operation],
+                constant: Some(value as u64 & arith.mask),
+            },
+            Operation::Unsigned(value) => Value {
+                operations: vec![operation],
+                constant: Some(value & arith.mask),
+            },
+            Operation::DerefSize(_) | Operation::Simple(gimli::DW_OP_deref) => {
+                let mut value = pop(&mut stack, &operation)?;
+                value.operations.push(operation);
+                value.constant = None;
+                value
+            }
+            Operation::Simple(op) if op == gimli::DW_OP_not || op == gimli::DW_OP_neg => {
+                let mut value = pop(&mut stack, &operation)?;
+                match value.constant {
+                    Some(constant) if fold && op == gimli::DW_OP_not => arith.constant(!constant),
+                    Some(constant) if fold => arith.constant(constant.wrapping_neg()),
+                    _ => {
+                        value.operations.push(operation);
+                        value.constant = None;
+                        value
+                    }
+                }
+            }
+            Operation::Simple(op) => {
+                let right = pop(&mut stack, &operation)?;
+                let left = pop(&mut stack, &operation)?;
+                arith.binary(op, left, right)?
+            }
+        };
+        stack.push(value);
+    }
+    match stack.len() {
+        0 => Err("empty expression".to_string()),
+        1 => Ok(stack.pop().unwrap().operations),
+        depth => Err(format!("{} values left instead of one", depth)),
+    }
+}
+
+fn pop(stack: &mut Vec<Value>, operation: &Operation) -> Result<Value, String> {
+    stack
+        .pop()
+        .ok_or_else(|| format!("missing operand for {}", operation))
+}
+
+/* The arithmetic of the DWARF stack, on address-sized values */
+struct Arith {
+    bits: u64,
+    mask: u64,
+    folding: bool,
+}
+
+impl Arith {
+    fn new(bits: u64, fold: bool) -> Self {
+        let mask = if bits < 64 { (1 << bits) - 1 } else { u64::MAX };
+        Arith {
+            bits,
+            mask,
+            folding: fold,
+        }
+    }
+
+    fn signed(&self, value: u64) -> i64 {
+        let shift = 64 - self.bits.min(64);
+        ((value << shift) as i64) >> shift
+    }
+
+    fn constant(&self, value: u64) -> Value {
+        let value = value & self.mask;
+        let signed = self.signed(value);
+        let operation = if signed < 0 {
+            Operation::Signed(signed)
+        } else {
+            Operation::Unsigned(value)
+        };
+        Value {
+            operations: vec![operation],
+            constant: Some(value),
+        }
+    }
+
+    fn binary(&self, op: DwOp, left: Value, right: Value) -> Result<Value, String> {
+        if (op == gimli::DW_OP_div || op == gimli::DW_OP_mod) && right.constant == Some(0) {
+            return Err("division by zero".to_string());
+        }
+        if !self.folding {
+            return Ok(
+                register_offset(op, &left, &right).unwrap_or_else(|| concat(op, left, right))
+            );
+        }
+        if let (Some(a), Some(b)) = (left.constant, right.constant) {
+            if let Some(value) = self.fold(op, a, b) {
+                return Ok(self.constant(value));
+            }
+        }
+        /* A register or the frame base plus or minus a constant */
+        let based = match op {
+            gimli::DW_OP_plus => right
+                .constant
+                .and_then(|b| add_offset(&left.operations, self.signed(b)))
+                .or_else(|| {
+                    left.constant
+                        .and_then(|a| add_offset(&right.operations, self.signed(a)))
+                }),
+            gimli::DW_OP_minus => right
+                .constant
+                .and_then(|b| self.signed(b).checked_neg())
+                .and_then(|offset| add_offset(&left.operations, offset)),
+            _ => None,
+        };
+        if let Some(operation) = based {
+            return Ok(Value {
+                operations: vec![operation],
+                constant: None,
+            });
+        }
+        if is_identity(op, right.constant, self.mask) {
+            return Ok(left);
+        }
+        if is_commutative(op) && is_identity(op, left.constant, self.mask) {
+            return Ok(right);
+        }
+        Ok(concat(op, left, right))
+    }
+
+    /* Shifts by the size of a value or more, and divisions, whose result gdb and the DWARF
+     * standard do not agree on, are left to the debugger */
+    fn fold(&self, op: DwOp, a: u64, b: u64) -> Option<u64> {
+        let value = match op {
+            gimli::DW_OP_plus => a.wrapping_add(b),
+            gimli::DW_OP_minus => a.wrapping_sub(b),
+            gimli::DW_OP_mul => a.wrapping_mul(b),
+            gimli::DW_OP_and => a & b,
+            gimli::DW_OP_or => a | b,
+            gimli::DW_OP_xor => a ^ b,
+            gimli::DW_OP_shl if b < self.bits => a << b,
+            gimli::DW_OP_shr if b < self.bits => a >> b,
+            gimli::DW_OP_shra if b < self.bits => (self.signed(a) >> b) as u64,
+            gimli::DW_OP_eq => (a == b) as u64,
+            gimli::DW_OP_ne => (a != b) as u64,
+            gimli::DW_OP_lt => (self.signed(a) < self.signed(b)) as u64,
+            gimli::DW_OP_le => (self.signed(a) <= self.signed(b)) as u64,
+            gimli::DW_OP_gt => (self.signed(a) > self.signed(b)) as u64,
+            gimli::DW_OP_ge => (self.signed(a) >= self.signed(b)) as u64,
+            _ => return None,
+        };
+        Some(value & self.mask)
+    }
+}
+
+fn concat(op: DwOp, mut left: Value, right: Value) -> Value {
+    left.operations.extend(right.operations);
+    left.operations.push(Operation::Simple(op));
+    left.constant = None;
+    left
+}
+
+/* A register plus or minus a decimal constant, as a single DW_OP_breg */
+fn register_offset(op: DwOp, left: &Value, right: &Value) -> Option<Value> {
+    let offset = match (op, &right.operations[..]) {
+        (gimli::DW_OP_plus, &[Operation::Signed(offset)]) => offset,
+        (gimli::DW_OP_minus, &[Operation::Signed(offset)]) => offset.checked_neg()?,
+        _ => return None,
+    };
+    match left.operations[..] {
+        [Operation::Register {
+            register,
+            offset: 0,
+        }] => Some(Value {
+            operations: vec![Operation::Register { register, offset }],
+            constant: None,
+        }),
+        _ => None,
+    }
+}
+
+/* The DW_OP_breg or DW_OP_fbreg of operations which are a single one, moved by offset */
+fn add_offset(operations: &[Operation], offset: i64) -> Option<Operation> {
+    match *operations {
+        [Operation::Register {
+            register,
+            offset: base,
+        }] => Some(Operation::Register {
+            register,
+            offset: base.checked_add(offset)?,
+        }),
+        [Operation::FrameBase { offset: base }] => Some(Operation::FrameBase {
+            offset: base.checked_add(offset)?,
+        }),
+        _ => None,
+    }
+}
+
+/* Whether an operation with the given constant operand leaves its other operand as it is */
+fn is_identity(op: DwOp, constant: Option<u64>, mask: u64) -> bool {
+    match (op, constant) {
+        (gimli::DW_OP_plus, Some(0))
+        | (gimli::DW_OP_minus, Some(0))
+        | (gimli::DW_OP_or, Some(0))
+        | (gimli::DW_OP_xor, Some(0))
+        | (gimli::DW_OP_shl, Some(0))
+        | (gimli::DW_OP_shr, Some(0))
+        | (gimli::DW_OP_shra, Some(0))
+        | (gimli::DW_OP_mul, Some(1))
+        | (gimli::DW_OP_div, Some(1)) => true,
+        (gimli::DW_OP_and, Some(constant)) => constant == mask,
+        _ => false,
+    }
+}
+
+fn is_commutative(op: DwOp) -> bool {
+    matches!(
+        op,
+        gimli::DW_OP_plus
+            | gimli::DW_OP_mul
+            | gimli::DW_OP_and
+            | gimli::DW_OP_or
+            | gimli::DW_OP_xor
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RAX: Operation = Operation::Register {
+        register: 0,
+        offset: 0,
+    };
+
+    fn op(op: DwOp) -> Operation {
+        Operation::Simple(op)
+    }
+
+    #[test]
+    fn constants_are_folded_on_address_sized_values() {
+        let operations = vec![
+            Operation::Signed(2),
+            Operation::Signed(3),
+            op(gimli::DW_OP_mul),
+            Operation::Signed(7),
+            op(gimli::DW_OP_minus),
+        ];
+        assert_eq!(
+            simplify(operations.clone(), 64, true),
+            Ok(vec![Operation::Signed(-1)])
+        );
+        assert_eq!(
+            simplify(operations, 32, true),
+            Ok(vec![Operation::Signed(-1)])
+        );
+        let operations = vec![
+            Operation::Unsigned(0xffff_ffff),
+            Operation::Signed(1),
+            op(gimli::DW_OP_plus),
+        ];
+        assert_eq!(
+            simplify(operations.clone(), 32, true),
+            Ok(vec![Operation::Unsigned(0)])
+        );
+        assert_eq!(
+            simplify(operations, 64, true),
+            Ok(vec![Operation::Unsigned(0x1_0000_0000)])
+        );
+        /* Left to the debugger */
+        let operations = vec![
+            Operation::Unsigned(1),
+            Operation::Unsigned(64),
+            op(gimli::DW_OP_shl),
+        ];
+        assert_eq!(simplify(operations.clone(), 64, true), Ok(operations));
+    }
+
+    #[test]
+    fn offsets_are_folded_into_the_register() {
+        let operations = vec![
+            RAX,
+            Operation::Signed(8),
+            op(gimli::DW_OP_plus),
+            Operation::Signed(4),
+            Operation::Signed(2),
+            op(gimli::DW_OP_mul),
+            op(gimli::DW_OP_minus),
+        ];
+        assert_eq!(
+            simplify(operations, 64, true),
+            Ok(vec![Operation::Register {
+                register: 0,
+                offset: 0
+            }])
+        );
+        let operations = vec![
+            Operation::Signed(-16),
+            Operation::FrameBase { offset: 0 },
+            op(gimli::DW_OP_plus),
+        ];
+        assert_eq!(
+            simplify(operations, 64, true),
+            Ok(vec![Operation::FrameBase { offset: -16 }])
+        );
+        /* Multiplying by 1 and adding 0 do nothing */
+        let operations = vec![
+            Operation::Signed(1),
+            RAX,
+            op(gimli::DW_OP_mul),
+            Operation::Signed(0),
+            op(gimli::DW_OP_plus),
+            Operation::Signed(4),
+            op(gimli::DW_OP_div),
+        ];
+        assert_eq!(
+            simplify(operations, 64, true),
+            Ok(vec![RAX, Operation::Unsigned(4), op(gimli::DW_OP_div)])
+        );
+    }
+
+    #[test]
+    fn operations_are_kept_unless_folding() {
+        let operations = vec![
+            Operation::Signed(1),
+            RAX,
+            op(gimli::DW_OP_mul),
+            Operation::Signed(0),
+            op(gimli::DW_OP_plus),
+        ];
+        assert_eq!(simplify(operations.clone(), 64, false), Ok(operations));
+        /* But for a register directly followed by a decimal constant and + or - */
+        let operations = vec![RAX, Operation::Signed(8), op(gimli::DW_OP_minus)];
+        assert_eq!(
+            simplify(operations, 64, false),
+            Ok(vec![Operation::Register {
+                register: 0,
+                offset: -8
+            }])
+        );
+        let operations = vec![RAX, Operation::Unsigned(8), op(gimli::DW_OP_plus)];
+        assert_eq!(simplify(operations.clone(), 64, false), Ok(operations));
+    }
+
+    #[test]
+    fn division_by_zero_is_rejected() {
+        for &fold in &[true, false] {
+            for &div in &[gimli::DW_OP_div, gimli::DW_OP_mod] {
+                let operations = vec![RAX, Operation::Signed(0), op(div)];
+                assert_eq!(
+                    simplify(operations, 64, fold),
+                    Err("division by zero".to_string())
+                );
+            }
+            /* Zero once truncated to the size of an address */
+            let operations = vec![
+                RAX,
+                Operation::Unsigned(0x1_0000_0000),
+                op(gimli::DW_OP_div),
+            ];
+            assert_eq!(
+                simplify(operations, 32, fold),
+                Err("division by zero".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn a_single_value_must_be_left() {
+        assert_eq!(
+            simplify(Vec::new(), 64, true),
+            Err("empty expression".to_string())
+        );
+        assert_eq!(
+            simplify(vec![RAX, RAX], 64, false),
+            Err("2 values left instead of one".to_string())
+        );
+        for operations in vec![
+            vec![op(gimli::DW_OP_deref)],
+            vec![op(gimli::DW_OP_neg)],
+            vec![RAX, op(gimli::DW_OP_plus)],
+        ] {
+            let err = simplify(operations.clone(), 64, true).unwrap_err();
+            assert!(
+                err.starts_with("missing operand for "),
+                "{:?}: {}",
+                operations,
+                err
+            );
+        }
+    }
+}